@@ -0,0 +1,241 @@
+use std::borrow::Cow;
+
+use glam::{Vec2, Vec3, vec2};
+use iced::Rectangle;
+use image::imageops;
+use image::{GrayImage, Luma, Rgba, RgbaImage};
+
+use crate::math::projection::SphereProjection;
+use crate::math::sample::{self, Sampling};
+use crate::selection::Selection;
+use crate::widget::sphere_canvas::SphereCanvasState;
+
+/// コピーした範囲
+///
+/// システムのクリップボードにはviewの射影で描画した平らな画像を置き、
+/// アプリ内では元の正距円筒図法のピクセルと選択範囲も持っておく。
+#[derive(Debug)]
+pub struct ClipboardContent {
+    /// viewの射影で描画した画像 (viewに写る不透明な部分だけ)
+    pub flat: RgbaImage,
+    /// flat の左上の、コピーした時の viewのピクセル座標 (左上が原点)
+    pub origin: Vec2,
+    /// コピーした時の viewの射影
+    pub projection: SphereProjection,
+    /// コピーした時の viewのピクセル数
+    pub view_size: Vec2,
+    /// 正距円筒図法のピクセル (選択範囲を囲む部分だけ)
+    pub pixels: RgbaImage,
+    /// pixels の各ピクセルを選択していた度合い
+    pub mask: GrayImage,
+    /// pixels の左上の、元の画像でのピクセル座標
+    pub x: u32,
+    pub y: u32,
+    /// 元の画像の大きさ
+    pub image_width: u32,
+    pub image_height: u32,
+}
+
+impl ClipboardContent {
+    /**
+     * 元の画像のピクセル (x, y) の色 (選択していた度合いを不透明度に掛ける)
+     * x は左右端で折り返し、コピーした範囲の外は透明
+     */
+    fn equirect_pixel(&self, x: i32, y: i32) -> Rgba<u8> {
+        let x = x.rem_euclid(self.image_width as i32) - self.x as i32;
+        let y = y - self.y as i32;
+        let mut color = sample::pixel_or_transparent(&self.pixels, x, y);
+        if color[3] > 0 {
+            let coverage = self.mask.get_pixel(x as u32, y as u32)[0] as u32;
+            color[3] = (color[3] as u32 * coverage / 255) as u8;
+        }
+        color
+    }
+
+    /**
+     * 球面上の方向 direction の色
+     */
+    fn sample_direction(&self, direction: Vec3, sampling: Sampling) -> Rgba<u8> {
+        let tex = SphereProjection::direction_to_tex(direction);
+        sampling.sample(
+            tex.x * self.image_width as f32,
+            tex.y * self.image_height as f32,
+            |x, y| self.equirect_pixel(x, y),
+        )
+    }
+
+    /**
+     * flat のピクセル座標 (連続した座標) の色を、元の正距円筒図法のピクセルから求める
+     * 拡大して貼り付けても、コピーした時の viewの解像度で粗くならない
+     */
+    pub fn sample_flat(&self, local: Vec2, sampling: Sampling) -> Rgba<u8> {
+        let view = (self.origin + local) / self.view_size;
+        match self.projection.view_to_direction(view.x, 1.0 - view.y) {
+            Some(direction) => self.sample_direction(direction, sampling),
+            None => Rgba([0, 0, 0, 0]),
+        }
+    }
+}
+
+/**
+ * 選択範囲を囲む矩形 (選択範囲が無ければ画像全体)
+ */
+fn selection_bounds(selection: &Selection, width: u32, height: u32) -> Option<Rectangle<u32>> {
+    let Some(mask) = selection.mask() else {
+        return (width > 0 && height > 0).then_some(Rectangle {
+            x: 0,
+            y: 0,
+            width,
+            height,
+        });
+    };
+
+    let mut min = (u32::MAX, u32::MAX);
+    let mut max = (0, 0);
+    for (x, y, pixel) in mask.enumerate_pixels() {
+        if pixel[0] > 0 {
+            min = (min.0.min(x), min.1.min(y));
+            max = (max.0.max(x), max.1.max(y));
+        }
+    }
+
+    (min.0 <= max.0).then(|| Rectangle {
+        x: min.0,
+        y: min.1,
+        width: max.0 - min.0 + 1,
+        height: max.1 - min.1 + 1,
+    })
+}
+
+/**
+ * アクティブなレイヤーの選択範囲 (選択範囲が無ければ全体) をコピーする
+ * 選択範囲がviewに写っていなければ None
+ */
+pub fn copy(canvas_state: &SphereCanvasState) -> Option<ClipboardContent> {
+    let layer = canvas_state.layers.active_layer()?;
+    let image = layer.image.read().ok()?;
+    let (width, height) = image.dimensions();
+    let bounds = selection_bounds(&canvas_state.selection, width, height)?;
+
+    let pixels =
+        imageops::crop_imm(&*image, bounds.x, bounds.y, bounds.width, bounds.height).to_image();
+    drop(image);
+    let mask = match canvas_state.selection.mask() {
+        Some(mask) => {
+            imageops::crop_imm(mask, bounds.x, bounds.y, bounds.width, bounds.height).to_image()
+        }
+        None => GrayImage::from_pixel(bounds.width, bounds.height, Luma([255])),
+    };
+
+    let view_w = canvas_state.viewport_bounds.width.round().max(0.0) as u32;
+    let view_h = canvas_state.viewport_bounds.height.round().max(0.0) as u32;
+    let mut content = ClipboardContent {
+        flat: RgbaImage::new(0, 0),
+        origin: Vec2::ZERO,
+        projection: canvas_state.sphere_projection(),
+        view_size: vec2(view_w as f32, view_h as f32),
+        pixels,
+        mask,
+        x: bounds.x,
+        y: bounds.y,
+        image_width: width,
+        image_height: height,
+    };
+
+    // viewに写る範囲を描画し、不透明な部分を囲む矩形に切り詰める
+    let mut flat = RgbaImage::new(view_w, view_h);
+    let mut min = (u32::MAX, u32::MAX);
+    let mut max = (0, 0);
+    for y in 0..view_h {
+        for x in 0..view_w {
+            let color =
+                content.sample_flat(vec2(x as f32 + 0.5, y as f32 + 0.5), Sampling::Bilinear);
+            if color[3] > 0 {
+                flat.put_pixel(x, y, color);
+                min = (min.0.min(x), min.1.min(y));
+                max = (max.0.max(x), max.1.max(y));
+            }
+        }
+    }
+    if min.0 > max.0 {
+        return None;
+    }
+
+    content.flat =
+        imageops::crop_imm(&flat, min.0, min.1, max.0 - min.0 + 1, max.1 - min.1 + 1).to_image();
+    content.origin = vec2(min.0 as f32, min.1 as f32);
+
+    Some(content)
+}
+
+/**
+ * アクティブなレイヤーの選択範囲 (選択範囲が無ければ全体) を透明にする
+ * 変更前の範囲を履歴に保存し、テクスチャの更新範囲に加える
+ */
+pub fn clear(canvas_state: &mut SphereCanvasState) -> bool {
+    let Some(rw_image) = canvas_state.active_image() else {
+        return false;
+    };
+    let Ok(mut image) = rw_image.write() else {
+        return false;
+    };
+    let (width, height) = image.dimensions();
+    let Some(bounds) = selection_bounds(&canvas_state.selection, width, height) else {
+        return false;
+    };
+
+    if let Ok(mut history) = canvas_state.history.write() {
+        history.record(&rw_image, &image, bounds);
+    }
+
+    for y in bounds.y..bounds.y + bounds.height {
+        for x in bounds.x..bounds.x + bounds.width {
+            let coverage = canvas_state.selection.coverage(x, y);
+            if coverage <= 0.0 {
+                continue;
+            }
+            let pixel = image.get_pixel_mut(x, y);
+            pixel[3] = (pixel[3] as f32 * (1.0 - coverage)).round() as u8;
+        }
+    }
+
+    // テクスチャの更新範囲
+    canvas_state.mark_modified_rect(
+        &rw_image,
+        Rectangle {
+            x: bounds.x as i32,
+            y: bounds.y as i32,
+            width: bounds.width as i32,
+            height: bounds.height as i32,
+        },
+    );
+
+    true
+}
+
+/**
+ * 画像をシステムのクリップボードに置く
+ */
+pub fn write_system(
+    clipboard: &mut arboard::Clipboard,
+    image: &RgbaImage,
+) -> Result<(), arboard::Error> {
+    clipboard.set_image(arboard::ImageData {
+        width: image.width() as usize,
+        height: image.height() as usize,
+        bytes: Cow::Borrowed(image.as_raw()),
+    })
+}
+
+/**
+ * システムのクリップボードにある画像
+ */
+pub fn read_system(clipboard: &mut arboard::Clipboard) -> Result<RgbaImage, arboard::Error> {
+    let data = clipboard.get_image()?;
+    RgbaImage::from_raw(
+        data.width as u32,
+        data.height as u32,
+        data.bytes.into_owned(),
+    )
+    .ok_or(arboard::Error::ConversionFailure)
+}
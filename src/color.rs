@@ -0,0 +1,117 @@
+use image::Rgba;
+
+/// 最近使った色を覚えておく数
+pub const MAX_RECENT_COLORS: usize = 16;
+
+/// 色相 (0.0 ... 360.0), 彩度, 明度 (0.0 ... 1.0)
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Hsv {
+    pub h: f32,
+    pub s: f32,
+    pub v: f32,
+}
+
+impl Hsv {
+    pub fn from_rgb(color: Rgba<u8>) -> Self {
+        let r = color[0] as f32 / 255.0;
+        let g = color[1] as f32 / 255.0;
+        let b = color[2] as f32 / 255.0;
+        let max = r.max(g).max(b);
+        let min = r.min(g).min(b);
+        let delta = max - min;
+
+        let h = if delta <= 0.0 {
+            0.0
+        } else if max == r {
+            60.0 * ((g - b) / delta).rem_euclid(6.0)
+        } else if max == g {
+            60.0 * ((b - r) / delta + 2.0)
+        } else {
+            60.0 * ((r - g) / delta + 4.0)
+        };
+        let s = if max <= 0.0 { 0.0 } else { delta / max };
+
+        Self { h, s, v: max }
+    }
+
+    pub fn to_rgb(&self, alpha: u8) -> Rgba<u8> {
+        let h = self.h.rem_euclid(360.0) / 60.0;
+        let s = self.s.clamp(0.0, 1.0);
+        let v = self.v.clamp(0.0, 1.0);
+        let c = v * s;
+        let x = c * (1.0 - (h.rem_euclid(2.0) - 1.0).abs());
+        let (r, g, b) = match h as u32 {
+            0 => (c, x, 0.0),
+            1 => (x, c, 0.0),
+            2 => (0.0, c, x),
+            3 => (0.0, x, c),
+            4 => (x, 0.0, c),
+            _ => (c, 0.0, x),
+        };
+        let m = v - c;
+        let to_u8 = |value: f32| ((value + m) * 255.0).round().clamp(0.0, 255.0) as u8;
+
+        Rgba([to_u8(r), to_u8(g), to_u8(b), alpha])
+    }
+}
+
+/**
+ * 色を #RRGGBB (不透明でなければ #RRGGBBAA) の形式にする
+ */
+pub fn to_hex(color: Rgba<u8>) -> String {
+    let [r, g, b, a] = color.0;
+    if a == 255 {
+        format!("#{:02X}{:02X}{:02X}", r, g, b)
+    } else {
+        format!("#{:02X}{:02X}{:02X}{:02X}", r, g, b, a)
+    }
+}
+
+/**
+ * #RGB, #RRGGBB, #RRGGBBAA (# は省略可) の形式の色を読み取る
+ */
+pub fn parse_hex(text: &str) -> Option<Rgba<u8>> {
+    let hex = text.trim().trim_start_matches('#');
+    if !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+    let channel = |i: usize| u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).ok();
+
+    match hex.len() {
+        3 => {
+            let mut color = [255u8; 4];
+            for (i, c) in hex.chars().enumerate() {
+                let value = c.to_digit(16)? as u8;
+                color[i] = value * 16 + value;
+            }
+            Some(Rgba(color))
+        }
+        6 => Some(Rgba([channel(0)?, channel(1)?, channel(2)?, 255])),
+        8 => Some(Rgba([channel(0)?, channel(1)?, channel(2)?, channel(3)?])),
+        _ => None,
+    }
+}
+
+/**
+ * 最近使った色の先頭に color を追加する (既にあれば先頭に移す)
+ */
+pub fn push_recent(colors: &mut Vec<Rgba<u8>>, color: Rgba<u8>) {
+    colors.retain(|c| *c != color);
+    colors.insert(0, color);
+    colors.truncate(MAX_RECENT_COLORS);
+}
+
+/**
+ * 2つの色を t (0.0 ... 1.0) の割合で混ぜる
+ */
+pub fn mix(from: Rgba<u8>, to: Rgba<u8>, t: f32) -> Rgba<u8> {
+    let t = t.clamp(0.0, 1.0);
+    Rgba(std::array::from_fn(|i| {
+        (from[i] as f32 + (to[i] as f32 - from[i] as f32) * t).round() as u8
+    }))
+}
+
+pub fn to_iced(color: Rgba<u8>) -> iced::Color {
+    let [r, g, b, a] = color.0;
+    iced::Color::from_rgba8(r, g, b, a as f32 / 255.0)
+}
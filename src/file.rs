@@ -0,0 +1,318 @@
+use std::fs::File;
+use std::io::{BufWriter, Cursor, Read};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use iced::futures::channel::{mpsc, oneshot};
+use iced::futures::{Stream, StreamExt, future, stream};
+use image::buffer::ConvertBuffer;
+use image::codecs::jpeg::JpegEncoder;
+use image::codecs::png::PngEncoder;
+use image::codecs::tiff::TiffEncoder;
+use image::codecs::webp::WebPEncoder;
+use image::error::ImageError;
+use image::{ExtendedColorType, ImageEncoder, ImageReader, Limits, RgbImage, RgbaImage};
+
+use crate::Error;
+use crate::layer::LayerStack;
+use crate::project::{self, Project};
+
+pub const DEFAULT_JPEG_QUALITY: u8 = 90;
+pub const DEFAULT_EXTENSION: &str = "png";
+
+/// 読み込める画像の最大の大きさ
+/// (最大枚数のレイヤーのタイルがテクスチャ配列の上限に収まる大きさ)
+pub const MAX_IMAGE_WIDTH: u32 = 32768;
+pub const MAX_IMAGE_HEIGHT: u32 = 16384;
+
+/// ファイルを読み出す単位 (この単位で進捗を通知する)
+const READ_CHUNK_SIZE: usize = 4 * 1024 * 1024;
+/// 読み込み全体のうちファイルの読み出しが占める進捗の割合 (残りはデコード)
+const READ_PROGRESS: f32 = 0.5;
+
+/// 保存ダイアログに表示するフィルタ (名前, 拡張子)
+pub const SAVE_FILTERS: &[(&str, &[&str])] = &[
+    ("PNG", &["png"]),
+    ("JPEG", &["jpg", "jpeg"]),
+    ("WebP", &["webp"]),
+    ("TIFF", &["tif", "tiff"]),
+    ("Pixrium Project", &[crate::project::PROJECT_EXTENSION]),
+];
+
+/// 球面に配置する画像を選ぶダイアログに表示するフィルタ (名前, 拡張子)
+pub const PLACE_IMAGE_FILTERS: &[(&str, &[&str])] = &[(
+    "Images",
+    &["png", "jpg", "jpeg", "webp", "tif", "tiff", "bmp", "gif"],
+)];
+
+/// 極の付近を切り出した画像を書き出すダイアログに表示するフィルタ (名前, 拡張子)
+pub const PATCH_FILTERS: &[(&str, &[&str])] = &[
+    ("PNG", &["png"]),
+    ("TIFF", &["tif", "tiff"]),
+    ("WebP", &["webp"]),
+];
+
+/// 読み込んだファイルの内容
+#[derive(Debug, Clone)]
+pub enum LoadedFile {
+    Image(RgbaImage),
+    Project(Project),
+}
+
+/// バックグラウンドでの読み込みの状況
+#[derive(Debug, Clone)]
+pub enum LoadEvent {
+    /// 読み込みの進捗 (0.0 ... 1.0)
+    Progress(f32),
+    Finished(Result<LoadedFile, Error>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SaveFormat {
+    Png,
+    Jpeg { quality: u8 },
+    WebP,
+    Tiff,
+}
+
+impl SaveFormat {
+    /**
+     * 拡張子から保存形式を決定する
+     */
+    pub fn from_path(path: &Path, jpeg_quality: u8) -> Result<Self, Error> {
+        let extension = path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .map(|ext| ext.to_ascii_lowercase())
+            .unwrap_or_default();
+
+        match extension.as_str() {
+            "png" => Ok(SaveFormat::Png),
+            "jpg" | "jpeg" => Ok(SaveFormat::Jpeg {
+                quality: jpeg_quality.clamp(1, 100),
+            }),
+            "webp" => Ok(SaveFormat::WebP),
+            "tif" | "tiff" => Ok(SaveFormat::Tiff),
+            _ => Err(Error::UnsupportedFormat(extension)),
+        }
+    }
+}
+
+/**
+ * 拡張子が無いパスにはデフォルトの拡張子を付与する
+ */
+pub fn with_default_extension(path: PathBuf) -> PathBuf {
+    if path.extension().is_some() {
+        path
+    } else {
+        path.with_extension(DEFAULT_EXTENSION)
+    }
+}
+
+/**
+ * 画像の大きさが読み込める範囲に収まっているか確認する
+ */
+pub fn check_dimensions(width: u32, height: u32) -> Result<(), Error> {
+    if width > MAX_IMAGE_WIDTH || height > MAX_IMAGE_HEIGHT {
+        Err(Error::TooLarge { width, height })
+    } else {
+        Ok(())
+    }
+}
+
+/**
+ * 画像またはプロジェクトファイルを別スレッドで読み込み、進捗と結果を通知する
+ */
+pub fn load_file(path: PathBuf) -> impl Stream<Item = LoadEvent> {
+    let (sender, receiver) = mpsc::unbounded();
+
+    std::thread::spawn(move || {
+        let report = |progress: f32| {
+            let _ = sender.unbounded_send(LoadEvent::Progress(progress));
+        };
+
+        let result = if project::is_project_path(&path) {
+            report(0.0);
+            project::load_project(&path).map(LoadedFile::Project)
+        } else {
+            load_image(&path, report).map(LoadedFile::Image)
+        };
+        let _ = sender.unbounded_send(LoadEvent::Finished(result));
+    });
+
+    // スレッドが panic して結果を送らずに終わっても、読み込み中のままにならないように失敗を通知する
+    let finished = Arc::new(AtomicBool::new(false));
+    let seen = finished.clone();
+    let interrupted = stream::once(async move {
+        (!finished.load(Ordering::Relaxed)).then(|| {
+            LoadEvent::Finished(Err(Error::Io("file loading was interrupted".to_string())))
+        })
+    })
+    .filter_map(future::ready);
+
+    receiver
+        .inspect(move |event| {
+            if matches!(event, LoadEvent::Finished(_)) {
+                seen.store(true, Ordering::Relaxed);
+            }
+        })
+        .chain(interrupted)
+}
+
+/**
+ * 球面に配置する平らな画像を別スレッドで読み込む
+ * 開く時と同じく、デコードする前に大きさを確認する
+ */
+pub async fn load_place_image(path: PathBuf) -> Result<RgbaImage, Error> {
+    let (sender, receiver) = oneshot::channel();
+
+    std::thread::spawn(move || {
+        let _ = sender.send(load_image(&path, |_| ()));
+    });
+
+    receiver
+        .await
+        .map_err(|_| Error::Io("image loading was interrupted".to_string()))?
+}
+
+fn load_image(path: &Path, report: impl Fn(f32)) -> Result<RgbaImage, Error> {
+    let bytes = read_bytes(path, |progress| report(progress * READ_PROGRESS))?;
+
+    let reader = ImageReader::new(Cursor::new(&bytes))
+        .with_guessed_format()
+        .map_err(|e| Error::Io(e.to_string()))?;
+    let Some(format) = reader.format() else {
+        let extension = path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .unwrap_or_default()
+            .to_string();
+        return Err(Error::UnsupportedFormat(extension));
+    };
+
+    // デコードする前に大きさを確認する
+    let (width, height) = reader.into_dimensions().map_err(image_error)?;
+    check_dimensions(width, height)?;
+
+    let mut reader = ImageReader::with_format(Cursor::new(&bytes), format);
+    reader.limits(decode_limits());
+    let image = reader.decode().map_err(image_error)?;
+    report(0.9);
+
+    Ok(image.to_rgba8())
+}
+
+/**
+ * 画像をデコードする時の制限
+ * パノラマは既定の上限 (512MiB) を超えることがあるので大きさの上限のみで制限する
+ */
+pub fn decode_limits() -> Limits {
+    let mut limits = Limits::default();
+    limits.max_image_width = Some(MAX_IMAGE_WIDTH);
+    limits.max_image_height = Some(MAX_IMAGE_HEIGHT);
+    limits.max_alloc = None;
+    limits
+}
+
+/**
+ * ファイル全体を読み出す (progress には 0.0 ... 1.0 の進捗を渡す)
+ */
+fn read_bytes(path: &Path, progress: impl Fn(f32)) -> Result<Vec<u8>, Error> {
+    let mut file = File::open(path).map_err(|e| Error::Io(e.to_string()))?;
+    let length = file.metadata().map(|m| m.len() as usize).unwrap_or(0);
+
+    let mut bytes = Vec::with_capacity(length);
+    let mut chunk = vec![0; READ_CHUNK_SIZE];
+    loop {
+        let read = file
+            .read(&mut chunk)
+            .map_err(|e| Error::Io(e.to_string()))?;
+        if read == 0 {
+            break;
+        }
+        bytes.extend_from_slice(&chunk[..read]);
+        if length > 0 {
+            progress((bytes.len() as f32 / length as f32).min(1.0));
+        }
+    }
+
+    Ok(bytes)
+}
+
+fn image_error(error: ImageError) -> Error {
+    match error {
+        ImageError::IoError(e) => Error::Io(e.to_string()),
+        ImageError::Unsupported(e) => Error::UnsupportedFormat(e.to_string()),
+        e => Error::Decode(e.to_string()),
+    }
+}
+
+/**
+ * レイヤーを合成した画像を拡張子に応じた形式でエンコードして保存する
+ */
+pub async fn save_image(
+    path: PathBuf,
+    layers: LayerStack,
+    jpeg_quality: u8,
+) -> Result<PathBuf, Error> {
+    let format = SaveFormat::from_path(&path, jpeg_quality)?;
+    let image = layers.flatten();
+
+    write_image(&path, &image, format)?;
+
+    Ok(path)
+}
+
+/**
+ * 1枚の画像を拡張子に応じた形式でエンコードして保存する
+ */
+pub async fn save_single_image(
+    path: PathBuf,
+    image: RgbaImage,
+    jpeg_quality: u8,
+) -> Result<PathBuf, Error> {
+    let format = SaveFormat::from_path(&path, jpeg_quality)?;
+
+    write_image(&path, &image, format)?;
+
+    Ok(path)
+}
+
+fn write_image(path: &Path, image: &RgbaImage, format: SaveFormat) -> Result<(), Error> {
+    let file = File::create(path).map_err(|e| Error::Io(e.to_string()))?;
+    let mut writer = BufWriter::new(file);
+
+    let result = match format {
+        SaveFormat::Png => PngEncoder::new(&mut writer).write_image(
+            image.as_raw(),
+            image.width(),
+            image.height(),
+            ExtendedColorType::Rgba8,
+        ),
+        SaveFormat::Jpeg { quality } => {
+            // JPEGはアルファを持てないのでRGBに変換する
+            let rgb: RgbImage = image.convert();
+            JpegEncoder::new_with_quality(&mut writer, quality).write_image(
+                rgb.as_raw(),
+                rgb.width(),
+                rgb.height(),
+                ExtendedColorType::Rgb8,
+            )
+        }
+        SaveFormat::WebP => WebPEncoder::new_lossless(&mut writer).write_image(
+            image.as_raw(),
+            image.width(),
+            image.height(),
+            ExtendedColorType::Rgba8,
+        ),
+        SaveFormat::Tiff => TiffEncoder::new(&mut writer).write_image(
+            image.as_raw(),
+            image.width(),
+            image.height(),
+            ExtendedColorType::Rgba8,
+        ),
+    };
+
+    result.map_err(|e| Error::Encode(e.to_string()))
+}
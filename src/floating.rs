@@ -0,0 +1,185 @@
+use std::sync::Arc;
+
+use glam::{Vec2, vec2};
+use iced::widget::image::Handle;
+use image::{Rgba, RgbaImage};
+
+use crate::clipboard::ClipboardContent;
+use crate::layer::{self, BlendMode};
+use crate::math::projection::SphereProjection;
+use crate::math::region::DirtyRegion;
+use crate::math::sample::{self, Sampling};
+use crate::math::shape::SphereShape;
+use crate::widget::sphere_canvas::SphereCanvasState;
+
+/// 貼り付けた後、確定するまで位置・大きさ・角度を変えられる画像
+///
+/// viewに平らに重ねて表示し、確定すると今のviewの射影で球面に描画する。
+#[derive(Debug, Clone)]
+pub struct FloatingImage {
+    pub image: Arc<RgbaImage>,
+    /// 表示に使う画像
+    pub handle: Handle,
+    /// 画像の中心の viewのピクセル座標 (左上が原点)
+    pub center: Vec2,
+    /// 拡大率
+    pub scale: f32,
+    /// 回転角 (ラジアン, 時計回り)
+    pub rotation: f32,
+    /// アプリ内でコピーした内容なら、元の正距円筒図法のピクセルから描画する
+    pub source: Option<Arc<ClipboardContent>>,
+}
+
+impl FloatingImage {
+    pub fn new(image: RgbaImage, center: Vec2) -> Self {
+        let handle = Handle::from_rgba(image.width(), image.height(), image.as_raw().clone());
+        Self {
+            image: Arc::new(image),
+            handle,
+            center,
+            scale: 1.0,
+            rotation: 0.0,
+            source: None,
+        }
+    }
+
+    /**
+     * コピーした内容を、コピーした時と同じ viewの位置に置く
+     */
+    pub fn from_clipboard(content: Arc<ClipboardContent>) -> Self {
+        let size = vec2(content.flat.width() as f32, content.flat.height() as f32);
+        let mut floating = Self::new(content.flat.clone(), content.origin + size * 0.5);
+        floating.source = Some(content);
+        floating
+    }
+
+    /**
+     * 拡大する前の画像のピクセル数
+     */
+    pub fn image_size(&self) -> Vec2 {
+        vec2(self.image.width() as f32, self.image.height() as f32)
+    }
+
+    /**
+     * viewに表示する大きさ (ピクセル)
+     */
+    pub fn size(&self) -> Vec2 {
+        self.image_size() * self.scale
+    }
+
+    /**
+     * viewのピクセル座標を画像のピクセル座標に変換する
+     */
+    pub fn to_local(&self, view: Vec2) -> Vec2 {
+        Vec2::from_angle(-self.rotation).rotate(view - self.center) / self.scale
+            + self.image_size() * 0.5
+    }
+
+    /**
+     * 画像の四隅の viewのピクセル座標 (左上から時計回り)
+     */
+    pub fn corners(&self) -> [Vec2; 4] {
+        let half = self.size() * 0.5;
+        let rotation = Vec2::from_angle(self.rotation);
+        [
+            vec2(-half.x, -half.y),
+            vec2(half.x, -half.y),
+            vec2(half.x, half.y),
+            vec2(-half.x, half.y),
+        ]
+        .map(|corner| self.center + rotation.rotate(corner))
+    }
+
+    /**
+     * 画像のピクセル座標 (連続した座標) の色
+     */
+    fn sample(&self, local: Vec2, sampling: Sampling) -> Rgba<u8> {
+        match &self.source {
+            Some(content) => content.sample_flat(local, sampling),
+            None => sampling.sample(local.x, local.y, |x, y| {
+                sample::pixel_or_transparent(&self.image, x, y)
+            }),
+        }
+    }
+}
+
+/**
+ * 貼り付けた画像を、今の viewの射影でアクティブなレイヤーに描画する
+ * 球面の各ピクセルから viewへ逆に射影して画像の色を補間するので、置いた視点から見て歪まない
+ * 選択範囲があれば、その内側だけに描画する
+ * 塗る前に変更範囲を履歴に保存し、テクスチャの更新範囲に加える
+ */
+pub fn draw_floating(
+    canvas_state: &mut SphereCanvasState,
+    floating: &FloatingImage,
+    sampling: Sampling,
+) -> bool {
+    let Some(rw_image) = canvas_state.active_image() else {
+        return false;
+    };
+    let Ok(mut image) = rw_image.write() else {
+        return false;
+    };
+    let tex_w = image.width();
+    let tex_h = image.height();
+    let bounds = canvas_state.viewport_bounds;
+    if tex_w == 0 || tex_h == 0 || bounds.width <= 0.0 || bounds.height <= 0.0 {
+        return false;
+    }
+    let view_size = vec2(bounds.width, bounds.height);
+    let proj = canvas_state.sphere_projection();
+
+    // 画像が覆う球面上の範囲
+    let corners = floating
+        .corners()
+        .map(|corner| vec2(corner.x / view_size.x, 1.0 - corner.y / view_size.y));
+    let shape = SphereShape::view_polygon(&proj, &corners);
+    let mut area = DirtyRegion::new(tex_w, tex_h);
+    area.add(shape.tex_bounds(tex_w, tex_h));
+
+    // 塗る前に変更範囲を履歴に保存する
+    if let Ok(mut history) = canvas_state.history.write() {
+        for rect in area.rects() {
+            history.record(&rw_image, &image, *rect);
+        }
+    }
+
+    // 各ピクセルの方向をviewに写し、貼り付けた画像の対応する位置の色を重ねる
+    let image_size = floating.image_size();
+    for rect in area.rects() {
+        for y in rect.y..rect.y + rect.height {
+            for x in rect.x..rect.x + rect.width {
+                let direction = SphereProjection::tex_to_direction(
+                    (x as f32 + 0.5) / tex_w as f32,
+                    (y as f32 + 0.5) / tex_h as f32,
+                );
+                let Some(view) = proj.direction_to_view(direction) else {
+                    continue;
+                };
+                let local =
+                    floating.to_local(vec2(view.x * view_size.x, (1.0 - view.y) * view_size.y));
+                if local.x < 0.0
+                    || local.y < 0.0
+                    || local.x > image_size.x
+                    || local.y > image_size.y
+                {
+                    continue;
+                }
+
+                let mut color = floating.sample(local, sampling);
+                let clip = canvas_state.selection.coverage(x, y);
+                color[3] = (color[3] as f32 * clip).round() as u8;
+                if color[3] == 0 {
+                    continue;
+                }
+                let pixel = *image.get_pixel(x, y);
+                image.put_pixel(x, y, layer::composite(pixel, color, 1.0, BlendMode::Normal));
+            }
+        }
+    }
+
+    // テクスチャの更新範囲
+    canvas_state.mark_modified(&rw_image, &area);
+
+    true
+}
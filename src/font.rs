@@ -13,6 +13,20 @@ pub const ICON_FONT_BYTES: &[u8] = include_bytes!("..\\resources\\fonts\\tabler-
 #[cfg(unix)]
 pub const ICON_FONT_BYTES: &[u8] = include_bytes!("../resources/fonts/tabler-icons.ttf");
 
+pub const ICON_EYE: char = '\u{ea9a}';
+pub const ICON_EYE_OFF: char = '\u{ecf0}';
+pub const ICON_LOCK: char = '\u{eae2}';
+pub const ICON_LOCK_OPEN: char = '\u{eae1}';
+pub const ICON_PLUS: char = '\u{eb0b}';
+pub const ICON_TRASH: char = '\u{eb41}';
+pub const ICON_ARROW_UP: char = '\u{ea25}';
+pub const ICON_ARROW_DOWN: char = '\u{ea16}';
+pub const ICON_X: char = '\u{eb55}';
+pub const ICON_ARROWS_EXCHANGE: char = '\u{f1f4}';
+pub const ICON_FOLDER_OPEN: char = '\u{faf7}';
+pub const ICON_DEVICE_FLOPPY: char = '\u{eb62}';
+pub const ICON_DROPLET: char = '\u{ea97}';
+
 pub const ICON_FONT_NAME: &'static str = "tabler-icons";
 pub const FONT_NAME: &'static str = "Noto Sans";
 pub const FONT_NAME_MONO: &'static str = "Noto Sans Mono";
@@ -34,3 +48,29 @@ pub fn mono_font() -> iced::Font {
         style: iced::font::Style::Normal,
     }
 }
+
+/**
+ * 文字ツールで選べるフォントのファミリー名
+ * 同梱のフォントを先頭に、システムのフォントを名前順に並べる (アイコンのフォントは除く)
+ */
+pub fn families() -> Vec<String> {
+    let mut families = vec![FONT_NAME.to_string(), FONT_NAME_MONO.to_string()];
+
+    let mut system: Vec<String> = iced::advanced::graphics::text::font_system()
+        .write()
+        .map(|mut font_system| {
+            font_system
+                .raw()
+                .db()
+                .faces()
+                .filter_map(|face| face.families.first().map(|(name, _)| name.clone()))
+                .filter(|name| name != ICON_FONT_NAME && !families.contains(name))
+                .collect()
+        })
+        .unwrap_or_default();
+    system.sort();
+    system.dedup();
+
+    families.extend(system);
+    families
+}
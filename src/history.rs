@@ -0,0 +1,435 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::{Arc, RwLock};
+
+use iced::Rectangle;
+use image::RgbaImage;
+
+/// 履歴を保存する単位となるタイルの一辺のピクセル数
+pub const TILE_SIZE: u32 = 256;
+/// 履歴全体で保持するピクセルデータの上限(バイト)
+pub const DEFAULT_MEMORY_LIMIT: usize = 512 * 1024 * 1024;
+/// 履歴全体で保持するステップ数の上限
+pub const DEFAULT_MAX_STEPS: usize = 100;
+
+const BYTES_PER_PIXEL: usize = 4;
+
+/// 画像の一部分(タイル)のピクセルデータ
+#[derive(Debug)]
+struct Tile {
+    x: u32,
+    y: u32,
+    width: u32,
+    height: u32,
+    /// プロジェクトの保存で複製を作る時にコピーしないように共有する
+    pixels: Arc<[u8]>,
+}
+
+impl Tile {
+    /**
+     * タイル座標 (tile_x, tile_y) のピクセルを画像から複製する
+     */
+    fn capture(image: &RgbaImage, tile_x: u32, tile_y: u32) -> Self {
+        let x = tile_x * TILE_SIZE;
+        let y = tile_y * TILE_SIZE;
+        let width = TILE_SIZE.min(image.width() - x);
+        let height = TILE_SIZE.min(image.height() - y);
+
+        let row_bytes = width as usize * BYTES_PER_PIXEL;
+        let mut pixels = Vec::with_capacity(row_bytes * height as usize);
+        let raw = image.as_raw();
+        for row in y..y + height {
+            let start = Self::offset(image, x, row);
+            pixels.extend_from_slice(&raw[start..start + row_bytes]);
+        }
+
+        Self {
+            x,
+            y,
+            width,
+            height,
+            pixels: pixels.into(),
+        }
+    }
+
+    /**
+     * タイルの内容と画像の内容を入れ替える
+     * (undo/redoはどちらもこの操作で実現する)
+     */
+    fn swap(&mut self, image: &mut RgbaImage) {
+        let row_bytes = self.width as usize * BYTES_PER_PIXEL;
+        // 保存中の複製が共有していれば、その時だけ複製してから書き換える
+        let pixels = Arc::make_mut(&mut self.pixels);
+        for row in 0..self.height {
+            let start = Self::offset(image, self.x, self.y + row);
+            let tile_start = row as usize * row_bytes;
+            let raw: &mut [u8] = &mut *image;
+            raw[start..start + row_bytes]
+                .swap_with_slice(&mut pixels[tile_start..tile_start + row_bytes]);
+        }
+    }
+
+    fn area(&self) -> Rectangle<u32> {
+        Rectangle {
+            x: self.x,
+            y: self.y,
+            width: self.width,
+            height: self.height,
+        }
+    }
+
+    fn bytes(&self) -> usize {
+        self.pixels.len()
+    }
+
+    fn offset(image: &RgbaImage, x: u32, y: u32) -> usize {
+        (y as usize * image.width() as usize + x as usize) * BYTES_PER_PIXEL
+    }
+}
+
+/// 1枚の画像(レイヤー)について変更されたタイルの集合
+#[derive(Debug)]
+struct ImageTiles {
+    image: Arc<RwLock<RgbaImage>>,
+    tiles: BTreeMap<(u32, u32), Tile>,
+}
+
+/// 1回の操作(押下〜移動〜解放)で変更されたタイルの集合
+#[derive(Debug, Default)]
+struct HistoryStep {
+    images: Vec<ImageTiles>,
+}
+
+/// プロジェクトファイルへの保存・復元に使う、タイルのピクセルデータの複製 (ピクセルは履歴と共有する)
+#[derive(Debug, Clone)]
+pub struct TileSnapshot {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Arc<[u8]>,
+}
+
+/// 1枚の画像について、1ステップで保存したタイルの複製
+#[derive(Debug, Clone)]
+pub struct ImageSnapshot {
+    pub image: Arc<RwLock<RgbaImage>>,
+    pub tiles: Vec<TileSnapshot>,
+}
+
+/// 1ステップ分の履歴の複製
+#[derive(Debug, Clone, Default)]
+pub struct StepSnapshot {
+    pub images: Vec<ImageSnapshot>,
+}
+
+impl HistoryStep {
+    fn swap(&mut self) {
+        for entry in self.images.iter_mut() {
+            if let Ok(mut image) = entry.image.write() {
+                for tile in entry.tiles.values_mut() {
+                    tile.swap(&mut image);
+                }
+            }
+        }
+    }
+
+    /**
+     * 変更されたタイルの範囲 (変更された画像ごと)
+     */
+    fn areas(&self) -> Vec<(Arc<RwLock<RgbaImage>>, Rectangle<u32>)> {
+        self.images
+            .iter()
+            .flat_map(|entry| {
+                entry
+                    .tiles
+                    .values()
+                    .map(|tile| (entry.image.clone(), tile.area()))
+            })
+            .collect()
+    }
+
+    fn bytes(&self) -> usize {
+        self.images
+            .iter()
+            .flat_map(|entry| entry.tiles.values().map(|tile| tile.bytes()))
+            .sum()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.images.iter().all(|entry| entry.tiles.is_empty())
+    }
+
+    fn snapshot(&self) -> StepSnapshot {
+        StepSnapshot {
+            images: self
+                .images
+                .iter()
+                .map(|entry| ImageSnapshot {
+                    image: entry.image.clone(),
+                    tiles: entry
+                        .tiles
+                        .values()
+                        .map(|tile| TileSnapshot {
+                            x: tile.x,
+                            y: tile.y,
+                            width: tile.width,
+                            height: tile.height,
+                            pixels: tile.pixels.clone(),
+                        })
+                        .collect(),
+                })
+                .collect(),
+        }
+    }
+
+    /**
+     * 複製からステップを復元する
+     * タイルの位置や大きさが画像と合わないものは捨てる
+     */
+    fn restore(snapshot: StepSnapshot) -> Self {
+        let images = snapshot
+            .images
+            .into_iter()
+            .map(|entry| {
+                let (width, height) = match entry.image.read() {
+                    Ok(image) => image.dimensions(),
+                    Err(_) => (0, 0),
+                };
+                let tiles = entry
+                    .tiles
+                    .into_iter()
+                    .filter(|tile| {
+                        tile.x % TILE_SIZE == 0
+                            && tile.y % TILE_SIZE == 0
+                            && tile.width == TILE_SIZE.min(width.saturating_sub(tile.x))
+                            && tile.height == TILE_SIZE.min(height.saturating_sub(tile.y))
+                            && tile.pixels.len()
+                                == (tile.width * tile.height) as usize * BYTES_PER_PIXEL
+                    })
+                    .map(|tile| {
+                        (
+                            (tile.x / TILE_SIZE, tile.y / TILE_SIZE),
+                            Tile {
+                                x: tile.x,
+                                y: tile.y,
+                                width: tile.width,
+                                height: tile.height,
+                                pixels: tile.pixels,
+                            },
+                        )
+                    })
+                    .collect();
+                ImageTiles {
+                    image: entry.image,
+                    tiles,
+                }
+            })
+            .collect();
+
+        Self { images }
+    }
+}
+
+/// タイル単位でスナップショットを取るundo/redo履歴
+#[derive(Debug)]
+pub struct History {
+    undo_steps: VecDeque<HistoryStep>,
+    redo_steps: Vec<HistoryStep>,
+    pending: Option<HistoryStep>,
+    memory_usage: usize,
+    memory_limit: usize,
+    max_steps: usize,
+}
+
+impl History {
+    pub fn new() -> Self {
+        Self::with_limits(DEFAULT_MEMORY_LIMIT, DEFAULT_MAX_STEPS)
+    }
+
+    pub fn with_limits(memory_limit: usize, max_steps: usize) -> Self {
+        Self {
+            undo_steps: VecDeque::new(),
+            redo_steps: Vec::new(),
+            pending: None,
+            memory_usage: 0,
+            memory_limit,
+            max_steps: max_steps.max(1),
+        }
+    }
+
+    /**
+     * 1ステップ分の記録を開始する
+     */
+    pub fn begin_step(&mut self) {
+        self.end_step();
+        self.pending = Some(HistoryStep::default());
+    }
+
+    /**
+     * 画像を変更する前に呼び出し、areaに掛かるタイルのうち
+     * 現在のステップでまだ保存していないものを保存する
+     * target は image のロック元 (undo/redoで書き戻す先) を指定する
+     */
+    pub fn record(
+        &mut self,
+        target: &Arc<RwLock<RgbaImage>>,
+        image: &RgbaImage,
+        area: Rectangle<u32>,
+    ) {
+        if area.width == 0 || area.height == 0 || image.width() == 0 || image.height() == 0 {
+            return;
+        }
+        let step = self.pending.get_or_insert_with(HistoryStep::default);
+        let index = match step
+            .images
+            .iter()
+            .position(|entry| Arc::ptr_eq(&entry.image, target))
+        {
+            Some(index) => index,
+            None => {
+                step.images.push(ImageTiles {
+                    image: target.clone(),
+                    tiles: BTreeMap::new(),
+                });
+                step.images.len() - 1
+            }
+        };
+        let tiles = &mut step.images[index].tiles;
+
+        let max_x = (area.x + area.width).min(image.width());
+        let max_y = (area.y + area.height).min(image.height());
+        if area.x >= max_x || area.y >= max_y {
+            return;
+        }
+
+        for tile_y in area.y / TILE_SIZE..=(max_y - 1) / TILE_SIZE {
+            for tile_x in area.x / TILE_SIZE..=(max_x - 1) / TILE_SIZE {
+                if !tiles.contains_key(&(tile_x, tile_y)) {
+                    let tile = Tile::capture(image, tile_x, tile_y);
+                    self.memory_usage += tile.bytes();
+                    tiles.insert((tile_x, tile_y), tile);
+                }
+            }
+        }
+    }
+
+    /**
+     * 記録中のステップを確定する
+     * 何も記録されていなければ破棄する
+     */
+    pub fn end_step(&mut self) -> bool {
+        let Some(step) = self.pending.take() else {
+            return false;
+        };
+        if step.is_empty() {
+            return false;
+        }
+
+        // 新しい操作をしたらredoはできなくなる
+        for step in self.redo_steps.drain(..) {
+            self.memory_usage -= step.bytes();
+        }
+        self.undo_steps.push_back(step);
+        self.trim();
+
+        true
+    }
+
+    /**
+     * 直前のステップを取り消し、変更された範囲を返す
+     */
+    pub fn undo(&mut self) -> Option<Vec<(Arc<RwLock<RgbaImage>>, Rectangle<u32>)>> {
+        self.end_step();
+
+        let mut step = self.undo_steps.pop_back()?;
+        step.swap();
+        let areas = step.areas();
+        self.redo_steps.push(step);
+
+        Some(areas)
+    }
+
+    /**
+     * 取り消したステップをやり直し、変更された範囲を返す
+     */
+    pub fn redo(&mut self) -> Option<Vec<(Arc<RwLock<RgbaImage>>, Rectangle<u32>)>> {
+        self.end_step();
+
+        let mut step = self.redo_steps.pop()?;
+        step.swap();
+        let areas = step.areas();
+        self.undo_steps.push_back(step);
+
+        Some(areas)
+    }
+
+    pub fn can_undo(&self) -> bool {
+        !self.undo_steps.is_empty()
+    }
+
+    pub fn can_redo(&self) -> bool {
+        !self.redo_steps.is_empty()
+    }
+
+    pub fn memory_usage(&self) -> usize {
+        self.memory_usage
+    }
+
+    /**
+     * undo/redoできるステップを古い順に複製する (記録中のステップは含まない)
+     */
+    pub fn snapshot(&self) -> (Vec<StepSnapshot>, Vec<StepSnapshot>) {
+        (
+            self.undo_steps.iter().map(HistoryStep::snapshot).collect(),
+            self.redo_steps.iter().map(HistoryStep::snapshot).collect(),
+        )
+    }
+
+    /**
+     * snapshot() で複製したステップで履歴を置き換える
+     */
+    pub fn restore(&mut self, undo_steps: Vec<StepSnapshot>, redo_steps: Vec<StepSnapshot>) {
+        self.clear();
+
+        for step in undo_steps.into_iter().map(HistoryStep::restore) {
+            if !step.is_empty() {
+                self.memory_usage += step.bytes();
+                self.undo_steps.push_back(step);
+            }
+        }
+        for step in redo_steps.into_iter().map(HistoryStep::restore) {
+            if !step.is_empty() {
+                self.memory_usage += step.bytes();
+                self.redo_steps.push(step);
+            }
+        }
+        self.trim();
+    }
+
+    pub fn clear(&mut self) {
+        self.undo_steps.clear();
+        self.redo_steps.clear();
+        self.pending = None;
+        self.memory_usage = 0;
+    }
+
+    /**
+     * 上限を超えた分だけ古いステップから破棄する
+     * 直前のステップは上限を超えていても残す
+     */
+    fn trim(&mut self) {
+        while self.undo_steps.len() > 1
+            && (self.undo_steps.len() > self.max_steps || self.memory_usage > self.memory_limit)
+        {
+            if let Some(step) = self.undo_steps.pop_front() {
+                self.memory_usage -= step.bytes();
+            }
+        }
+    }
+}
+
+impl Default for History {
+    fn default() -> Self {
+        Self::new()
+    }
+}
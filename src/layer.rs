@@ -0,0 +1,281 @@
+use core::fmt;
+use std::sync::{Arc, RwLock};
+
+use image::{Rgba, RgbaImage};
+use serde::{Deserialize, Serialize};
+
+/// 重ねられるレイヤーの上限 (sphere.wgsl の MAX_LAYERS と一致させること)
+pub const MAX_LAYERS: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum BlendMode {
+    #[default]
+    Normal,
+    Multiply,
+    Screen,
+    Overlay,
+    Add,
+}
+
+impl BlendMode {
+    pub const ALL: [BlendMode; 5] = [
+        BlendMode::Normal,
+        BlendMode::Multiply,
+        BlendMode::Screen,
+        BlendMode::Overlay,
+        BlendMode::Add,
+    ];
+
+    /**
+     * シェーダーに渡す合成方法の番号 (sphere.wgsl と一致させること)
+     */
+    pub fn shader_index(&self) -> u32 {
+        match self {
+            BlendMode::Normal => 0,
+            BlendMode::Multiply => 1,
+            BlendMode::Screen => 2,
+            BlendMode::Overlay => 3,
+            BlendMode::Add => 4,
+        }
+    }
+
+    /**
+     * 下の色 backdrop と上の色 source (0.0 ... 1.0) を合成する
+     */
+    pub fn blend(&self, backdrop: f32, source: f32) -> f32 {
+        match self {
+            BlendMode::Normal => source,
+            BlendMode::Multiply => backdrop * source,
+            BlendMode::Screen => backdrop + source - backdrop * source,
+            BlendMode::Overlay => {
+                if backdrop <= 0.5 {
+                    2.0 * backdrop * source
+                } else {
+                    1.0 - 2.0 * (1.0 - backdrop) * (1.0 - source)
+                }
+            }
+            BlendMode::Add => (backdrop + source).min(1.0),
+        }
+    }
+}
+
+impl fmt::Display for BlendMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            BlendMode::Normal => "Normal",
+            BlendMode::Multiply => "Multiply",
+            BlendMode::Screen => "Screen",
+            BlendMode::Overlay => "Overlay",
+            BlendMode::Add => "Add",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// equirectangular画像1枚分のレイヤー
+#[derive(Debug, Clone)]
+pub struct Layer {
+    pub name: String,
+    pub image: Arc<RwLock<RgbaImage>>,
+    pub visible: bool,
+    pub locked: bool,
+    pub opacity: f32,
+    pub blend_mode: BlendMode,
+}
+
+impl Layer {
+    pub fn new(name: impl Into<String>, image: RgbaImage) -> Self {
+        Self {
+            name: name.into(),
+            image: Arc::new(RwLock::new(image)),
+            visible: true,
+            locked: false,
+            opacity: 1.0,
+            blend_mode: BlendMode::Normal,
+        }
+    }
+}
+
+/// 下から順に重ねるレイヤーの一覧
+#[derive(Debug, Clone, Default)]
+pub struct LayerStack {
+    pub layers: Vec<Layer>,
+    pub active: usize,
+    pub width: u32,
+    pub height: u32,
+    // 新しいレイヤーの名前に付ける番号
+    next_number: usize,
+}
+
+impl LayerStack {
+    pub fn new(image: RgbaImage) -> Self {
+        let width = image.width();
+        let height = image.height();
+
+        Self {
+            layers: vec![Layer::new("Background", image)],
+            active: 0,
+            width,
+            height,
+            next_number: 1,
+        }
+    }
+
+    /**
+     * 読み込んだレイヤーから作る (layers は空でないこと)
+     */
+    pub fn from_layers(layers: Vec<Layer>, active: usize, width: u32, height: u32) -> Self {
+        // 削除されたレイヤーがあっても名前が重ならないように、使われている最大の番号の次から付ける
+        let next_number = layers
+            .iter()
+            .filter_map(|layer| layer.name.strip_prefix("Layer "))
+            .filter_map(|number| number.parse::<usize>().ok())
+            .max()
+            .map_or(1, |number| number + 1);
+
+        Self {
+            active: active.min(layers.len().saturating_sub(1)),
+            next_number,
+            layers,
+            width,
+            height,
+        }
+    }
+
+    pub fn active_layer(&self) -> Option<&Layer> {
+        self.layers.get(self.active)
+    }
+
+    pub fn active_layer_mut(&mut self) -> Option<&mut Layer> {
+        self.layers.get_mut(self.active)
+    }
+
+    /**
+     * 描画対象となるアクティブなレイヤーの画像
+     * 非表示またはロックされている場合は描画できないので None
+     */
+    pub fn active_image(&self) -> Option<Arc<RwLock<RgbaImage>>> {
+        self.active_layer()
+            .filter(|layer| layer.visible && !layer.locked)
+            .map(|layer| layer.image.clone())
+    }
+
+    pub fn can_add(&self) -> bool {
+        self.layers.len() < MAX_LAYERS
+    }
+
+    /**
+     * アクティブなレイヤーの上に透明なレイヤーを追加する
+     */
+    pub fn add_layer(&mut self) -> bool {
+        if !self.can_add() {
+            return false;
+        }
+
+        let layer = Layer::new(
+            format!("Layer {}", self.next_number),
+            RgbaImage::new(self.width, self.height),
+        );
+        self.next_number += 1;
+
+        let index = if self.layers.is_empty() {
+            0
+        } else {
+            self.active + 1
+        };
+        self.layers.insert(index, layer);
+        self.active = index;
+        true
+    }
+
+    /**
+     * アクティブなレイヤーを削除する (最後の1枚は削除しない)
+     */
+    pub fn remove_active(&mut self) -> bool {
+        if self.layers.len() <= 1 {
+            return false;
+        }
+
+        self.layers.remove(self.active);
+        self.active = self.active.min(self.layers.len() - 1);
+        true
+    }
+
+    /**
+     * アクティブなレイヤーを上(offset > 0)または下へ移動する
+     */
+    pub fn move_active(&mut self, offset: i32) -> bool {
+        let target = self.active as i32 + offset;
+        if target < 0 || target as usize >= self.layers.len() {
+            return false;
+        }
+
+        self.layers.swap(self.active, target as usize);
+        self.active = target as usize;
+        true
+    }
+
+    /**
+     * 全レイヤーを合成した1枚の画像を作る
+     */
+    pub fn flatten(&self) -> RgbaImage {
+        let mut output = RgbaImage::new(self.width, self.height);
+
+        for layer in self.layers.iter().filter(|layer| layer.visible) {
+            let Ok(image) = layer.image.read() else {
+                continue;
+            };
+            for (dst, src) in output.pixels_mut().zip(image.pixels()) {
+                *dst = composite(*dst, *src, layer.opacity, layer.blend_mode);
+            }
+        }
+
+        output
+    }
+
+    /**
+     * 全レイヤーを合成した (x, y) のピクセルの色
+     */
+    pub fn composite_pixel(&self, x: u32, y: u32) -> Rgba<u8> {
+        let mut output = Rgba([0, 0, 0, 0]);
+
+        for layer in self.layers.iter().filter(|layer| layer.visible) {
+            if let Ok(image) = layer.image.read() {
+                output = composite(
+                    output,
+                    *image.get_pixel(x, y),
+                    layer.opacity,
+                    layer.blend_mode,
+                );
+            }
+        }
+
+        output
+    }
+}
+
+/**
+ * 下の色 backdrop に上の色 source を不透明度 opacity で合成する
+ * (sphere.wgsl の composite と同じ計算)
+ */
+pub fn composite(backdrop: Rgba<u8>, source: Rgba<u8>, opacity: f32, mode: BlendMode) -> Rgba<u8> {
+    let alpha_s = source[3] as f32 / 255.0 * opacity;
+    if alpha_s <= 0.0 {
+        return backdrop;
+    }
+    let alpha_b = backdrop[3] as f32 / 255.0;
+    let alpha_o = alpha_s + alpha_b * (1.0 - alpha_s);
+
+    let mut output = [0u8; 4];
+    for i in 0..3 {
+        let cs = source[i] as f32 / 255.0;
+        let cb = backdrop[i] as f32 / 255.0;
+        // 下が透明な部分では上の色をそのまま使う
+        let mixed = (1.0 - alpha_b) * cs + alpha_b * mode.blend(cb, cs);
+        let co = (alpha_s * mixed + alpha_b * (1.0 - alpha_s) * cb) / alpha_o;
+        output[i] = (co.clamp(0.0, 1.0) * 255.0).round() as u8;
+    }
+    output[3] = (alpha_o.clamp(0.0, 1.0) * 255.0).round() as u8;
+
+    Rgba(output)
+}
@@ -1,25 +1,61 @@
+mod clipboard;
+mod color;
+mod file;
+mod floating;
 mod font;
+mod history;
+mod layer;
 mod math;
+mod palette;
+mod patch;
+mod project;
+mod selection;
 mod tool;
 mod widget;
 
+use core::fmt;
 use glam::{Vec3, vec2};
 use iced::border::Radius;
 use iced::event::Status;
-use iced::widget::{Space, button, center, column, container, row, shader, text};
-use iced::{Alignment, Background, Border, Color, Font, Length, Theme, alignment, window};
-use iced::{Element, Task};
+use iced::keyboard;
+use iced::widget::{
+    Column, Row, Space, button, canvas, center, checkbox, column, container, pick_list,
+    progress_bar, row, scrollable, shader, slider, stack, text, text_input,
+};
+use iced::{Alignment, Background, Border, Color, Font, Length, Theme, alignment, mouse, window};
+use iced::{Element, Subscription, Task};
 use iced_aw::menu::{Item, Menu};
 use iced_aw::{menu_bar, menu_items};
 use iced_aw::{quad, widgets::InnerBounds};
-use image::{self, ImageReader};
+use image;
+use image::Rgba;
 use rfd;
-use std::f32::consts::PI;
+use rfd::{MessageButtons, MessageDialogResult, MessageLevel};
+use std::f32::consts::{PI, TAU};
 use std::path::PathBuf;
 use std::sync::{Arc, RwLock};
+use widget::sphere_canvas::overlay::sphere_overlay;
 use widget::sphere_canvas::sphere_canvas;
 
+use crate::clipboard::ClipboardContent;
+use crate::color::Hsv;
+use crate::floating::FloatingImage;
+use crate::layer::{BlendMode, LayerStack};
+use crate::math::projection::ViewProjection;
+use crate::math::sample::Sampling;
+use crate::math::symmetry::Symmetry;
+use crate::palette::{Palette, Swatch};
+use crate::patch::{Pole, PolePatch};
+use crate::selection::SelectionMode;
 use crate::tool::ToolHandle;
+use crate::tool::clone::{CloneKind, CloneTool};
+use crate::tool::eyedropper::{EyedropperTool, SampleSize};
+use crate::tool::fill::FillTool;
+use crate::tool::gradient::{Gradient, GradientMode, GradientPreset, GradientStop, GradientTool};
+use crate::tool::retouch::{RetouchKind, RetouchTool};
+use crate::tool::select::{SelectKind, SelectTool};
+use crate::tool::shape::{ShapeKind, ShapeTool};
+use crate::tool::text::{TextOrientation, TextTool};
 use crate::widget::sphere_canvas::{SphereCanvasMessage, SphereCanvasState};
 
 #[cfg(windows)]
@@ -27,8 +63,13 @@ const SAMPLE_IMAGE_BYTES: &[u8] = include_bytes!("..\\resources\\images\\sample.
 #[cfg(unix)]
 const SAMPLE_IMAGE_BYTES: &[u8] = include_bytes!("../resources/images/sample.png");
 
+/// グラデーションパネルの見本を分割する数
+const GRADIENT_PREVIEW_STEPS: usize = 32;
+
 fn main() -> iced::Result {
-    iced::application("Pixrium", App::update, App::view)
+    iced::application(App::title, App::update, App::view)
+        .subscription(App::subscription)
+        .exit_on_close_request(false)
         .font(font::UI_FONT_BYTES)
         .font(font::MONO_FONT_BYTES)
         .font(font::ICON_FONT_BYTES)
@@ -40,21 +81,247 @@ fn main() -> iced::Result {
 enum Message {
     OpenFile,
     FileOpened(Result<PathBuf, Error>),
+    FileLoading(file::LoadEvent),
+    DismissError,
+    Save,
+    SaveAs,
+    SaveFileSelected(Result<PathBuf, Error>),
+    FileSaved(Result<PathBuf, Error>),
+    SetJpegQuality(u8),
+    PlaceImage,
+    PlaceImageLoaded(Result<image::RgbaImage, Error>),
+    SetSampling(Sampling),
+    ModifiersChanged(keyboard::Modifiers),
+
+    Undo,
+    Redo,
+    Selection(SelectionMessage),
+    Symmetry(SymmetryMessage),
+    Clipboard(ClipboardMessage),
+    Patch(PatchMessage),
 
     SphereCanvasMessage(widget::sphere_canvas::SphereCanvasMessage),
 
     ChangeTool(ToolHandle),
+    ChangeProjection(ViewProjection),
+    Layer(LayerMessage),
+    Color(ColorMessage),
+    Brush(BrushMessage),
+    Fill(FillMessage),
+    Shape(ShapeMessage),
+    Select(SelectMessage),
+    Clone(CloneMessage),
+    Retouch(RetouchMessage),
+    Eyedropper(EyedropperMessage),
+    Gradient(GradientMessage),
+    Text(TextMessage),
 
     Exit,
+    ExitConfirmed(MessageDialogResult),
+}
+
+#[derive(Debug, Clone)]
+enum SelectionMessage {
+    All,
+    Deselect,
+    Invert,
+}
+
+#[derive(Debug, Clone)]
+enum SymmetryMessage {
+    ToggleMeridian,
+    ToggleEquator,
+    /// 縦軸の周りに繰り返す数 (1 で繰り返さない)
+    SetRadial(u32),
+    /// 反転・繰り返しの基準の子午線を viewの中心の経度に合わせる
+    AlignToView,
+}
+
+#[derive(Debug, Clone)]
+enum ClipboardMessage {
+    Cut,
+    Copy,
+    Paste,
+    /// 貼り付けた画像を確定する
+    Commit,
+    /// 貼り付けた画像を取り消す
+    Cancel,
+}
+
+#[derive(Debug, Clone)]
+enum PatchMessage {
+    /// 切り出す範囲を極からの角度 (度) で設定する
+    SetAngle(u32),
+    /// 極を真上から見る view に切り替えて、そのまま編集できるようにする
+    View(Pole),
+    Export(Pole),
+    Exported(Result<PathBuf, Error>),
+    Import(Pole),
+    Imported(Pole, Result<image::RgbaImage, Error>),
+    PlaceLogo(Pole),
+    LogoLoaded(Pole, Result<image::RgbaImage, Error>),
+    BlurFill(Pole),
+}
+
+#[derive(Debug, Clone)]
+enum LayerMessage {
+    Select(usize),
+    ToggleVisible(usize),
+    ToggleLocked(usize),
+    SetOpacity(f32),
+    SetBlendMode(BlendMode),
+    Add,
+    Remove,
+    MoveUp,
+    MoveDown,
+}
+
+#[derive(Debug, Clone)]
+enum ColorMessage {
+    SelectTarget(ColorTarget),
+    Swap,
+    SetHue(f32),
+    SetSaturation(f32),
+    SetValue(f32),
+    /// (チャンネルの番号 R, G, B, A, 値)
+    SetChannel(usize, f32),
+    HexChanged(String),
+    Pick(Rgba<u8>),
+    SelectSwatch(usize),
+    AddSwatch,
+    RemoveSwatch,
+    LoadPalette,
+    PaletteLoaded(Result<Palette, Error>),
+    SavePalette,
+    PaletteSaved(Result<PathBuf, Error>),
+}
+
+#[derive(Debug, Clone)]
+enum BrushMessage {
+    SetWidth(f32),
+    SetHardness(f32),
+    SetSpacing(f32),
+    SetOpacity(f32),
+    SetFlow(f32),
+    ToggleAntialias(bool),
+}
+
+#[derive(Debug, Clone)]
+enum FillMessage {
+    SetTolerance(f32),
+    ToggleContiguous(bool),
+    ToggleAntialias(bool),
+    SetOpacity(f32),
+}
+
+#[derive(Debug, Clone)]
+enum ShapeMessage {
+    ToggleStroke(bool),
+    ToggleFill(bool),
+}
+
+#[derive(Debug, Clone)]
+enum SelectMessage {
+    SetMode(SelectionMode),
+    SetTolerance(f32),
+    ToggleAntialias(bool),
+    ToggleContiguous(bool),
+}
+
+#[derive(Debug, Clone)]
+enum CloneMessage {
+    ToggleAligned(bool),
+}
+
+#[derive(Debug, Clone)]
+enum RetouchMessage {
+    SetStrength(f32),
+    SetKernel(f32),
+}
+
+#[derive(Debug, Clone)]
+enum EyedropperMessage {
+    SetSampleSize(SampleSize),
+    ToggleSampleMerged(bool),
+}
+
+#[derive(Debug, Clone)]
+enum GradientMessage {
+    SetMode(GradientMode),
+    SetOpacity(f32),
+    ToggleReverse(bool),
+    SelectStop(usize),
+    SetStopPosition(f32),
+    /// 選択している区切りの色を描画色にする
+    SetStopColor,
+    AddStop,
+    RemoveStop,
+    LoadPreset(String),
+    SavePreset,
+}
+
+#[derive(Debug, Clone)]
+enum TextMessage {
+    SetText(String),
+    SetFamily(String),
+    SetSize(f32),
+    SetOrientation(TextOrientation),
+    /// 置いた文字をレイヤーに確定する
+    Commit,
+    /// 置いた文字を取り消す
+    Cancel,
+}
+
+/// カラーパネルで編集する色
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ColorTarget {
+    Foreground,
+    Background,
 }
 
 #[derive(Debug, Clone)]
 enum Error {
     DialogClosed,
+    Io(String),
+    Encode(String),
+    Decode(String),
+    UnsupportedFormat(String),
+    TooLarge { width: u32, height: u32 },
+    Clipboard(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::DialogClosed => write!(f, "The dialog was closed."),
+            Error::Io(e) => write!(f, "Could not access the file: {}", e),
+            Error::Encode(e) => write!(f, "Could not encode the image: {}", e),
+            Error::Decode(e) => write!(f, "Could not decode the file: {}", e),
+            Error::UnsupportedFormat(format) => write!(f, "Unsupported format: {}", format),
+            Error::TooLarge { width, height } => write!(
+                f,
+                "The image is too large: {}x{} (up to {}x{} is supported)",
+                width,
+                height,
+                file::MAX_IMAGE_WIDTH,
+                file::MAX_IMAGE_HEIGHT
+            ),
+            Error::Clipboard(e) => write!(f, "Could not access the clipboard: {}", e),
+        }
+    }
 }
 
 struct App {
     image_path: PathBuf,
+    // 最後に保存してから編集されたか
+    is_dirty: bool,
+    // 保存完了後にアプリを終了するか
+    exit_after_save: bool,
+    jpeg_quality: u8,
+    // 読み込み中のファイルと進捗
+    loading: Option<(PathBuf, f32)>,
+    // バナーに表示するエラー
+    error: Option<Error>,
 
     canvas_state: Arc<RwLock<SphereCanvasState>>,
 
@@ -62,6 +329,76 @@ struct App {
     pan_tool: ToolHandle,
     zoom_tool: ToolHandle,
     pen_tool: ToolHandle,
+    eraser_tool: ToolHandle,
+    // 塗りつぶしの設定をパネルで編集するので、具体的な型でも持っておく
+    fill: Arc<FillTool>,
+    fill_tool: ToolHandle,
+    // スポイトの設定をパネルで編集するので、具体的な型でも持っておく
+    eyedropper: Arc<EyedropperTool>,
+    eyedropper_tool: ToolHandle,
+    line_tool: ToolHandle,
+    // 図形の設定をパネルで編集するので、具体的な型でも持っておく
+    circle: Arc<ShapeTool>,
+    circle_tool: ToolHandle,
+    rectangle: Arc<ShapeTool>,
+    rectangle_tool: ToolHandle,
+    polygon: Arc<ShapeTool>,
+    polygon_tool: ToolHandle,
+    // 選択の設定をパネルで編集するので、具体的な型でも持っておく
+    marquee: Arc<SelectTool>,
+    marquee_tool: ToolHandle,
+    lasso: Arc<SelectTool>,
+    lasso_tool: ToolHandle,
+    magic_wand: Arc<SelectTool>,
+    magic_wand_tool: ToolHandle,
+    // コピースタンプの設定をパネルで編集するので、具体的な型でも持っておく
+    clone: Arc<CloneTool>,
+    clone_tool: ToolHandle,
+    healing: Arc<CloneTool>,
+    healing_tool: ToolHandle,
+    // 修正ツールの設定をパネルで編集するので、具体的な型でも持っておく
+    smudge: Arc<RetouchTool>,
+    smudge_tool: ToolHandle,
+    blur: Arc<RetouchTool>,
+    blur_tool: ToolHandle,
+    sharpen: Arc<RetouchTool>,
+    sharpen_tool: ToolHandle,
+    dodge: Arc<RetouchTool>,
+    dodge_tool: ToolHandle,
+    burn: Arc<RetouchTool>,
+    burn_tool: ToolHandle,
+    // グラデーションの設定をパネルで編集するので、具体的な型でも持っておく
+    gradient: Arc<GradientTool>,
+    gradient_tool: ToolHandle,
+    // 文字の設定をパネルで編集し、確定・取り消しも行うので、具体的な型でも持っておく
+    text: Arc<TextTool>,
+    text_tool: ToolHandle,
+    // 文字ツールで選べるフォントのファミリー名
+    font_families: Vec<String>,
+    // 貼り付けた画像を確定するまで使うツール
+    transform_tool: ToolHandle,
+    // 押下から解放までの間だけ current_tool の代わりに使うツール
+    temporary_tool: Option<ToolHandle>,
+    // 貼り付けた画像を確定したら戻すツール
+    tool_before_paste: Option<ToolHandle>,
+    // 貼り付けた画像を球面に描画する際の補間方法
+    sampling: Sampling,
+    // 極の付近を切り出す範囲 (極からの角度, 度)
+    patch_angle: u32,
+
+    // システムのクリップボード (Linuxでは置いた内容を保つために開いたままにする)
+    system_clipboard: Option<arboard::Clipboard>,
+    // 最後にコピーした内容
+    clipboard: Option<Arc<ClipboardContent>>,
+
+    color_target: ColorTarget,
+    // 彩度や明度が0になっても色相を保てるように、パネルで編集したHSVを覚えておく
+    color_hsv: Hsv,
+    hex_input: String,
+    recent_colors: Vec<Rgba<u8>>,
+    palette: Palette,
+    selected_swatch: Option<usize>,
+    selected_stop: usize,
 }
 
 impl App {
@@ -71,9 +408,31 @@ impl App {
         let pen_tool = tool::ToolHandle {
             handle: Arc::new(tool::pen::PenTool::new()),
         };
+        let gradient = Arc::new(GradientTool::new());
+        let text = Arc::new(TextTool::new());
+        let fill = Arc::new(FillTool::new());
+        let eyedropper = Arc::new(EyedropperTool::new());
+        let circle = Arc::new(ShapeTool::new(ShapeKind::Circle));
+        let rectangle = Arc::new(ShapeTool::new(ShapeKind::Rectangle));
+        let polygon = Arc::new(ShapeTool::new(ShapeKind::Polygon));
+        let marquee = Arc::new(SelectTool::new(SelectKind::Marquee));
+        let lasso = Arc::new(SelectTool::new(SelectKind::Lasso));
+        let magic_wand = Arc::new(SelectTool::new(SelectKind::MagicWand));
+        let clone = Arc::new(CloneTool::new(CloneKind::Stamp));
+        let healing = Arc::new(CloneTool::new(CloneKind::Healing));
+        let smudge = Arc::new(RetouchTool::new(RetouchKind::Smudge));
+        let blur = Arc::new(RetouchTool::new(RetouchKind::Blur));
+        let sharpen = Arc::new(RetouchTool::new(RetouchKind::Sharpen));
+        let dodge = Arc::new(RetouchTool::new(RetouchKind::Dodge));
+        let burn = Arc::new(RetouchTool::new(RetouchKind::Burn));
 
         Self {
             image_path: PathBuf::new(),
+            is_dirty: false,
+            exit_after_save: false,
+            jpeg_quality: file::DEFAULT_JPEG_QUALITY,
+            loading: None,
+            error: None,
             canvas_state: Arc::new(RwLock::new(SphereCanvasState::new(img))),
             current_tool: pen_tool.clone(),
             pan_tool: tool::ToolHandle {
@@ -83,26 +442,333 @@ impl App {
                 handle: Arc::new(tool::zoom::ZoomTool::new()),
             },
             pen_tool: pen_tool.clone(),
+            eraser_tool: tool::ToolHandle {
+                handle: Arc::new(tool::eraser::EraserTool::new()),
+            },
+            fill_tool: tool::ToolHandle {
+                handle: fill.clone(),
+            },
+            fill,
+            eyedropper_tool: tool::ToolHandle {
+                handle: eyedropper.clone(),
+            },
+            eyedropper,
+            line_tool: tool::ToolHandle {
+                handle: Arc::new(tool::line::LineTool::new()),
+            },
+            circle_tool: tool::ToolHandle {
+                handle: circle.clone(),
+            },
+            circle,
+            rectangle_tool: tool::ToolHandle {
+                handle: rectangle.clone(),
+            },
+            rectangle,
+            polygon_tool: tool::ToolHandle {
+                handle: polygon.clone(),
+            },
+            polygon,
+            marquee_tool: tool::ToolHandle {
+                handle: marquee.clone(),
+            },
+            marquee,
+            lasso_tool: tool::ToolHandle {
+                handle: lasso.clone(),
+            },
+            lasso,
+            magic_wand_tool: tool::ToolHandle {
+                handle: magic_wand.clone(),
+            },
+            magic_wand,
+            clone_tool: tool::ToolHandle {
+                handle: clone.clone(),
+            },
+            clone,
+            healing_tool: tool::ToolHandle {
+                handle: healing.clone(),
+            },
+            healing,
+            smudge_tool: tool::ToolHandle {
+                handle: smudge.clone(),
+            },
+            smudge,
+            blur_tool: tool::ToolHandle {
+                handle: blur.clone(),
+            },
+            blur,
+            sharpen_tool: tool::ToolHandle {
+                handle: sharpen.clone(),
+            },
+            sharpen,
+            dodge_tool: tool::ToolHandle {
+                handle: dodge.clone(),
+            },
+            dodge,
+            burn_tool: tool::ToolHandle {
+                handle: burn.clone(),
+            },
+            burn,
+            gradient_tool: tool::ToolHandle {
+                handle: gradient.clone(),
+            },
+            gradient,
+            text_tool: tool::ToolHandle {
+                handle: text.clone(),
+            },
+            text,
+            font_families: font::families(),
+            transform_tool: tool::ToolHandle {
+                handle: Arc::new(tool::transform::TransformTool::new()),
+            },
+            temporary_tool: None,
+            tool_before_paste: None,
+            sampling: Sampling::default(),
+            patch_angle: 30,
+            system_clipboard: None,
+            clipboard: None,
+            color_target: ColorTarget::Foreground,
+            color_hsv: Hsv::default(),
+            hex_input: String::new(),
+            recent_colors: Vec::new(),
+            palette: Palette::default(),
+            selected_swatch: None,
+            selected_stop: 0,
+        }
+    }
+
+    fn title(&self) -> String {
+        let file_name = self
+            .image_path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .unwrap_or("Untitled");
+        let dirty_mark = if self.is_dirty { "*" } else { "" };
+
+        format!("{}{} - Pixrium", dirty_mark, file_name)
+    }
+
+    fn subscription(&self) -> Subscription<Message> {
+        Subscription::batch([
+            window::close_requests().map(|_| Message::Exit),
+            keyboard::on_key_press(Self::hotkey),
+            iced::event::listen_with(|event, _status, _window| match event {
+                iced::Event::Keyboard(keyboard::Event::ModifiersChanged(modifiers)) => {
+                    Some(Message::ModifiersChanged(modifiers))
+                }
+                _ => None,
+            }),
+        ])
+    }
+
+    fn hotkey(key: keyboard::Key, modifiers: keyboard::Modifiers) -> Option<Message> {
+        match (key.as_ref(), modifiers.command(), modifiers.shift()) {
+            (keyboard::Key::Character("z" | "Z"), true, false) => Some(Message::Undo),
+            (keyboard::Key::Character("z" | "Z"), true, true) => Some(Message::Redo),
+            (keyboard::Key::Character("y" | "Y"), true, false) => Some(Message::Redo),
+            (keyboard::Key::Character("x" | "X"), true, false) => {
+                Some(Message::Clipboard(ClipboardMessage::Cut))
+            }
+            (keyboard::Key::Character("c" | "C"), true, false) => {
+                Some(Message::Clipboard(ClipboardMessage::Copy))
+            }
+            (keyboard::Key::Character("v" | "V"), true, false) => {
+                Some(Message::Clipboard(ClipboardMessage::Paste))
+            }
+            (keyboard::Key::Named(keyboard::key::Named::Enter), false, false) => {
+                Some(Message::Clipboard(ClipboardMessage::Commit))
+            }
+            (keyboard::Key::Named(keyboard::key::Named::Escape), false, false) => {
+                Some(Message::Clipboard(ClipboardMessage::Cancel))
+            }
+            (keyboard::Key::Character("a" | "A"), true, false) => {
+                Some(Message::Selection(SelectionMessage::All))
+            }
+            (keyboard::Key::Character("d" | "D"), true, false) => {
+                Some(Message::Selection(SelectionMessage::Deselect))
+            }
+            (keyboard::Key::Character("i" | "I"), true, true) => {
+                Some(Message::Selection(SelectionMessage::Invert))
+            }
+            (keyboard::Key::Character("x" | "X"), false, false) => {
+                Some(Message::Color(ColorMessage::Swap))
+            }
+            _ => None,
         }
     }
 
     fn update(&mut self, message: Message) -> Task<Message> {
         match message {
             Message::OpenFile => Task::perform(open_file(), Message::FileOpened),
-            Message::FileOpened(result) => {
-                let image_path = result.unwrap();
-                let dyn_image = ImageReader::open(image_path.as_path())
-                    .expect("Failed to open image.")
-                    .decode()
-                    .expect("Failed to decode image.");
-                self.image_path = image_path;
-                if let Ok(mut canvas_state) = self.canvas_state.try_write() {
-                    canvas_state.set_image(dyn_image);
+            Message::FileOpened(result) => match result {
+                Ok(path) => {
+                    self.error = None;
+                    self.loading = Some((path.clone(), 0.0));
+                    Task::run(file::load_file(path), Message::FileLoading)
                 }
-
+                // ダイアログを閉じただけなら何もしない
+                Err(Error::DialogClosed) => Task::none(),
+                Err(error) => {
+                    self.error = Some(error);
+                    Task::none()
+                }
+            },
+            Message::FileLoading(event) => {
+                match event {
+                    file::LoadEvent::Progress(progress) => {
+                        if let Some((_, current)) = self.loading.as_mut() {
+                            *current = progress;
+                        }
+                    }
+                    file::LoadEvent::Finished(result) => {
+                        let path = self.loading.take().map(|(path, _)| path);
+                        match (result, path) {
+                            (Ok(loaded), Some(path)) => {
+                                self.cancel_text();
+                                match loaded {
+                                    file::LoadedFile::Image(image) => {
+                                        if let Ok(mut canvas_state) = self.canvas_state.write() {
+                                            canvas_state.set_layers(LayerStack::new(image));
+                                        }
+                                    }
+                                    file::LoadedFile::Project(project) => {
+                                        self.apply_project(project)
+                                    }
+                                }
+                                self.image_path = path;
+                                self.is_dirty = false;
+                            }
+                            // 読み込みに失敗しても編集中のキャンバスはそのまま残す
+                            (Err(error), _) => self.error = Some(error),
+                            (Ok(_), None) => (),
+                        }
+                    }
+                }
+                Task::none()
+            }
+            Message::DismissError => {
+                self.error = None;
                 Task::none()
             }
-            Message::Exit => window::get_latest().and_then(window::close),
+            Message::Save => {
+                if self.image_path.as_os_str().is_empty() {
+                    Task::done(Message::SaveAs)
+                } else {
+                    self.save_image(self.image_path.clone())
+                }
+            }
+            Message::SaveAs => Task::perform(
+                pick_save_file(self.image_path.clone()),
+                Message::SaveFileSelected,
+            ),
+            Message::SaveFileSelected(result) => match result {
+                Ok(path) => self.save_image(path),
+                Err(_) => {
+                    self.exit_after_save = false;
+                    Task::none()
+                }
+            },
+            Message::FileSaved(result) => match result {
+                Ok(path) => {
+                    self.image_path = path;
+                    self.is_dirty = false;
+                    if self.exit_after_save {
+                        Self::close_window()
+                    } else {
+                        Task::none()
+                    }
+                }
+                Err(error) => {
+                    self.error = Some(error);
+                    self.exit_after_save = false;
+                    Task::none()
+                }
+            },
+            Message::SetJpegQuality(quality) => {
+                self.jpeg_quality = quality;
+                Task::none()
+            }
+            Message::PlaceImage => Task::perform(open_place_image(), Message::PlaceImageLoaded),
+            Message::PlaceImageLoaded(result) => {
+                match result {
+                    Ok(image) => {
+                        self.commit_floating();
+                        let floating = self.floating_in_view(image);
+                        self.start_floating(floating);
+                    }
+                    Err(Error::DialogClosed) => (),
+                    Err(error) => self.error = Some(error),
+                }
+                Task::none()
+            }
+            Message::SetSampling(sampling) => {
+                self.sampling = sampling;
+                Task::none()
+            }
+            Message::ModifiersChanged(modifiers) => {
+                if let Ok(mut state) = self.canvas_state.write() {
+                    state.modifiers = modifiers;
+                }
+                Task::none()
+            }
+            Message::Undo => {
+                if let Ok(mut state) = self.canvas_state.write() {
+                    if state.undo() {
+                        self.is_dirty = true;
+                    }
+                }
+                Task::none()
+            }
+            Message::Redo => {
+                if let Ok(mut state) = self.canvas_state.write() {
+                    if state.redo() {
+                        self.is_dirty = true;
+                    }
+                }
+                Task::none()
+            }
+            Message::Selection(message) => {
+                if let Ok(mut state) = self.canvas_state.write() {
+                    match message {
+                        SelectionMessage::All => state.selection.select_all(),
+                        SelectionMessage::Deselect => state.selection.clear(),
+                        SelectionMessage::Invert => state.selection.invert(),
+                    }
+                }
+                Task::none()
+            }
+            Message::Symmetry(message) => {
+                if let Ok(mut state) = self.canvas_state.write() {
+                    match message {
+                        SymmetryMessage::ToggleMeridian => {
+                            state.symmetry.mirror_meridian = !state.symmetry.mirror_meridian
+                        }
+                        SymmetryMessage::ToggleEquator => {
+                            state.symmetry.mirror_equator = !state.symmetry.mirror_equator
+                        }
+                        SymmetryMessage::SetRadial(radial) => state.symmetry.radial = radial,
+                        SymmetryMessage::AlignToView => {
+                            let (lon, _) = Self::look_at_to_latlng(state.look_at);
+                            state.symmetry.meridian = lon;
+                        }
+                    }
+                }
+                Task::none()
+            }
+            Message::Exit => {
+                if self.is_dirty {
+                    Task::perform(confirm_discard_changes(), Message::ExitConfirmed)
+                } else {
+                    Self::close_window()
+                }
+            }
+            Message::ExitConfirmed(result) => match result {
+                MessageDialogResult::Yes => {
+                    self.exit_after_save = true;
+                    Task::done(Message::Save)
+                }
+                MessageDialogResult::No => Self::close_window(),
+                _ => Task::none(),
+            },
 
             Message::SphereCanvasMessage(msg) => {
                 match msg {
@@ -113,12 +779,35 @@ impl App {
                         if let Ok(mut state) = self.canvas_state.write() {
                             state.mouse_button = Some(button);
                             state.mouse_delta = vec2(0.0, 0.0);
+                            // 押下から解放までの編集を1つの履歴にまとめる
+                            if let Ok(mut history) = state.history.write() {
+                                history.begin_step();
+                            }
                         }
 
+                        // 描画ツールでAltキーを押しながらクリックしたら、解放するまでスポイトを使う
+                        let alt = self
+                            .canvas_state
+                            .read()
+                            .is_ok_and(|state| state.modifiers.alt());
+                        self.temporary_tool = (alt && self.current_tool.handle.paints())
+                            .then(|| self.eyedropper_tool.clone());
+
                         let mut status = self
-                            .current_tool
+                            .active_tool()
                             .handle
                             .on_mouse_pressed(&self.canvas_state);
+
+                        // 描画色で描いたら、その色を最近使った色に加える
+                        if button == mouse::Button::Left
+                            && status == Status::Captured
+                            && self.active_tool().handle.uses_foreground()
+                        {
+                            if let Ok(state) = self.canvas_state.read() {
+                                color::push_recent(&mut self.recent_colors, state.foreground);
+                            }
+                        }
+
                         if status == Status::Ignored {
                             status = self.pan_tool.handle.on_mouse_pressed(&self.canvas_state);
                         }
@@ -135,7 +824,7 @@ impl App {
                         }
 
                         let mut status = self
-                            .current_tool
+                            .active_tool()
                             .handle
                             .on_mouse_released(&self.canvas_state);
                         if status == Status::Ignored {
@@ -144,6 +833,13 @@ impl App {
                         if status == Status::Ignored {
                             status = self.zoom_tool.handle.on_mouse_released(&self.canvas_state);
                         }
+                        self.temporary_tool = None;
+
+                        if let Ok(state) = self.canvas_state.read() {
+                            if let Ok(mut history) = state.history.write() {
+                                history.end_step();
+                            }
+                        }
                     }
                     widget::sphere_canvas::SphereCanvasMessage::MouseMoved { position } => {
                         if let Ok(mut state) = self.canvas_state.write() {
@@ -156,7 +852,7 @@ impl App {
                         }
 
                         let mut status =
-                            self.current_tool.handle.on_mouse_moved(&self.canvas_state);
+                            self.active_tool().handle.on_mouse_moved(&self.canvas_state);
                         if status == Status::Ignored {
                             status = self.pan_tool.handle.on_mouse_moved(&self.canvas_state);
                         }
@@ -169,7 +865,7 @@ impl App {
                             state.mouse_wheel_delta = delta;
                         }
 
-                        let mut status = self.current_tool.handle.on_wheel(&self.canvas_state);
+                        let mut status = self.active_tool().handle.on_wheel(&self.canvas_state);
                         if status == Status::Ignored {
                             status = self.pan_tool.handle.on_wheel(&self.canvas_state);
                         }
@@ -183,68 +879,866 @@ impl App {
                         }
                     }
                 }
-
-                Task::none()
+
+                // ツールがテクスチャを更新したら未保存扱いにする
+                if let Ok(state) = self.canvas_state.read() {
+                    if state.is_modified() {
+                        self.is_dirty = true;
+                    }
+                }
+                // スポイトで色が変わったら入力欄にも反映する
+                let color = self.edited_color();
+                if color::parse_hex(&self.hex_input) != Some(color) {
+                    self.hex_input = color::to_hex(color);
+                }
+
+                Task::none()
+            }
+
+            Message::ChangeTool(tool) => {
+                // 貼り付けた画像は別のツールに切り替えたら確定する
+                self.commit_floating();
+                // 置いた文字も別のツールに切り替えたら確定する
+                self.commit_text();
+                // 前のツールの描画途中の状態とプレビューを消す
+                self.current_tool.handle.deactivate();
+                if let Ok(mut state) = self.canvas_state.write() {
+                    state.overlay.clear();
+                }
+                self.current_tool = tool;
+                Task::none()
+            }
+            Message::ChangeProjection(projection) => {
+                if let Ok(mut state) = self.canvas_state.write() {
+                    state.projection = projection;
+                    // 正距円筒図法では画像全体を表示する
+                    state.aov = if projection == ViewProjection::Equirectangular {
+                        TAU
+                    } else {
+                        state.aov.min(projection.max_aov())
+                    };
+                }
+                Task::none()
+            }
+            Message::Layer(msg) => {
+                if let Ok(mut state) = self.canvas_state.write() {
+                    let layers = &mut state.layers;
+                    let changed = match msg {
+                        LayerMessage::Select(index) => {
+                            if index < layers.layers.len() {
+                                layers.active = index;
+                            }
+                            false
+                        }
+                        LayerMessage::ToggleVisible(index) => match layers.layers.get_mut(index) {
+                            Some(layer) => {
+                                layer.visible = !layer.visible;
+                                true
+                            }
+                            None => false,
+                        },
+                        LayerMessage::ToggleLocked(index) => match layers.layers.get_mut(index) {
+                            Some(layer) => {
+                                layer.locked = !layer.locked;
+                                true
+                            }
+                            None => false,
+                        },
+                        LayerMessage::SetOpacity(opacity) => match layers.active_layer_mut() {
+                            Some(layer) => {
+                                layer.opacity = opacity.clamp(0.0, 1.0);
+                                true
+                            }
+                            None => false,
+                        },
+                        LayerMessage::SetBlendMode(blend_mode) => match layers.active_layer_mut() {
+                            Some(layer) => {
+                                layer.blend_mode = blend_mode;
+                                true
+                            }
+                            None => false,
+                        },
+                        LayerMessage::Add => layers.add_layer(),
+                        LayerMessage::Remove => layers.remove_active(),
+                        LayerMessage::MoveUp => layers.move_active(1),
+                        LayerMessage::MoveDown => layers.move_active(-1),
+                    };
+                    if changed {
+                        self.is_dirty = true;
+                    }
+                }
+                Task::none()
+            }
+            Message::Color(msg) => {
+                let task = self.update_color(msg);
+                // 置いた文字を描画色で描き直す
+                self.refresh_text();
+                task
+            }
+            Message::Brush(msg) => self.update_brush(msg),
+            Message::Eyedropper(msg) => {
+                if let Ok(mut settings) = self.eyedropper.settings.write() {
+                    match msg {
+                        EyedropperMessage::SetSampleSize(size) => settings.sample_size = size,
+                        EyedropperMessage::ToggleSampleMerged(merged) => {
+                            settings.sample_merged = merged
+                        }
+                    }
+                }
+                Task::none()
+            }
+            Message::Fill(msg) => {
+                if let Ok(mut settings) = self.fill.settings.write() {
+                    match msg {
+                        FillMessage::SetTolerance(tolerance) => settings.tolerance = tolerance,
+                        FillMessage::ToggleContiguous(contiguous) => {
+                            settings.contiguous = contiguous
+                        }
+                        FillMessage::ToggleAntialias(antialias) => settings.antialias = antialias,
+                        FillMessage::SetOpacity(opacity) => settings.opacity = opacity,
+                    }
+                }
+                Task::none()
+            }
+            Message::Shape(msg) => {
+                if let Some(shape) = self.current_shape() {
+                    if let Ok(mut settings) = shape.settings.write() {
+                        match msg {
+                            ShapeMessage::ToggleStroke(stroke) => settings.stroke = stroke,
+                            ShapeMessage::ToggleFill(fill) => settings.fill = fill,
+                        }
+                    }
+                }
+                Task::none()
+            }
+            Message::Select(msg) => {
+                if let Some(select) = self.current_select() {
+                    if let Ok(mut settings) = select.settings.write() {
+                        match msg {
+                            SelectMessage::SetMode(mode) => settings.mode = mode,
+                            SelectMessage::SetTolerance(tolerance) => {
+                                settings.tolerance = tolerance
+                            }
+                            SelectMessage::ToggleAntialias(antialias) => {
+                                settings.antialias = antialias
+                            }
+                            SelectMessage::ToggleContiguous(contiguous) => {
+                                settings.contiguous = contiguous
+                            }
+                        }
+                    }
+                }
+                Task::none()
+            }
+            Message::Clone(msg) => {
+                if let Some(clone) = self.current_clone() {
+                    if let Ok(mut settings) = clone.settings.write() {
+                        match msg {
+                            CloneMessage::ToggleAligned(aligned) => settings.aligned = aligned,
+                        }
+                    }
+                }
+                Task::none()
+            }
+            Message::Retouch(msg) => {
+                if let Some(retouch) = self.current_retouch() {
+                    if let Ok(mut settings) = retouch.settings.write() {
+                        match msg {
+                            RetouchMessage::SetStrength(strength) => settings.strength = strength,
+                            RetouchMessage::SetKernel(kernel) => settings.kernel = kernel,
+                        }
+                    }
+                }
+                Task::none()
+            }
+            Message::Gradient(msg) => self.update_gradient(msg),
+            Message::Text(msg) => self.update_text(msg),
+            Message::Clipboard(msg) => self.update_clipboard(msg),
+            Message::Patch(msg) => self.update_patch(msg),
+        }
+    }
+
+    fn update_clipboard(&mut self, message: ClipboardMessage) -> Task<Message> {
+        match message {
+            ClipboardMessage::Cut | ClipboardMessage::Copy => {
+                self.commit_floating();
+                let content = self
+                    .canvas_state
+                    .read()
+                    .ok()
+                    .and_then(|state| clipboard::copy(&state));
+                let Some(content) = content else {
+                    return Task::none();
+                };
+
+                // システムのクリップボードに置けなくてもアプリ内では貼り付けられる
+                let content = Arc::new(content);
+                if let Err(error) = self
+                    .system_clipboard()
+                    .and_then(|system| clipboard::write_system(system, &content.flat))
+                {
+                    self.error = Some(Error::Clipboard(error.to_string()));
+                }
+                self.clipboard = Some(content);
+
+                if matches!(message, ClipboardMessage::Cut) {
+                    if let Ok(mut state) = self.canvas_state.write() {
+                        if let Ok(mut history) = state.history.write() {
+                            history.begin_step();
+                        }
+                        if clipboard::clear(&mut state) {
+                            self.is_dirty = true;
+                        }
+                        if let Ok(mut history) = state.history.write() {
+                            history.end_step();
+                        }
+                    }
+                }
+            }
+            ClipboardMessage::Paste => {
+                self.commit_floating();
+
+                // システムのクリップボードの画像が最後にコピーした内容なら、元のピクセルから貼り付ける
+                let floating = match self.system_clipboard().and_then(clipboard::read_system) {
+                    Ok(image) => match self.clipboard.as_ref() {
+                        Some(content)
+                            if content.flat.dimensions() == image.dimensions()
+                                && content.flat.as_raw() == image.as_raw() =>
+                        {
+                            FloatingImage::from_clipboard(content.clone())
+                        }
+                        _ => self.floating_in_view(image),
+                    },
+                    Err(_) => match self.clipboard.as_ref() {
+                        Some(content) => FloatingImage::from_clipboard(content.clone()),
+                        None => return Task::none(),
+                    },
+                };
+
+                self.start_floating(floating);
+            }
+            ClipboardMessage::Commit => self.commit_floating(),
+            ClipboardMessage::Cancel => {
+                if let Ok(mut state) = self.canvas_state.write() {
+                    state.floating = None;
+                }
+                if let Some(tool) = self.tool_before_paste.take() {
+                    self.current_tool = tool;
+                }
+            }
+        }
+        Task::none()
+    }
+
+    /**
+     * システムのクリップボード (初めて使う時に開く)
+     */
+    fn system_clipboard(&mut self) -> Result<&mut arboard::Clipboard, arboard::Error> {
+        let system = match self.system_clipboard.take() {
+            Some(system) => system,
+            None => arboard::Clipboard::new()?,
+        };
+        Ok(self.system_clipboard.insert(system))
+    }
+
+    /**
+     * 貼り付ける画像をviewの中央に、はみ出さない大きさで置く
+     */
+    fn floating_in_view(&self, image: image::RgbaImage) -> FloatingImage {
+        let bounds = self
+            .canvas_state
+            .read()
+            .map(|state| state.viewport_bounds)
+            .unwrap_or_default();
+        let mut floating = FloatingImage::new(image, vec2(bounds.width, bounds.height) * 0.5);
+        let size = floating.image_size();
+        if size.x > 0.0 && size.y > 0.0 {
+            floating.scale = (bounds.width / size.x).min(bounds.height / size.y).min(1.0);
+        }
+        floating
+    }
+
+    /**
+     * 画像を確定前の状態で置き、変形ツールに切り替える
+     */
+    fn start_floating(&mut self, floating: FloatingImage) {
+        if let Ok(mut state) = self.canvas_state.write() {
+            state.floating = Some(floating);
+        }
+        if self.tool_before_paste.is_none() {
+            self.tool_before_paste = Some(self.current_tool.clone());
+        }
+        self.current_tool = self.transform_tool.clone();
+    }
+
+    /**
+     * 貼り付けた画像を球面に描画して確定し、貼り付ける前のツールに戻す
+     */
+    fn commit_floating(&mut self) {
+        if let Ok(mut state) = self.canvas_state.write() {
+            if let Some(floating) = state.floating.take() {
+                if let Ok(mut history) = state.history.write() {
+                    history.begin_step();
+                }
+                if floating::draw_floating(&mut state, &floating, self.sampling) {
+                    self.is_dirty = true;
+                }
+                if let Ok(mut history) = state.history.write() {
+                    history.end_step();
+                }
+            }
+        }
+        if let Some(tool) = self.tool_before_paste.take() {
+            self.current_tool = tool;
+        }
+    }
+
+    fn update_patch(&mut self, message: PatchMessage) -> Task<Message> {
+        match message {
+            PatchMessage::SetAngle(angle) => self.patch_angle = angle,
+            PatchMessage::View(pole) => {
+                if let Ok(mut state) = self.canvas_state.write() {
+                    let patch = PolePatch::new(
+                        pole,
+                        (self.patch_angle as f32).to_radians(),
+                        state.image_width,
+                    );
+                    state.projection = ViewProjection::Rectilinear;
+                    state.aov = patch.projection.aov;
+                    state.look_at = patch.projection.look_at;
+                    state.up = patch.projection.up;
+                    state.right = patch.projection.right;
+                }
+            }
+            PatchMessage::Export(pole) => {
+                if let Some((_, image)) = self.extract_patch(pole) {
+                    return Task::perform(
+                        save_patch_as(pole, image, self.jpeg_quality),
+                        |result| Message::Patch(PatchMessage::Exported(result)),
+                    );
+                }
+            }
+            PatchMessage::Exported(result) => match result {
+                Ok(_) | Err(Error::DialogClosed) => (),
+                Err(error) => self.error = Some(error),
+            },
+            PatchMessage::Import(pole) => {
+                return Task::perform(open_place_image(), move |result| {
+                    Message::Patch(PatchMessage::Imported(pole, result))
+                });
+            }
+            PatchMessage::Imported(pole, result) => match result {
+                // 外部のエディタで編集した画像で置き換える
+                Ok(image) => self.edit_patch(pole, |_, _| image),
+                Err(Error::DialogClosed) => (),
+                Err(error) => self.error = Some(error),
+            },
+            PatchMessage::PlaceLogo(pole) => {
+                return Task::perform(open_place_image(), move |result| {
+                    Message::Patch(PatchMessage::LogoLoaded(pole, result))
+                });
+            }
+            PatchMessage::LogoLoaded(pole, result) => match result {
+                Ok(logo) => self.edit_patch(pole, |patch, mut image| {
+                    patch.place_logo(&mut image, &logo);
+                    image
+                }),
+                Err(Error::DialogClosed) => (),
+                Err(error) => self.error = Some(error),
+            },
+            PatchMessage::BlurFill(pole) => self.edit_patch(pole, |patch, mut image| {
+                patch.blur_fill(&mut image);
+                image
+            }),
+        }
+        Task::none()
+    }
+
+    /**
+     * アクティブなレイヤーから極の付近を切り出す
+     */
+    fn extract_patch(&self, pole: Pole) -> Option<(PolePatch, image::RgbaImage)> {
+        let state = self.canvas_state.read().ok()?;
+        let patch = PolePatch::new(
+            pole,
+            (self.patch_angle as f32).to_radians(),
+            state.image_width,
+        );
+        let rw_image = state.active_image()?;
+        let image = rw_image.read().ok()?;
+        Some((patch, patch.extract(&image, self.sampling)))
+    }
+
+    /**
+     * 極の付近を切り出して edit で編集し、アクティブなレイヤーに射影し直す
+     */
+    fn edit_patch(
+        &mut self,
+        pole: Pole,
+        edit: impl FnOnce(&PolePatch, image::RgbaImage) -> image::RgbaImage,
+    ) {
+        self.commit_floating();
+        let Some((patch, original)) = self.extract_patch(pole) else {
+            return;
+        };
+        let edited = edit(&patch, original.clone());
+
+        if let Ok(mut state) = self.canvas_state.write() {
+            if let Ok(mut history) = state.history.write() {
+                history.begin_step();
+            }
+            if patch.apply(&mut state, &original, &edited, self.sampling) {
+                self.is_dirty = true;
+            }
+            if let Ok(mut history) = state.history.write() {
+                history.end_step();
+            }
+        }
+    }
+
+    fn update_brush(&mut self, message: BrushMessage) -> Task<Message> {
+        let tool = &self.current_tool.handle;
+        let Some(mut brush) = tool.brush() else {
+            return Task::none();
+        };
+
+        match message {
+            BrushMessage::SetWidth(width) => brush.width = width,
+            BrushMessage::SetHardness(hardness) => brush.hardness = hardness,
+            BrushMessage::SetSpacing(spacing) => brush.spacing = spacing,
+            BrushMessage::SetOpacity(opacity) => brush.opacity = opacity,
+            BrushMessage::SetFlow(flow) => brush.flow = flow,
+            BrushMessage::ToggleAntialias(antialias) => brush.antialias = antialias,
+        }
+        tool.set_brush(brush);
+
+        Task::none()
+    }
+
+    fn update_gradient(&mut self, message: GradientMessage) -> Task<Message> {
+        let foreground = self.canvas_state.read().ok().map(|state| state.foreground);
+        let Ok(mut settings) = self.gradient.settings.write() else {
+            return Task::none();
+        };
+        // 区切りが減っていても範囲外を指さないようにする
+        self.selected_stop = self
+            .selected_stop
+            .min(settings.gradient.stops.len().saturating_sub(1));
+
+        match message {
+            GradientMessage::SetMode(mode) => settings.mode = mode,
+            GradientMessage::SetOpacity(opacity) => settings.opacity = opacity,
+            GradientMessage::ToggleReverse(reverse) => settings.reverse = reverse,
+            GradientMessage::SelectStop(index) => {
+                if index < settings.gradient.stops.len() {
+                    self.selected_stop = index;
+                }
+            }
+            GradientMessage::SetStopPosition(position) => {
+                if let Some(stop) = settings.gradient.stops.get_mut(self.selected_stop) {
+                    stop.position = position;
+                }
+            }
+            GradientMessage::SetStopColor => {
+                if let (Some(stop), Some(color)) = (
+                    settings.gradient.stops.get_mut(self.selected_stop),
+                    foreground,
+                ) {
+                    stop.color = color.0;
+                }
+            }
+            GradientMessage::AddStop => {
+                if let Some(color) = foreground {
+                    settings.gradient.stops.push(GradientStop {
+                        position: 0.5,
+                        color: color.0,
+                    });
+                    self.selected_stop = settings.gradient.stops.len() - 1;
+                }
+            }
+            GradientMessage::RemoveStop => {
+                // 色を決めるには区切りが2つ必要
+                if settings.gradient.stops.len() > 2
+                    && self.selected_stop < settings.gradient.stops.len()
+                {
+                    settings.gradient.stops.remove(self.selected_stop);
+                    self.selected_stop = self.selected_stop.min(settings.gradient.stops.len() - 1);
+                }
+            }
+            GradientMessage::LoadPreset(name) => {
+                if let Some(preset) = settings.presets.iter().find(|preset| preset.name == name) {
+                    settings.gradient = preset.gradient.clone();
+                    self.selected_stop = 0;
+                }
+            }
+            GradientMessage::SavePreset => {
+                let name = format!("Preset {}", settings.presets.len() + 1);
+                let gradient = settings.gradient.clone();
+                settings.presets.push(GradientPreset { name, gradient });
+            }
+        }
+
+        Task::none()
+    }
+
+    fn update_text(&mut self, message: TextMessage) -> Task<Message> {
+        if let Ok(mut settings) = self.text.settings.write() {
+            match message {
+                TextMessage::SetText(ref text) => settings.text = text.clone(),
+                TextMessage::SetFamily(ref family) => settings.family = family.clone(),
+                TextMessage::SetSize(size) => settings.size = size,
+                TextMessage::SetOrientation(orientation) => settings.orientation = orientation,
+                TextMessage::Commit | TextMessage::Cancel => (),
+            }
+        }
+
+        match message {
+            TextMessage::Commit => self.commit_text(),
+            TextMessage::Cancel => self.cancel_text(),
+            _ => self.refresh_text(),
+        }
+        Task::none()
+    }
+
+    /**
+     * 置いた文字を今の設定で描画し直す
+     */
+    fn refresh_text(&mut self) {
+        if !self.text.is_placed() {
+            return;
+        }
+        if let Ok(mut state) = self.canvas_state.write() {
+            self.text.refresh(&mut state);
+        }
+    }
+
+    /**
+     * 置いた文字をレイヤーに確定する
+     */
+    fn commit_text(&mut self) {
+        if !self.text.is_placed() {
+            return;
+        }
+        if let Ok(mut state) = self.canvas_state.write() {
+            if let Ok(mut history) = state.history.write() {
+                history.begin_step();
+            }
+            if self.text.commit(&mut state) {
+                self.is_dirty = true;
+            }
+            if let Ok(mut history) = state.history.write() {
+                history.end_step();
+            }
+        }
+    }
+
+    /**
+     * 置いた文字を取り消す
+     */
+    fn cancel_text(&mut self) {
+        if let Ok(mut state) = self.canvas_state.write() {
+            self.text.cancel(&mut state);
+        }
+    }
+
+    fn update_color(&mut self, message: ColorMessage) -> Task<Message> {
+        match message {
+            ColorMessage::SelectTarget(target) => {
+                self.color_target = target;
+                self.hex_input = color::to_hex(self.edited_color());
+            }
+            ColorMessage::Swap => {
+                if let Ok(mut state) = self.canvas_state.write() {
+                    let state = &mut *state;
+                    std::mem::swap(&mut state.foreground, &mut state.background);
+                }
+                self.hex_input = color::to_hex(self.edited_color());
+            }
+            ColorMessage::SetHue(hue) => self.set_hsv(Hsv {
+                h: hue,
+                ..self.current_hsv()
+            }),
+            ColorMessage::SetSaturation(saturation) => self.set_hsv(Hsv {
+                s: saturation,
+                ..self.current_hsv()
+            }),
+            ColorMessage::SetValue(value) => self.set_hsv(Hsv {
+                v: value,
+                ..self.current_hsv()
+            }),
+            ColorMessage::SetChannel(channel, value) => {
+                let mut color = self.edited_color();
+                if let Some(c) = color.0.get_mut(channel) {
+                    *c = value.round().clamp(0.0, 255.0) as u8;
+                }
+                self.set_edited_color(color);
+            }
+            ColorMessage::HexChanged(input) => {
+                // 入力途中の文字列はそのまま残し、色として読めたときだけ反映する
+                if let Some(color) = color::parse_hex(&input) {
+                    self.set_edited_color(color);
+                }
+                self.hex_input = input;
+            }
+            ColorMessage::Pick(color) => self.set_edited_color(color),
+            ColorMessage::SelectSwatch(index) => {
+                if let Some(swatch) = self.palette.swatches.get(index) {
+                    let color = swatch.color;
+                    self.selected_swatch = Some(index);
+                    self.set_edited_color(color);
+                }
+            }
+            ColorMessage::AddSwatch => {
+                let color = self.edited_color();
+                self.palette.swatches.push(Swatch {
+                    name: color::to_hex(color),
+                    color,
+                });
+                self.selected_swatch = Some(self.palette.swatches.len() - 1);
+            }
+            ColorMessage::RemoveSwatch => {
+                if let Some(index) = self.selected_swatch.take() {
+                    if index < self.palette.swatches.len() {
+                        self.palette.swatches.remove(index);
+                    }
+                }
+            }
+            ColorMessage::LoadPalette => {
+                return Task::perform(open_palette(), |result| {
+                    Message::Color(ColorMessage::PaletteLoaded(result))
+                });
+            }
+            ColorMessage::PaletteLoaded(result) => match result {
+                Ok(palette) => {
+                    self.palette = palette;
+                    self.selected_swatch = None;
+                }
+                Err(Error::DialogClosed) => (),
+                Err(error) => self.error = Some(error),
+            },
+            ColorMessage::SavePalette => {
+                return Task::perform(save_palette_as(self.palette.clone()), |result| {
+                    Message::Color(ColorMessage::PaletteSaved(result))
+                });
             }
+            ColorMessage::PaletteSaved(result) => match result {
+                Ok(_) | Err(Error::DialogClosed) => (),
+                Err(error) => self.error = Some(error),
+            },
+        }
+        Task::none()
+    }
 
-            Message::ChangeTool(tool) => {
-                self.current_tool = tool;
-                Task::none()
+    /**
+     * カラーパネルで編集している色
+     */
+    fn edited_color(&self) -> Rgba<u8> {
+        let Ok(state) = self.canvas_state.read() else {
+            return Rgba([0, 0, 0, 255]);
+        };
+        match self.color_target {
+            ColorTarget::Foreground => state.foreground,
+            ColorTarget::Background => state.background,
+        }
+    }
+
+    fn set_edited_color(&mut self, color: Rgba<u8>) {
+        if let Ok(mut state) = self.canvas_state.write() {
+            match self.color_target {
+                ColorTarget::Foreground => state.foreground = color,
+                ColorTarget::Background => state.background = color,
             }
         }
+        self.hex_input = color::to_hex(color);
+    }
+
+    /**
+     * 編集している色のHSV (覚えているHSVが今の色と一致すればそちらを使う)
+     */
+    fn current_hsv(&self) -> Hsv {
+        let color = self.edited_color();
+        if self.color_hsv.to_rgb(color[3]) == color {
+            self.color_hsv
+        } else {
+            Hsv::from_rgb(color)
+        }
+    }
+
+    fn set_hsv(&mut self, hsv: Hsv) {
+        let alpha = self.edited_color()[3];
+        self.color_hsv = hsv;
+        self.set_edited_color(hsv.to_rgb(alpha));
     }
 
     fn view(&'_ self) -> Element<'_, Message> {
         let menu_tpl = |items| Menu::new(items).max_width(180.0).offset(0.0).spacing(5.0);
+        let symmetry = self
+            .canvas_state
+            .read()
+            .map(|state| state.symmetry)
+            .unwrap_or_default();
 
         #[rustfmt::skip]
         let content = column![
             menu_bar!(
                 (Self::menu_bar_item("File"), menu_tpl(
                     menu_items!(
-                        (Self::menu_button("Open").on_press(Message::OpenFile))
-                        (Self::menu_button("Save"))
+                        (Self::menu_button("Open").on_press_maybe(self.loading.is_none().then_some(Message::OpenFile)))
+                        (Self::menu_button("Save").on_press(Message::Save))
+                        (Self::menu_button("Save As").on_press(Message::SaveAs))
+                        (Self::menu_button("Place Image").on_press(Message::PlaceImage))
+                        (Self::menu_button("JPEG Quality"), menu_tpl(
+                            menu_items!(
+                                (self.jpeg_quality_button("Low", 60))
+                                (self.jpeg_quality_button("Medium", 75))
+                                (self.jpeg_quality_button("High", 90))
+                                (self.jpeg_quality_button("Maximum", 100))
+                            )
+                        ))
                         (Self::separator())
                         (Self::menu_button("Exit").on_press(Message::Exit))
                     )
                 ))
                 (Self::menu_bar_item("Edit"), menu_tpl(
                     menu_items!(
-                        (Self::menu_button("Undo"))
-                        (Self::menu_button("Redo"))
+                        (Self::menu_button("Undo").on_press(Message::Undo))
+                        (Self::menu_button("Redo").on_press(Message::Redo))
                         (Self::separator())
-                        (Self::menu_button("Cut"))
-                        (Self::menu_button("Copy"))
-                        (Self::menu_button("Paste"))
+                        (Self::menu_button("Cut").on_press(Message::Clipboard(ClipboardMessage::Cut)))
+                        (Self::menu_button("Copy").on_press(Message::Clipboard(ClipboardMessage::Copy)))
+                        (Self::menu_button("Paste").on_press(Message::Clipboard(ClipboardMessage::Paste)))
+                        (Self::menu_button("Resampling"), menu_tpl(
+                            menu_items!(
+                                (self.sampling_button(Sampling::Bilinear))
+                                (self.sampling_button(Sampling::Bicubic))
+                            )
+                        ))
+                        (Self::menu_button("Symmetry"), menu_tpl(
+                            menu_items!(
+                                (Self::check_menu_button("Mirror Meridian", symmetry.mirror_meridian).on_press(Message::Symmetry(SymmetryMessage::ToggleMeridian)))
+                                (Self::check_menu_button("Mirror Equator", symmetry.mirror_equator).on_press(Message::Symmetry(SymmetryMessage::ToggleEquator)))
+                                (Self::menu_button("Radial"), menu_tpl(
+                                    menu_items!(
+                                        (Self::radial_button(&symmetry, 1))
+                                        (Self::radial_button(&symmetry, 2))
+                                        (Self::radial_button(&symmetry, 3))
+                                        (Self::radial_button(&symmetry, 4))
+                                        (Self::radial_button(&symmetry, 6))
+                                        (Self::radial_button(&symmetry, 8))
+                                        (Self::radial_button(&symmetry, 12))
+                                    )
+                                ))
+                                (Self::menu_button("Align to View").on_press(Message::Symmetry(SymmetryMessage::AlignToView)))
+                            )
+                        ))
+                    )
+                ))
+                (Self::menu_bar_item("Select"), menu_tpl(
+                    menu_items!(
+                        (Self::menu_button("All").on_press(Message::Selection(SelectionMessage::All)))
+                        (Self::menu_button("Deselect").on_press(Message::Selection(SelectionMessage::Deselect)))
+                        (Self::menu_button("Invert").on_press(Message::Selection(SelectionMessage::Invert)))
+                    )
+                ))
+                (Self::menu_bar_item("Pole"), menu_tpl(
+                    menu_items!(
+                        (Self::menu_button("Nadir"), menu_tpl(
+                            menu_items!(
+                                (Self::menu_button("Edit in View").on_press(Message::Patch(PatchMessage::View(Pole::Nadir))))
+                                (Self::menu_button("Export Patch").on_press(Message::Patch(PatchMessage::Export(Pole::Nadir))))
+                                (Self::menu_button("Import Patch").on_press(Message::Patch(PatchMessage::Import(Pole::Nadir))))
+                                (Self::menu_button("Place Logo").on_press(Message::Patch(PatchMessage::PlaceLogo(Pole::Nadir))))
+                                (Self::menu_button("Blur Fill").on_press(Message::Patch(PatchMessage::BlurFill(Pole::Nadir))))
+                            )
+                        ))
+                        (Self::menu_button("Zenith"), menu_tpl(
+                            menu_items!(
+                                (Self::menu_button("Edit in View").on_press(Message::Patch(PatchMessage::View(Pole::Zenith))))
+                                (Self::menu_button("Export Patch").on_press(Message::Patch(PatchMessage::Export(Pole::Zenith))))
+                                (Self::menu_button("Import Patch").on_press(Message::Patch(PatchMessage::Import(Pole::Zenith))))
+                                (Self::menu_button("Place Logo").on_press(Message::Patch(PatchMessage::PlaceLogo(Pole::Zenith))))
+                                (Self::menu_button("Blur Fill").on_press(Message::Patch(PatchMessage::BlurFill(Pole::Zenith))))
+                            )
+                        ))
+                        (Self::menu_button("Patch Size"), menu_tpl(
+                            menu_items!(
+                                (self.patch_angle_button(20))
+                                (self.patch_angle_button(30))
+                                (self.patch_angle_button(45))
+                            )
+                        ))
+                    )
+                ))
+                (Self::menu_bar_item("View"), menu_tpl(
+                    menu_items!(
+                        (self.projection_button(ViewProjection::Rectilinear))
+                        (self.projection_button(ViewProjection::Stereographic))
+                        (self.projection_button(ViewProjection::Fisheye))
+                        (self.projection_button(ViewProjection::Pannini))
+                        (self.projection_button(ViewProjection::Equirectangular))
                     )
                 ))
             ),
+            self.error_banner(),
             row![
                 column![
                     self.tool_button(&self.pen_tool),
+                    self.tool_button(&self.eraser_tool),
+                    self.tool_button(&self.fill_tool),
+                    self.tool_button(&self.eyedropper_tool),
+                    self.tool_button(&self.line_tool),
+                    self.tool_button(&self.circle_tool),
+                    self.tool_button(&self.rectangle_tool),
+                    self.tool_button(&self.polygon_tool),
+                    self.tool_button(&self.gradient_tool),
+                    self.tool_button(&self.marquee_tool),
+                    self.tool_button(&self.lasso_tool),
+                    self.tool_button(&self.magic_wand_tool),
+                    self.tool_button(&self.clone_tool),
+                    self.tool_button(&self.healing_tool),
+                    self.tool_button(&self.smudge_tool),
+                    self.tool_button(&self.blur_tool),
+                    self.tool_button(&self.sharpen_tool),
+                    self.tool_button(&self.dodge_tool),
+                    self.tool_button(&self.burn_tool),
+                    self.tool_button(&self.text_tool),
                 ]
                 .height(Length::Fill),
-                shader((|| {
-                    sphere_canvas(
-                        self.canvas_state.clone(),
-                    ).on_event(|msg| {
-                        match msg {
-                            SphereCanvasMessage::MousePressed { button, position } => Message::SphereCanvasMessage(SphereCanvasMessage::MousePressed { button, position }),
-                            SphereCanvasMessage::MouseReleased { button, position } => Message::SphereCanvasMessage(SphereCanvasMessage::MouseReleased { button, position }),
-                            SphereCanvasMessage::MouseMoved { position } => Message::SphereCanvasMessage(SphereCanvasMessage::MouseMoved { position }),
-                            SphereCanvasMessage::MouseWheel { delta } => Message::SphereCanvasMessage(SphereCanvasMessage::MouseWheel { delta }),
-                            SphereCanvasMessage::BoundsChanged(bounds) => Message::SphereCanvasMessage(SphereCanvasMessage::BoundsChanged(bounds)),
-                        }
-                    })
-                })())
+                self.color_panel(),
+                self.tool_options_panel(),
+                self.gradient_panel(),
+                self.text_panel(),
+                stack![
+                    shader((|| {
+                        sphere_canvas(
+                            self.canvas_state.clone(),
+                        ).on_event(|msg| {
+                            match msg {
+                                SphereCanvasMessage::MousePressed { button, position } => Message::SphereCanvasMessage(SphereCanvasMessage::MousePressed { button, position }),
+                                SphereCanvasMessage::MouseReleased { button, position } => Message::SphereCanvasMessage(SphereCanvasMessage::MouseReleased { button, position }),
+                                SphereCanvasMessage::MouseMoved { position } => Message::SphereCanvasMessage(SphereCanvasMessage::MouseMoved { position }),
+                                SphereCanvasMessage::MouseWheel { delta } => Message::SphereCanvasMessage(SphereCanvasMessage::MouseWheel { delta }),
+                                SphereCanvasMessage::BoundsChanged(bounds) => Message::SphereCanvasMessage(SphereCanvasMessage::BoundsChanged(bounds)),
+                            }
+                        })
+                    })())
+                    .width(Length::Fill)
+                    .height(Length::Fill),
+                    // ツールのプレビューを画像に重ねて描画する
+                    canvas(sphere_overlay(self.canvas_state.clone()))
+                        .width(Length::Fill)
+                        .height(Length::Fill),
+                ]
                 .width(Length::Fill)
-                .height(Length::Fill)
+                .height(Length::Fill),
+                self.layers_panel(),
             ]
             .width(Length::Fill)
             .height(Length::Fill),
             row![
                 container(text!("{}", self.image_path.as_path().to_str().unwrap()))
                     .width(Length::Fill),
+                self.loading_indicator(),
                 container(row![
                     (|| {
                         if let Ok(state) = self.canvas_state.read() {
@@ -297,13 +1791,234 @@ impl App {
         container(text(label).align_x(Alignment::Start)).padding([4, 8])
     }
 
-    fn menu_button(label: &'_ str) -> button::Button<'_, Message, iced::Theme, iced::Renderer> {
+    fn menu_button<'a>(
+        label: impl text::IntoFragment<'a>,
+    ) -> button::Button<'a, Message, iced::Theme, iced::Renderer> {
         button(text(label).align_x(Alignment::Start))
             .padding([4, 8])
             .style(Self::menu_button_style)
             .width(Length::Fill)
     }
 
+    fn jpeg_quality_button(
+        &self,
+        label: &str,
+        quality: u8,
+    ) -> button::Button<'_, Message, iced::Theme, iced::Renderer> {
+        let mark = if self.jpeg_quality == quality {
+            "•"
+        } else {
+            " "
+        };
+        Self::menu_button(format!("{} {} ({})", mark, label, quality))
+            .on_press(Message::SetJpegQuality(quality))
+    }
+
+    fn patch_angle_button(
+        &self,
+        angle: u32,
+    ) -> button::Button<'_, Message, iced::Theme, iced::Renderer> {
+        let mark = if self.patch_angle == angle {
+            "•"
+        } else {
+            " "
+        };
+        Self::menu_button(format!("{} {}°", mark, angle))
+            .on_press(Message::Patch(PatchMessage::SetAngle(angle)))
+    }
+
+    fn check_menu_button<'a>(
+        label: &str,
+        checked: bool,
+    ) -> button::Button<'a, Message, iced::Theme, iced::Renderer> {
+        let mark = if checked { "•" } else { " " };
+        Self::menu_button(format!("{} {}", mark, label))
+    }
+
+    fn radial_button<'a>(
+        symmetry: &Symmetry,
+        radial: u32,
+    ) -> button::Button<'a, Message, iced::Theme, iced::Renderer> {
+        let label = if radial > 1 {
+            format!("{}", radial)
+        } else {
+            "Off".to_string()
+        };
+        Self::check_menu_button(&label, symmetry.radial == radial)
+            .on_press(Message::Symmetry(SymmetryMessage::SetRadial(radial)))
+    }
+
+    fn sampling_button(
+        &self,
+        sampling: Sampling,
+    ) -> button::Button<'_, Message, iced::Theme, iced::Renderer> {
+        let mark = if self.sampling == sampling {
+            "•"
+        } else {
+            " "
+        };
+        Self::menu_button(format!("{} {}", mark, sampling.name()))
+            .on_press(Message::SetSampling(sampling))
+    }
+
+    fn projection_button(
+        &self,
+        projection: ViewProjection,
+    ) -> button::Button<'_, Message, iced::Theme, iced::Renderer> {
+        let is_current = self
+            .canvas_state
+            .read()
+            .map(|state| state.projection == projection)
+            .unwrap_or(false);
+        let mark = if is_current { "•" } else { " " };
+        Self::menu_button(format!("{} {}", mark, projection.name()))
+            .on_press(Message::ChangeProjection(projection))
+    }
+
+    fn close_window() -> Task<Message> {
+        window::get_latest().and_then(window::close)
+    }
+
+    fn save_image(&mut self, path: PathBuf) -> Task<Message> {
+        let path = file::with_default_extension(path);
+        if project::is_project_path(&path) {
+            return match self.project() {
+                Some(project) => {
+                    Task::perform(project::save_project(path, project), Message::FileSaved)
+                }
+                None => Task::none(),
+            };
+        }
+
+        let layers = self
+            .canvas_state
+            .read()
+            .ok()
+            .map(|state| state.layers.clone())
+            .filter(|layers| !layers.layers.is_empty());
+
+        match layers {
+            Some(layers) => Task::perform(
+                file::save_image(path, layers, self.jpeg_quality),
+                Message::FileSaved,
+            ),
+            None => Task::none(),
+        }
+    }
+
+    fn tools(&self) -> [&ToolHandle; 22] {
+        [
+            &self.pan_tool,
+            &self.zoom_tool,
+            &self.pen_tool,
+            &self.eraser_tool,
+            &self.fill_tool,
+            &self.eyedropper_tool,
+            &self.line_tool,
+            &self.circle_tool,
+            &self.rectangle_tool,
+            &self.polygon_tool,
+            &self.gradient_tool,
+            &self.marquee_tool,
+            &self.lasso_tool,
+            &self.magic_wand_tool,
+            &self.clone_tool,
+            &self.healing_tool,
+            &self.smudge_tool,
+            &self.blur_tool,
+            &self.sharpen_tool,
+            &self.dodge_tool,
+            &self.burn_tool,
+            &self.text_tool,
+        ]
+    }
+
+    /**
+     * マウスの操作を渡すツール
+     */
+    fn active_tool(&self) -> &ToolHandle {
+        self.temporary_tool.as_ref().unwrap_or(&self.current_tool)
+    }
+
+    /**
+     * 現在の編集状態をプロジェクトとしてまとめる
+     */
+    fn project(&self) -> Option<project::Project> {
+        let state = self.canvas_state.read().ok()?;
+        if state.layers.layers.is_empty() {
+            return None;
+        }
+        let (undo_steps, redo_steps) = state.history.read().ok()?.snapshot();
+
+        Some(project::Project {
+            layers: state.layers.clone(),
+            view: project::ProjectView {
+                projection: state.projection,
+                aov: state.aov,
+                look_at: state.look_at,
+                up: state.up,
+                right: state.right,
+            },
+            tool: self.current_tool.handle.name().to_string(),
+            tool_settings: self
+                .tools()
+                .iter()
+                .filter_map(|tool| {
+                    let settings = tool.handle.settings()?;
+                    Some((tool.handle.name().to_string(), settings))
+                })
+                .collect(),
+            foreground: state.foreground,
+            background: state.background,
+            symmetry: state.symmetry,
+            undo_steps,
+            redo_steps,
+        })
+    }
+
+    /**
+     * 読み込んだプロジェクトの状態を復元する
+     */
+    fn apply_project(&mut self, project: project::Project) {
+        if let Ok(mut state) = self.canvas_state.write() {
+            state.set_layers(project.layers);
+            state.projection = project.view.projection;
+            state.aov = project
+                .view
+                .aov
+                .clamp(0.01, project.view.projection.max_aov());
+            state.look_at = project.view.look_at;
+            state.up = project.view.up;
+            state.right = project.view.right;
+            if let Ok(mut history) = state.history.write() {
+                history.restore(project.undo_steps, project.redo_steps);
+            }
+        }
+
+        for tool in self.tools() {
+            if let Some(settings) = project.tool_settings.get(tool.handle.name()) {
+                tool.handle.load_settings(settings);
+            }
+        }
+        // 読み込んだグラデーションの区切りの数は以前と違う
+        self.selected_stop = 0;
+        if let Ok(mut state) = self.canvas_state.write() {
+            state.foreground = project.foreground;
+            state.background = project.background;
+            state.symmetry = project.symmetry;
+        }
+        self.hex_input = color::to_hex(self.edited_color());
+
+        let current_tool = self
+            .tools()
+            .into_iter()
+            .find(|tool| tool.handle.name() == project.tool)
+            .cloned();
+        if let Some(tool) = current_tool {
+            self.current_tool = tool;
+        }
+    }
+
     fn separator() -> quad::Quad {
         quad::Quad {
             quad_color: Color::from([0.8; 3]).into(),
@@ -359,6 +2074,746 @@ impl App {
             .on_press(Message::ChangeTool(tool.clone()))
     }
 
+    fn error_banner(&self) -> Element<'_, Message> {
+        let Some(error) = self.error.as_ref() else {
+            return Space::with_height(0).into();
+        };
+
+        container(
+            row![
+                text(error.to_string()).width(Length::Fill),
+                button(text(font::ICON_X).font(font::icon_font()).size(16))
+                    .padding(4)
+                    .style(Self::menu_button_style)
+                    .on_press(Message::DismissError),
+            ]
+            .spacing(10)
+            .align_y(Alignment::Center),
+        )
+        .padding([4, 8])
+        .width(Length::Fill)
+        .style(|_| container::Style {
+            background: Some(Background::Color(Color::from_rgb8(200, 60, 60))),
+            text_color: Some(Color::from_rgb8(255, 255, 255)),
+            ..container::Style::default()
+        })
+        .into()
+    }
+
+    fn loading_indicator(&self) -> Element<'_, Message> {
+        let Some((path, progress)) = self.loading.as_ref() else {
+            return Space::with_width(0).into();
+        };
+        let file_name = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .unwrap_or_default();
+
+        row![
+            text!("Loading {}", file_name),
+            progress_bar(0.0..=1.0, *progress)
+                .width(Length::Fixed(160.0))
+                .height(Length::Fixed(12.0)),
+            Space::with_width(10),
+        ]
+        .spacing(10)
+        .align_y(Alignment::Center)
+        .into()
+    }
+
+    fn layers_panel(&self) -> Element<'_, Message> {
+        let Ok(state) = self.canvas_state.read() else {
+            return column![].into();
+        };
+        let layers = &state.layers;
+
+        // 上のレイヤーから順に表示する
+        let mut list = column![].spacing(2);
+        for (index, layer) in layers.layers.iter().enumerate().rev() {
+            let visible_icon = if layer.visible {
+                font::ICON_EYE
+            } else {
+                font::ICON_EYE_OFF
+            };
+            let lock_icon = if layer.locked {
+                font::ICON_LOCK
+            } else {
+                font::ICON_LOCK_OPEN
+            };
+
+            list = list.push(
+                row![
+                    Self::icon_button(visible_icon)
+                        .on_press(Message::Layer(LayerMessage::ToggleVisible(index))),
+                    Self::icon_button(lock_icon)
+                        .on_press(Message::Layer(LayerMessage::ToggleLocked(index))),
+                    button(text(layer.name.clone()))
+                        .padding([4, 8])
+                        .width(Length::Fill)
+                        .style(Self::layer_button_style(index == layers.active))
+                        .on_press(Message::Layer(LayerMessage::Select(index))),
+                ]
+                .align_y(Alignment::Center),
+            );
+        }
+
+        let mut panel = column![text("Layers")].spacing(5);
+        if let Some(layer) = layers.active_layer() {
+            panel = panel.push(pick_list(
+                &BlendMode::ALL[..],
+                Some(layer.blend_mode),
+                |blend_mode| Message::Layer(LayerMessage::SetBlendMode(blend_mode)),
+            ));
+            panel = panel.push(
+                row![
+                    slider(0.0..=1.0, layer.opacity, |opacity| {
+                        Message::Layer(LayerMessage::SetOpacity(opacity))
+                    })
+                    .step(0.01),
+                    text!("{:.0}%", layer.opacity * 100.0).font(font::mono_font()),
+                ]
+                .spacing(5)
+                .align_y(Alignment::Center),
+            );
+        }
+        panel = panel.push(scrollable(list).height(Length::Fill));
+        panel = panel.push(row![
+            Self::icon_button(font::ICON_PLUS).on_press_maybe(
+                layers
+                    .can_add()
+                    .then_some(Message::Layer(LayerMessage::Add))
+            ),
+            Self::icon_button(font::ICON_TRASH).on_press_maybe(
+                (layers.layers.len() > 1).then_some(Message::Layer(LayerMessage::Remove))
+            ),
+            Self::icon_button(font::ICON_ARROW_UP).on_press(Message::Layer(LayerMessage::MoveUp)),
+            Self::icon_button(font::ICON_ARROW_DOWN)
+                .on_press(Message::Layer(LayerMessage::MoveDown)),
+        ]);
+
+        container(panel)
+            .width(Length::Fixed(220.0))
+            .height(Length::Fill)
+            .padding(5)
+            .into()
+    }
+
+    fn color_panel(&self) -> Element<'_, Message> {
+        let (foreground, background) = match self.canvas_state.read() {
+            Ok(state) => (state.foreground, state.background),
+            Err(_) => return column![].into(),
+        };
+        let color = self.edited_color();
+        let hsv = self.current_hsv();
+
+        let targets = row![
+            Self::swatch_button(
+                foreground,
+                self.color_target == ColorTarget::Foreground,
+                28.0
+            )
+            .on_press(Message::Color(ColorMessage::SelectTarget(
+                ColorTarget::Foreground
+            ))),
+            Self::swatch_button(
+                background,
+                self.color_target == ColorTarget::Background,
+                28.0
+            )
+            .on_press(Message::Color(ColorMessage::SelectTarget(
+                ColorTarget::Background
+            ))),
+            Self::icon_button(font::ICON_ARROWS_EXCHANGE)
+                .on_press(Message::Color(ColorMessage::Swap)),
+        ]
+        .spacing(5)
+        .align_y(Alignment::Center);
+
+        let sliders = column![
+            Self::color_slider("H", 0.0..=360.0, hsv.h, 1.0, ColorMessage::SetHue),
+            Self::color_slider("S", 0.0..=1.0, hsv.s, 0.01, ColorMessage::SetSaturation),
+            Self::color_slider("V", 0.0..=1.0, hsv.v, 0.01, ColorMessage::SetValue),
+            Self::color_slider("R", 0.0..=255.0, color[0] as f32, 1.0, |v| {
+                ColorMessage::SetChannel(0, v)
+            }),
+            Self::color_slider("G", 0.0..=255.0, color[1] as f32, 1.0, |v| {
+                ColorMessage::SetChannel(1, v)
+            }),
+            Self::color_slider("B", 0.0..=255.0, color[2] as f32, 1.0, |v| {
+                ColorMessage::SetChannel(2, v)
+            }),
+            Self::color_slider("A", 0.0..=255.0, color[3] as f32, 1.0, |v| {
+                ColorMessage::SetChannel(3, v)
+            }),
+        ]
+        .spacing(2);
+
+        let hex = text_input("#RRGGBB", &self.hex_input)
+            .on_input(|input| Message::Color(ColorMessage::HexChanged(input)))
+            .font(font::mono_font());
+
+        let recent = Row::with_children(self.recent_colors.iter().map(|color| {
+            Self::swatch_button(*color, false, 16.0)
+                .on_press(Message::Color(ColorMessage::Pick(*color)))
+                .into()
+        }))
+        .spacing(2)
+        .wrap();
+
+        let swatches = Row::with_children(self.palette.swatches.iter().enumerate().map(
+            |(index, swatch)| {
+                Self::swatch_button(swatch.color, self.selected_swatch == Some(index), 16.0)
+                    .on_press(Message::Color(ColorMessage::SelectSwatch(index)))
+                    .into()
+            },
+        ))
+        .spacing(2)
+        .wrap();
+
+        let panel = column![
+            text("Color"),
+            targets,
+            sliders,
+            hex,
+            text("Recent"),
+            recent,
+            text!("Palette: {}", self.palette.name),
+            scrollable(swatches).height(Length::Fill),
+            row![
+                Self::icon_button(font::ICON_PLUS)
+                    .on_press(Message::Color(ColorMessage::AddSwatch)),
+                Self::icon_button(font::ICON_TRASH).on_press_maybe(
+                    self.selected_swatch
+                        .map(|_| Message::Color(ColorMessage::RemoveSwatch))
+                ),
+                Self::icon_button(font::ICON_FOLDER_OPEN)
+                    .on_press(Message::Color(ColorMessage::LoadPalette)),
+                Self::icon_button(font::ICON_DEVICE_FLOPPY)
+                    .on_press(Message::Color(ColorMessage::SavePalette)),
+            ],
+        ]
+        .spacing(5);
+
+        container(panel)
+            .width(Length::Fixed(200.0))
+            .height(Length::Fill)
+            .padding(5)
+            .into()
+    }
+
+    fn gradient_panel(&self) -> Element<'_, Message> {
+        if self.current_tool != self.gradient_tool {
+            return Space::with_width(0).into();
+        }
+        let Ok(settings) = self
+            .gradient
+            .settings
+            .read()
+            .map(|settings| settings.clone())
+        else {
+            return Space::with_width(0).into();
+        };
+
+        // 描画されるグラデーションの見本
+        let stops = settings.gradient.sorted_stops();
+        let preview = Row::with_children((0..GRADIENT_PREVIEW_STEPS).map(|i| {
+            let t = (i as f32 + 0.5) / GRADIENT_PREVIEW_STEPS as f32;
+            let t = if settings.reverse { 1.0 - t } else { t };
+            let color = Gradient::color_at(&stops, t);
+            container(Space::new(Length::Fill, Length::Fixed(16.0)))
+                .width(Length::Fill)
+                .style(move |_| container::Style {
+                    background: Some(Background::Color(color::to_iced(color))),
+                    ..container::Style::default()
+                })
+                .into()
+        }));
+
+        let stop_buttons = Row::with_children(settings.gradient.stops.iter().enumerate().map(
+            |(index, stop)| {
+                Self::swatch_button(Rgba(stop.color), index == self.selected_stop, 16.0)
+                    .on_press(Message::Gradient(GradientMessage::SelectStop(index)))
+                    .into()
+            },
+        ))
+        .spacing(2)
+        .wrap();
+
+        let position = settings
+            .gradient
+            .stops
+            .get(self.selected_stop)
+            .map(|stop| stop.position)
+            .unwrap_or_default();
+        let preset_names: Vec<String> = settings
+            .presets
+            .iter()
+            .map(|preset| preset.name.clone())
+            .collect();
+
+        let panel = column![
+            text("Gradient"),
+            pick_list(&GradientMode::ALL[..], Some(settings.mode), |mode| {
+                Message::Gradient(GradientMessage::SetMode(mode))
+            }),
+            preview,
+            stop_buttons,
+            row![
+                slider(0.0..=1.0, position, |position| {
+                    Message::Gradient(GradientMessage::SetStopPosition(position))
+                })
+                .step(0.01),
+                text!("{:.2}", position)
+                    .font(font::mono_font())
+                    .width(Length::Fixed(40.0)),
+            ]
+            .spacing(5)
+            .align_y(Alignment::Center),
+            row![
+                Self::icon_button(font::ICON_PLUS)
+                    .on_press(Message::Gradient(GradientMessage::AddStop)),
+                Self::icon_button(font::ICON_TRASH).on_press_maybe(
+                    (settings.gradient.stops.len() > 2)
+                        .then_some(Message::Gradient(GradientMessage::RemoveStop))
+                ),
+                Self::icon_button(font::ICON_DROPLET)
+                    .on_press(Message::Gradient(GradientMessage::SetStopColor)),
+            ],
+            checkbox("Reverse", settings.reverse)
+                .on_toggle(|reverse| Message::Gradient(GradientMessage::ToggleReverse(reverse))),
+            row![
+                text("Opacity"),
+                slider(0.0..=1.0, settings.opacity, |opacity| {
+                    Message::Gradient(GradientMessage::SetOpacity(opacity))
+                })
+                .step(0.01),
+            ]
+            .spacing(5)
+            .align_y(Alignment::Center),
+            text("Presets"),
+            row![
+                pick_list(preset_names, None::<String>, |name| {
+                    Message::Gradient(GradientMessage::LoadPreset(name))
+                })
+                .placeholder("Load")
+                .width(Length::Fill),
+                Self::icon_button(font::ICON_DEVICE_FLOPPY)
+                    .on_press(Message::Gradient(GradientMessage::SavePreset)),
+            ]
+            .spacing(5)
+            .align_y(Alignment::Center),
+        ]
+        .spacing(5);
+
+        container(panel)
+            .width(Length::Fixed(200.0))
+            .height(Length::Fill)
+            .padding(5)
+            .into()
+    }
+
+    /**
+     * 選んでいるツールのオプション (ツールに応じた項目だけを並べる)
+     */
+    fn tool_options_panel(&self) -> Element<'_, Message> {
+        let sections: Vec<Element<'_, Message>> = [
+            self.brush_options(),
+            self.fill_options(),
+            self.shape_options(),
+            self.select_options(),
+            self.clone_options(),
+            self.retouch_options(),
+            self.eyedropper_options(),
+        ]
+        .into_iter()
+        .flatten()
+        .collect();
+        if sections.is_empty() {
+            return Space::with_width(0).into();
+        }
+
+        container(Column::with_children(sections).spacing(10))
+            .width(Length::Fixed(200.0))
+            .height(Length::Fill)
+            .padding(5)
+            .into()
+    }
+
+    fn brush_options(&self) -> Option<Element<'_, Message>> {
+        let brush = self.current_tool.handle.brush()?;
+
+        Some(
+            column![
+                text("Brush"),
+                Self::option_slider("Size", 1.0..=500.0, brush.width, 1.0, |width| {
+                    Message::Brush(BrushMessage::SetWidth(width))
+                }),
+                Self::option_slider("Hardness", 0.0..=1.0, brush.hardness, 0.01, |hardness| {
+                    Message::Brush(BrushMessage::SetHardness(hardness))
+                }),
+                Self::option_slider("Spacing", 0.01..=1.0, brush.spacing, 0.01, |spacing| {
+                    Message::Brush(BrushMessage::SetSpacing(spacing))
+                }),
+                Self::option_slider("Opacity", 0.0..=1.0, brush.opacity, 0.01, |opacity| {
+                    Message::Brush(BrushMessage::SetOpacity(opacity))
+                }),
+                Self::option_slider("Flow", 0.01..=1.0, brush.flow, 0.01, |flow| {
+                    Message::Brush(BrushMessage::SetFlow(flow))
+                }),
+                checkbox("Anti-alias", brush.antialias).on_toggle(|antialias| {
+                    Message::Brush(BrushMessage::ToggleAntialias(antialias))
+                }),
+            ]
+            .spacing(5)
+            .into(),
+        )
+    }
+
+    fn fill_options(&self) -> Option<Element<'_, Message>> {
+        if self.current_tool != self.fill_tool {
+            return None;
+        }
+        let settings = self.fill.settings.read().ok().map(|settings| *settings)?;
+
+        Some(
+            column![
+                text("Fill"),
+                Self::option_slider("Tolerance", 0.0..=1.0, settings.tolerance, 0.01, |value| {
+                    Message::Fill(FillMessage::SetTolerance(value))
+                }),
+                Self::option_slider("Opacity", 0.0..=1.0, settings.opacity, 0.01, |value| {
+                    Message::Fill(FillMessage::SetOpacity(value))
+                }),
+                checkbox("Contiguous", settings.contiguous)
+                    .on_toggle(|value| Message::Fill(FillMessage::ToggleContiguous(value))),
+                checkbox("Anti-alias", settings.antialias)
+                    .on_toggle(|value| Message::Fill(FillMessage::ToggleAntialias(value))),
+            ]
+            .spacing(5)
+            .into(),
+        )
+    }
+
+    /**
+     * 選んでいる図形ツール (図形以外なら None)
+     */
+    fn current_shape(&self) -> Option<&Arc<ShapeTool>> {
+        [
+            (&self.circle_tool, &self.circle),
+            (&self.rectangle_tool, &self.rectangle),
+            (&self.polygon_tool, &self.polygon),
+        ]
+        .into_iter()
+        .find(|(tool, _)| **tool == self.current_tool)
+        .map(|(_, shape)| shape)
+    }
+
+    fn shape_options(&self) -> Option<Element<'_, Message>> {
+        let settings = self
+            .current_shape()?
+            .settings
+            .read()
+            .ok()
+            .map(|settings| *settings)?;
+
+        Some(
+            column![
+                text("Shape"),
+                checkbox("Stroke", settings.stroke)
+                    .on_toggle(|value| Message::Shape(ShapeMessage::ToggleStroke(value))),
+                checkbox("Fill", settings.fill)
+                    .on_toggle(|value| Message::Shape(ShapeMessage::ToggleFill(value))),
+            ]
+            .spacing(5)
+            .into(),
+        )
+    }
+
+    /**
+     * 選んでいる選択ツール (選択以外なら None)
+     */
+    fn current_select(&self) -> Option<&Arc<SelectTool>> {
+        [
+            (&self.marquee_tool, &self.marquee),
+            (&self.lasso_tool, &self.lasso),
+            (&self.magic_wand_tool, &self.magic_wand),
+        ]
+        .into_iter()
+        .find(|(tool, _)| **tool == self.current_tool)
+        .map(|(_, select)| select)
+    }
+
+    fn select_options(&self) -> Option<Element<'_, Message>> {
+        let select = self.current_select()?;
+        let settings = select.settings.read().ok().map(|settings| *settings)?;
+
+        let mut section = column![
+            text("Selection"),
+            pick_list(&SelectionMode::ALL[..], Some(settings.mode), |mode| {
+                Message::Select(SelectMessage::SetMode(mode))
+            })
+            .width(Length::Fill),
+            checkbox("Anti-alias", settings.antialias)
+                .on_toggle(|value| Message::Select(SelectMessage::ToggleAntialias(value))),
+        ]
+        .spacing(5);
+        // 色の許容範囲と連続は自動選択だけで使う
+        if select.kind == SelectKind::MagicWand {
+            section = section
+                .push(Self::option_slider(
+                    "Tolerance",
+                    0.0..=1.0,
+                    settings.tolerance,
+                    0.01,
+                    |value| Message::Select(SelectMessage::SetTolerance(value)),
+                ))
+                .push(
+                    checkbox("Contiguous", settings.contiguous)
+                        .on_toggle(|value| Message::Select(SelectMessage::ToggleContiguous(value))),
+                );
+        }
+
+        Some(section.into())
+    }
+
+    /**
+     * 選んでいるコピースタンプ・修復ブラシ (それ以外なら None)
+     */
+    fn current_clone(&self) -> Option<&Arc<CloneTool>> {
+        [
+            (&self.clone_tool, &self.clone),
+            (&self.healing_tool, &self.healing),
+        ]
+        .into_iter()
+        .find(|(tool, _)| **tool == self.current_tool)
+        .map(|(_, clone)| clone)
+    }
+
+    fn clone_options(&self) -> Option<Element<'_, Message>> {
+        let settings = self
+            .current_clone()?
+            .settings
+            .read()
+            .ok()
+            .map(|settings| *settings)?;
+
+        Some(
+            column![
+                text("Source"),
+                checkbox("Aligned", settings.aligned)
+                    .on_toggle(|value| Message::Clone(CloneMessage::ToggleAligned(value))),
+            ]
+            .spacing(5)
+            .into(),
+        )
+    }
+
+    /**
+     * 選んでいる修正ツール (それ以外なら None)
+     */
+    fn current_retouch(&self) -> Option<&Arc<RetouchTool>> {
+        [
+            (&self.smudge_tool, &self.smudge),
+            (&self.blur_tool, &self.blur),
+            (&self.sharpen_tool, &self.sharpen),
+            (&self.dodge_tool, &self.dodge),
+            (&self.burn_tool, &self.burn),
+        ]
+        .into_iter()
+        .find(|(tool, _)| **tool == self.current_tool)
+        .map(|(_, retouch)| retouch)
+    }
+
+    fn retouch_options(&self) -> Option<Element<'_, Message>> {
+        let retouch = self.current_retouch()?;
+        let settings = retouch.settings.read().ok().map(|settings| *settings)?;
+
+        let mut section = column![
+            text(retouch.name.as_str()),
+            Self::option_slider("Strength", 0.0..=1.0, settings.strength, 0.01, |value| {
+                Message::Retouch(RetouchMessage::SetStrength(value))
+            }),
+        ]
+        .spacing(5);
+        // 混ぜる範囲はぼかし・シャープだけで使う
+        if matches!(retouch.kind, RetouchKind::Blur | RetouchKind::Sharpen) {
+            section = section.push(Self::option_slider(
+                "Kernel",
+                0.5..=20.0,
+                settings.kernel,
+                0.5,
+                |value| Message::Retouch(RetouchMessage::SetKernel(value)),
+            ));
+        }
+
+        Some(section.into())
+    }
+
+    fn eyedropper_options(&self) -> Option<Element<'_, Message>> {
+        if self.current_tool != self.eyedropper_tool {
+            return None;
+        }
+        let settings = self
+            .eyedropper
+            .settings
+            .read()
+            .ok()
+            .map(|settings| *settings)?;
+
+        Some(
+            column![
+                text("Eyedropper"),
+                pick_list(&SampleSize::ALL[..], Some(settings.sample_size), |size| {
+                    Message::Eyedropper(EyedropperMessage::SetSampleSize(size))
+                })
+                .width(Length::Fill),
+                checkbox("Sample All Layers", settings.sample_merged).on_toggle(|merged| {
+                    Message::Eyedropper(EyedropperMessage::ToggleSampleMerged(merged))
+                }),
+            ]
+            .spacing(5)
+            .into(),
+        )
+    }
+
+    fn option_slider<'a>(
+        label: &'a str,
+        range: std::ops::RangeInclusive<f32>,
+        value: f32,
+        step: f32,
+        on_change: impl Fn(f32) -> Message + 'a,
+    ) -> Element<'a, Message> {
+        let precision = if step < 1.0 { 2 } else { 0 };
+        row![
+            text(label).width(Length::Fixed(60.0)),
+            slider(range, value, on_change).step(step),
+            text!("{:.*}", precision, value)
+                .font(font::mono_font())
+                .width(Length::Fixed(40.0)),
+        ]
+        .spacing(5)
+        .align_y(Alignment::Center)
+        .into()
+    }
+
+    fn text_panel(&self) -> Element<'_, Message> {
+        if self.current_tool != self.text_tool {
+            return Space::with_width(0).into();
+        }
+        let Ok(settings) = self.text.settings.read().map(|settings| settings.clone()) else {
+            return Space::with_width(0).into();
+        };
+        let placed = self.text.is_placed();
+
+        let panel = column![
+            text("Text"),
+            text_input("Click the view to place", &settings.text)
+                .on_input(|text| Message::Text(TextMessage::SetText(text)))
+                .on_submit(Message::Text(TextMessage::Commit)),
+            pick_list(
+                self.font_families.as_slice(),
+                Some(settings.family.clone()),
+                |family| Message::Text(TextMessage::SetFamily(family)),
+            )
+            .width(Length::Fill),
+            row![
+                text("Size"),
+                slider(8.0..=512.0, settings.size, |size| {
+                    Message::Text(TextMessage::SetSize(size))
+                })
+                .step(1.0),
+                text!("{:.0}", settings.size)
+                    .font(font::mono_font())
+                    .width(Length::Fixed(40.0)),
+            ]
+            .spacing(5)
+            .align_y(Alignment::Center),
+            pick_list(
+                &TextOrientation::ALL[..],
+                Some(settings.orientation),
+                |orientation| Message::Text(TextMessage::SetOrientation(orientation)),
+            ),
+            row![
+                button("Apply")
+                    .on_press_maybe(placed.then_some(Message::Text(TextMessage::Commit))),
+                button("Cancel")
+                    .on_press_maybe(placed.then_some(Message::Text(TextMessage::Cancel))),
+            ]
+            .spacing(5),
+        ]
+        .spacing(5);
+
+        container(panel)
+            .width(Length::Fixed(200.0))
+            .height(Length::Fill)
+            .padding(5)
+            .into()
+    }
+
+    fn color_slider<'a>(
+        label: &'a str,
+        range: std::ops::RangeInclusive<f32>,
+        value: f32,
+        step: f32,
+        on_change: impl Fn(f32) -> ColorMessage + 'a,
+    ) -> Element<'a, Message> {
+        let precision = if step < 1.0 { 2 } else { 0 };
+        row![
+            text(label)
+                .font(font::mono_font())
+                .width(Length::Fixed(14.0)),
+            slider(range, value, move |value| Message::Color(on_change(value))).step(step),
+            text!("{:.*}", precision, value)
+                .font(font::mono_font())
+                .width(Length::Fixed(40.0)),
+        ]
+        .spacing(5)
+        .align_y(Alignment::Center)
+        .into()
+    }
+
+    fn swatch_button<'a>(
+        color: Rgba<u8>,
+        selected: bool,
+        size: f32,
+    ) -> button::Button<'a, Message, iced::Theme, iced::Renderer> {
+        button(Space::new(Length::Fixed(size), Length::Fixed(size)))
+            .padding(0)
+            .style(move |_, status| button::Style {
+                background: Some(Background::Color(color::to_iced(color))),
+                border: Border {
+                    color: if selected || status == button::Status::Hovered {
+                        Color::from_rgb8(60, 60, 200)
+                    } else {
+                        Color::from_rgb8(160, 160, 160)
+                    },
+                    width: if selected { 2.0 } else { 1.0 },
+                    radius: Radius::new(2.0),
+                },
+                ..button::Style::default()
+            })
+    }
+
+    fn icon_button<'a>(icon: char) -> button::Button<'a, Message, iced::Theme, iced::Renderer> {
+        button(text(icon).font(font::icon_font()).size(16))
+            .padding(4)
+            .style(Self::menu_button_style)
+    }
+
+    fn layer_button_style(is_active: bool) -> impl Fn(&Theme, button::Status) -> button::Style {
+        move |theme, status| {
+            if is_active {
+                button::Style {
+                    background: Some(Background::Color(Color::from_rgb8(60, 60, 200))),
+                    text_color: Color::from_rgb8(255, 255, 255),
+                    ..button::Style::default()
+                }
+            } else {
+                Self::menu_button_style(theme, status)
+            }
+        }
+    }
+
     fn rad2degree(rad: f32) -> f32 {
         rad * (180.0 / PI)
     }
@@ -388,3 +2843,80 @@ async fn open_file() -> Result<PathBuf, Error> {
 
     Ok(picked_file.into())
 }
+
+async fn open_place_image() -> Result<image::RgbaImage, Error> {
+    let mut dialog = rfd::AsyncFileDialog::new();
+    for (name, extensions) in file::PLACE_IMAGE_FILTERS {
+        dialog = dialog.add_filter(*name, *extensions);
+    }
+    let picked_file = dialog.pick_file().await.ok_or(Error::DialogClosed)?;
+
+    file::load_place_image(picked_file.into()).await
+}
+
+async fn save_patch_as(
+    pole: Pole,
+    image: image::RgbaImage,
+    jpeg_quality: u8,
+) -> Result<PathBuf, Error> {
+    let mut dialog =
+        rfd::AsyncFileDialog::new().set_file_name(format!("{}.png", pole.name().to_lowercase()));
+    for (name, extensions) in file::PATCH_FILTERS {
+        dialog = dialog.add_filter(*name, *extensions);
+    }
+    let picked_file = dialog.save_file().await.ok_or(Error::DialogClosed)?;
+
+    file::save_single_image(
+        file::with_default_extension(picked_file.into()),
+        image,
+        jpeg_quality,
+    )
+    .await
+}
+
+async fn pick_save_file(current_path: PathBuf) -> Result<PathBuf, Error> {
+    let mut dialog = rfd::AsyncFileDialog::new();
+    for (name, extensions) in file::SAVE_FILTERS {
+        dialog = dialog.add_filter(*name, *extensions);
+    }
+    if let Some(directory) = current_path.parent() {
+        dialog = dialog.set_directory(directory);
+    }
+    if let Some(file_name) = current_path.file_name().and_then(|name| name.to_str()) {
+        dialog = dialog.set_file_name(file_name);
+    }
+
+    let picked_file = dialog.save_file().await.ok_or(Error::DialogClosed)?;
+
+    Ok(picked_file.into())
+}
+
+async fn open_palette() -> Result<Palette, Error> {
+    let mut dialog = rfd::AsyncFileDialog::new();
+    for (name, extensions) in palette::PALETTE_FILTERS {
+        dialog = dialog.add_filter(*name, *extensions);
+    }
+    let picked_file = dialog.pick_file().await.ok_or(Error::DialogClosed)?;
+
+    palette::load_palette(picked_file.into()).await
+}
+
+async fn save_palette_as(palette: Palette) -> Result<PathBuf, Error> {
+    let mut dialog = rfd::AsyncFileDialog::new().set_file_name(format!("{}.gpl", palette.name));
+    for (name, extensions) in palette::PALETTE_FILTERS {
+        dialog = dialog.add_filter(*name, *extensions);
+    }
+    let picked_file = dialog.save_file().await.ok_or(Error::DialogClosed)?;
+
+    palette::save_palette(picked_file.into(), palette).await
+}
+
+async fn confirm_discard_changes() -> MessageDialogResult {
+    rfd::AsyncMessageDialog::new()
+        .set_level(MessageLevel::Warning)
+        .set_title("Pixrium")
+        .set_description("The image has unsaved changes. Save before exiting?")
+        .set_buttons(MessageButtons::YesNoCancel)
+        .show()
+        .await
+}
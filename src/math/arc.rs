@@ -0,0 +1,39 @@
+use std::f32::consts::{PI, TAU};
+
+use glam::Vec3;
+
+use crate::math::projection::SphereProjection;
+
+/**
+ * from から to までの大円 (最短の弧) 上に step (ラジアン) 以下の間隔で並べた点を返す
+ * 両端の点を含む
+ */
+pub fn great_circle(from: Vec3, to: Vec3, step: f32) -> Vec<Vec3> {
+    let angle = from.angle_between(to);
+    if angle.is_nan() || angle <= 0.0 {
+        return vec![from];
+    }
+
+    let count = (angle / step.max(1e-4)).ceil().max(1.0) as usize;
+    (0..=count)
+        .map(|i| from.slerp(to, i as f32 / count as f32).normalize())
+        .collect()
+}
+
+/**
+ * 緯度 lat の緯線上で、経度 from_lon から to_lon まで step (ラジアン) 以下の間隔で並べた点を返す
+ * 経度は近い方へ回り、両端の点を含む
+ */
+pub fn parallel(lat: f32, from_lon: f32, to_lon: f32, step: f32) -> Vec<Vec3> {
+    let d_lon = (to_lon - from_lon + PI).rem_euclid(TAU) - PI;
+    // 緯線の長さは cos(緯度) に比例する
+    let length = (d_lon * lat.cos()).abs();
+
+    let count = (length / step.max(1e-4)).ceil().max(1.0) as usize;
+    (0..=count)
+        .map(|i| {
+            let lon = from_lon + d_lon * i as f32 / count as f32;
+            SphereProjection::latlng_to_direction(lon, lat)
+        })
+        .collect()
+}
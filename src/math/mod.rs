@@ -1 +1,6 @@
+pub mod arc;
 pub mod projection;
+pub mod region;
+pub mod sample;
+pub mod shape;
+pub mod symmetry;
@@ -1,18 +1,97 @@
-use std::f32::consts::PI;
+use std::f32::consts::{FRAC_PI_2, PI, TAU};
 
-use glam::{Mat3, Vec2, Vec3, vec2, vec3};
+use glam::{Vec2, Vec3, vec2, vec3};
+use serde::{Deserialize, Serialize};
 
+const EPSILON: f32 = 1e-6;
+
+/// 球面をviewに描画する際の射影方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum ViewProjection {
+    /// 透視投影 (gnomonic)
+    #[default]
+    Rectilinear,
+    /// 等角投影 (little planet)
+    Stereographic,
+    /// 等立体角投影の魚眼
+    Fisheye,
+    /// Pannini投影 (d = 1)
+    Pannini,
+    /// 正距円筒図法 (画像そのもの)
+    Equirectangular,
+}
+
+impl ViewProjection {
+    pub const ALL: [ViewProjection; 5] = [
+        ViewProjection::Rectilinear,
+        ViewProjection::Stereographic,
+        ViewProjection::Fisheye,
+        ViewProjection::Pannini,
+        ViewProjection::Equirectangular,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ViewProjection::Rectilinear => "Rectilinear",
+            ViewProjection::Stereographic => "Stereographic",
+            ViewProjection::Fisheye => "Fisheye",
+            ViewProjection::Pannini => "Pannini",
+            ViewProjection::Equirectangular => "Equirectangular",
+        }
+    }
+
+    /**
+     * 射影が破綻しない最大の水平画角
+     */
+    pub fn max_aov(&self) -> f32 {
+        match self {
+            ViewProjection::Rectilinear => 170f32.to_radians(),
+            ViewProjection::Stereographic => 340f32.to_radians(),
+            ViewProjection::Fisheye => TAU,
+            ViewProjection::Pannini => 320f32.to_radians(),
+            ViewProjection::Equirectangular => TAU,
+        }
+    }
+
+    /**
+     * シェーダーに渡す射影方式の番号 (sphere.wgsl と一致させること)
+     */
+    pub fn shader_index(&self) -> u32 {
+        match self {
+            ViewProjection::Rectilinear => 0,
+            ViewProjection::Stereographic => 1,
+            ViewProjection::Fisheye => 2,
+            ViewProjection::Pannini => 3,
+            ViewProjection::Equirectangular => 4,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub struct SphereProjection {
+    pub kind: ViewProjection,
+    /// 水平画角
     pub aov: f32,
+    /// viewの縦横比 (高さ / 幅)
+    pub aspect: f32,
     pub look_at: Vec3,
     pub up: Vec3,
     pub right: Vec3,
 }
 
 impl SphereProjection {
-    pub fn new(aov: f32, look_at: Vec3, up: Vec3, right: Vec3) -> Self {
+    pub fn new(
+        kind: ViewProjection,
+        aov: f32,
+        aspect: f32,
+        look_at: Vec3,
+        up: Vec3,
+        right: Vec3,
+    ) -> Self {
         Self {
+            kind,
             aov,
+            aspect,
             look_at,
             up,
             right,
@@ -20,22 +99,130 @@ impl SphereProjection {
     }
 
     /**
-     * view座標 (-1.0 ... 1.0) をテクスチャ座標 (-1.0 ... 1.0) に射影する
+     * view座標 (0.0 ... 1.0) をテクスチャ座標 (0.0 ... 1.0) に射影する
+     * viewの点が球面に対応しない場合 (魚眼の円の外など) は None
      */
-    pub fn proj(&self, view_x: f32, view_y: f32) -> Vec2 {
-        // Calculate yaw and pitch
-        let yaw = self.aov * (view_x - 0.5);
-        let pitch = self.aov * (view_y - 0.5);
+    pub fn proj(&self, view_x: f32, view_y: f32) -> Option<Vec2> {
+        self.view_to_direction(view_x, view_y)
+            .map(Self::direction_to_tex)
+    }
 
-        // Calculate sphere coordinates
-        let sphere_coord = (self.look_at + yaw * self.right + pitch * self.up).normalize();
+    /**
+     * テクスチャ座標 (0.0 ... 1.0) を view座標 (0.0 ... 1.0) に逆射影する
+     * 球面上の点がviewに写らない場合 (透視投影で視点の後ろなど) は None
+     */
+    pub fn unproj(&self, tex_u: f32, tex_v: f32) -> Option<Vec2> {
+        self.direction_to_view(Self::tex_to_direction(tex_u, tex_v))
+    }
+
+    /**
+     * view座標 (0.0 ... 1.0) を球面上の方向(単位ベクトル)に変換する
+     */
+    pub fn view_to_direction(&self, view_x: f32, view_y: f32) -> Option<Vec3> {
+        // 画角の端が ±1.0 になる投影面上の座標
+        let plane = vec2((view_x - 0.5) * 2.0, (view_y - 0.5) * 2.0 * self.aspect);
+        let half_aov = self.aov * 0.5;
 
-        // Calculate spherical coordinates
+        match self.kind {
+            ViewProjection::Rectilinear => {
+                let p = plane * half_aov.tan();
+                Some(self.local_to_world(vec3(p.x, p.y, 1.0)).normalize())
+            }
+            ViewProjection::Stereographic => {
+                // r = 2 tan(θ / 2)
+                let p = plane * 2.0 * (half_aov * 0.5).tan();
+                let theta = 2.0 * (p.length() * 0.5).atan();
+                Some(self.polar_to_world(p, theta))
+            }
+            ViewProjection::Fisheye => {
+                // r = 2 sin(θ / 2)
+                let p = plane * 2.0 * (half_aov * 0.5).sin();
+                let rho = p.length();
+                if rho > 2.0 {
+                    return None;
+                }
+                let theta = 2.0 * (rho * 0.5).asin();
+                Some(self.polar_to_world(p, theta))
+            }
+            ViewProjection::Pannini => {
+                // x = S sin(φ), y = S tan(ψ), S = 2 / (1 + cos(φ))
+                let p = plane * 2.0 * (half_aov * 0.5).tan();
+                let phi = 2.0 * (p.x * 0.5).atan();
+                let s = 2.0 / (1.0 + phi.cos());
+                let local = vec3(phi.sin(), p.y / s, phi.cos());
+                Some(self.local_to_world(local).normalize())
+            }
+            ViewProjection::Equirectangular => {
+                let (lon0, lat0) = Self::direction_to_latlng(self.look_at);
+                let lon = lon0 + plane.x * half_aov;
+                let lat = lat0 + plane.y * half_aov;
+                if lat.abs() > FRAC_PI_2 {
+                    return None;
+                }
+                Some(Self::latlng_to_direction(lon, lat))
+            }
+        }
+    }
+
+    /**
+     * 球面上の方向(単位ベクトル)を view座標 (0.0 ... 1.0) に変換する
+     */
+    pub fn direction_to_view(&self, direction: Vec3) -> Option<Vec2> {
+        let x = direction.dot(self.right);
+        let y = direction.dot(self.up);
+        let z = direction.dot(self.look_at);
+        let half_aov = self.aov * 0.5;
+
+        let plane = match self.kind {
+            ViewProjection::Rectilinear => {
+                if z <= EPSILON {
+                    return None;
+                }
+                vec2(x / z, y / z) / half_aov.tan()
+            }
+            ViewProjection::Stereographic => {
+                let theta = z.clamp(-1.0, 1.0).acos();
+                if theta >= PI - EPSILON {
+                    return None;
+                }
+                let rho = 2.0 * (theta * 0.5).tan();
+                Self::polar_direction(x, y) * rho / (2.0 * (half_aov * 0.5).tan())
+            }
+            ViewProjection::Fisheye => {
+                let theta = z.clamp(-1.0, 1.0).acos();
+                let rho = 2.0 * (theta * 0.5).sin();
+                Self::polar_direction(x, y) * rho / (2.0 * (half_aov * 0.5).sin())
+            }
+            ViewProjection::Pannini => {
+                let phi = x.atan2(z);
+                let horizontal = (x * x + z * z).sqrt();
+                if phi.abs() >= PI - EPSILON || horizontal <= EPSILON {
+                    return None;
+                }
+                let s = 2.0 / (1.0 + phi.cos());
+                vec2(s * phi.sin(), s * y / horizontal) / (2.0 * (half_aov * 0.5).tan())
+            }
+            ViewProjection::Equirectangular => {
+                let (lon0, lat0) = Self::direction_to_latlng(self.look_at);
+                let (lon, lat) = Self::direction_to_latlng(direction);
+                // 経度差は -π ... π に正規化する
+                let d_lon = (lon - lon0 + PI).rem_euclid(TAU) - PI;
+                vec2(d_lon, lat - lat0) / half_aov
+            }
+        };
+
+        Some(vec2(plane.x * 0.5 + 0.5, plane.y * 0.5 / self.aspect + 0.5))
+    }
+
+    /**
+     * 球面上の方向をテクスチャ座標 (0.0 ... 1.0) に変換する
+     */
+    pub fn direction_to_tex(direction: Vec3) -> Vec2 {
         // XZ平面への射影角度(phi)とY軸からの偏角(theta)
-        let phi: f32 = sphere_coord.z.atan2(sphere_coord.x);
-        let theta = sphere_coord
+        let phi: f32 = direction.z.atan2(direction.x);
+        let theta = direction
             .y
-            .atan2((sphere_coord.x.powi(2) + sphere_coord.z.powi(2)).sqrt());
+            .atan2((direction.x.powi(2) + direction.z.powi(2)).sqrt());
 
         // Convert to texture UV
         let tex_u = phi / (2.0 * PI) + 0.5;
@@ -44,69 +231,55 @@ impl SphereProjection {
     }
 
     /**
-     * view座標 (-1.0 ... 1.0) をテクスチャ座標 (-1.0 ... 1.0) に逆射影する
-     * (線形化射影を用いる場合)
+     * テクスチャ座標 (0.0 ... 1.0) を球面上の方向に変換する
      */
-    // pub fn unproj(&self, tex_u: f32, tex_v: f32) -> Vec2 {
-    //     // Convert texture UV back to spherical angles
-    //     let phi = (tex_u - 0.5) * 2.0 * PI; // azimuth
-    //     let theta = (tex_v - 0.5) * PI; // elevation
+    pub fn tex_to_direction(tex_u: f32, tex_v: f32) -> Vec3 {
+        let phi = (tex_u - 0.5) * 2.0 * PI; // azimuth
+        let theta = (0.5 - tex_v) * PI; // elevation (note the sign to invert tex_v = 0.5 - theta/PI)
 
-    //     // Reconstruct direction on the unit sphere from spherical angles
-    //     let sphere_coord = vec3(
-    //         theta.cos() * phi.cos(),
-    //         theta.sin(),
-    //         theta.cos() * phi.sin(),
-    //     );
+        Self::latlng_to_direction(phi, theta)
+    }
 
-    //     // Project onto the local right/up axes to recover yaw and pitch (linearized)
-    //     let yaw = (sphere_coord - self.look_at).dot(self.right);
-    //     let pitch = (sphere_coord - self.look_at).dot(self.up);
+    /**
+     * 方向を経度(XZ平面での角度), 緯度 (ラジアン) に変換する
+     */
+    pub fn direction_to_latlng(direction: Vec3) -> (f32, f32) {
+        let lon = direction.z.atan2(direction.x);
+        let lat = direction
+            .y
+            .atan2((direction.x.powi(2) + direction.z.powi(2)).sqrt());
+        (lon, lat)
+    }
 
-    //     // Convert back to view coordinates
-    //     let view_x = yaw / self.aov + 0.5;
-    //     let view_y = pitch / self.aov + 0.5;
+    pub fn latlng_to_direction(lon: f32, lat: f32) -> Vec3 {
+        vec3(lat.cos() * lon.cos(), lat.sin(), lat.cos() * lon.sin())
+    }
 
-    //     vec2(view_x, view_y)
-    // }
+    /**
+     * 視点座標系 (right, up, look_at) のベクトルをワールド座標に変換する
+     */
+    fn local_to_world(&self, local: Vec3) -> Vec3 {
+        local.x * self.right + local.y * self.up + local.z * self.look_at
+    }
 
     /**
-     * テクスチャ座標 (-1.0 ... 1.0) を view座標 (-1.0 ... 1.0) に逆射影する
-     * (線形化射影ではなく厳密解を求める)
+     * 投影面上の向き p と視点からの角度 theta から方向を求める
      */
-    pub fn unproj(&self, tex_u: f32, tex_v: f32) -> Vec2 {
-        // Convert texture UV back to spherical angles (inverse of proj)
-        let phi = (tex_u - 0.5) * 2.0 * PI; // azimuth
-        let theta = (0.5 - tex_v) * PI; // elevation (note the sign to invert tex_v = 0.5 - theta/PI)
+    fn polar_to_world(&self, p: Vec2, theta: f32) -> Vec3 {
+        let rho = p.length();
+        if rho <= EPSILON {
+            return self.look_at;
+        }
+        let d = p / rho * theta.sin();
+        self.local_to_world(vec3(d.x, d.y, theta.cos())).normalize()
+    }
 
-        // Reconstruct direction on the unit sphere from spherical angles
-        let sphere_dir = vec3(
-            theta.cos() * phi.cos(),
-            theta.sin(),
-            theta.cos() * phi.sin(),
-        );
-
-        // Solve for yaw, pitch and scale s:
-        // look_at + yaw * right + pitch * up = s * sphere_dir
-        // => [ right | up | -sphere_dir ] * [yaw, pitch, s]^T = -look_at
-        let mat = Mat3::from_cols(self.right, self.up, -sphere_dir);
-        let b = -self.look_at;
-
-        let eps = 1e-6;
-        if mat.determinant().abs() > eps {
-            let sol = mat.inverse() * b;
-            let yaw = sol.x;
-            let pitch = sol.y;
-            let view_x = yaw / self.aov + 0.5;
-            let view_y = pitch / self.aov + 0.5;
-            vec2(view_x, view_y)
+    fn polar_direction(x: f32, y: f32) -> Vec2 {
+        let length = (x * x + y * y).sqrt();
+        if length <= EPSILON {
+            Vec2::ZERO
         } else {
-            // Fallback to linearized projection (approximate)
-            let yaw = (sphere_dir - self.look_at).dot(self.right);
-            let pitch = (sphere_dir - self.look_at).dot(self.up);
-            let view_x = yaw / self.aov + 0.5;
-            let view_y = pitch / self.aov + 0.5;
-            vec2(view_x, view_y)
+            vec2(x, y) / length
         }
     }
 }
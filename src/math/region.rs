@@ -0,0 +1,177 @@
+use iced::Rectangle;
+
+/// 矩形の数がこれを超えたら全体を囲む1つの矩形にまとめる
+const MAX_RECTS: usize = 32;
+
+/// テクスチャ(equirectangular画像)のピクセル座標での更新範囲の集合
+///
+/// 左右端(経度±180°)をまたぐ範囲は2つの矩形に分割して保持する。
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DirtyRegion {
+    width: u32,
+    height: u32,
+    rects: Vec<Rectangle<u32>>,
+}
+
+impl DirtyRegion {
+    pub fn new(width: u32, height: u32) -> Self {
+        Self {
+            width,
+            height,
+            rects: Vec::new(),
+        }
+    }
+
+    /**
+     * 画像の大きさを変更する (範囲はクリアされる)
+     */
+    pub fn resize(&mut self, width: u32, height: u32) {
+        self.width = width;
+        self.height = height;
+        self.rects.clear();
+    }
+
+    /**
+     * ピクセル座標の範囲を追加する
+     * x は画像の幅を超えたり負になっても良く、左右端で折り返す
+     * y は画像の範囲に切り詰める
+     */
+    pub fn add(&mut self, area: Rectangle<i32>) {
+        if self.width == 0 || self.height == 0 || area.width <= 0 || area.height <= 0 {
+            return;
+        }
+
+        let y0 = area.y.max(0) as u32;
+        let y1 = (area.y + area.height).min(self.height as i32);
+        if y1 <= y0 as i32 {
+            return;
+        }
+        let height = y1 as u32 - y0;
+
+        if area.width as u32 >= self.width {
+            self.add_rect(Rectangle {
+                x: 0,
+                y: y0,
+                width: self.width,
+                height,
+            });
+            return;
+        }
+
+        let x0 = area.x.rem_euclid(self.width as i32) as u32;
+        let width = area.width as u32;
+        if x0 + width <= self.width {
+            self.add_rect(Rectangle {
+                x: x0,
+                y: y0,
+                width,
+                height,
+            });
+        } else {
+            // 右端からはみ出した分は左端に折り返す
+            self.add_rect(Rectangle {
+                x: x0,
+                y: y0,
+                width: self.width - x0,
+                height,
+            });
+            self.add_rect(Rectangle {
+                x: 0,
+                y: y0,
+                width: x0 + width - self.width,
+                height,
+            });
+        }
+    }
+
+    /**
+     * 画像全体を範囲に追加する
+     */
+    pub fn add_all(&mut self) {
+        self.rects.clear();
+        if self.width > 0 && self.height > 0 {
+            self.rects.push(Rectangle {
+                x: 0,
+                y: 0,
+                width: self.width,
+                height: self.height,
+            });
+        }
+    }
+
+    /**
+     * 別の範囲を合成する
+     */
+    pub fn merge(&mut self, other: &DirtyRegion) {
+        for rect in &other.rects {
+            self.add_rect(*rect);
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rects.is_empty()
+    }
+
+    pub fn rects(&self) -> &[Rectangle<u32>] {
+        &self.rects
+    }
+
+    pub fn take(&mut self) -> Vec<Rectangle<u32>> {
+        std::mem::take(&mut self.rects)
+    }
+
+    pub fn clear(&mut self) {
+        self.rects.clear();
+    }
+
+    /**
+     * 画像の範囲内に収まっている矩形を追加する
+     * 重なる(接する)矩形とは統合する
+     */
+    fn add_rect(&mut self, rect: Rectangle<u32>) {
+        let x1 = (rect.x + rect.width).min(self.width);
+        let y1 = (rect.y + rect.height).min(self.height);
+        if rect.x >= x1 || rect.y >= y1 {
+            return;
+        }
+
+        let mut rect = Rectangle {
+            x: rect.x,
+            y: rect.y,
+            width: x1 - rect.x,
+            height: y1 - rect.y,
+        };
+        while let Some(index) = self.rects.iter().position(|r| Self::touches(r, &rect)) {
+            rect = Self::union(&self.rects.swap_remove(index), &rect);
+        }
+        self.rects.push(rect);
+
+        if self.rects.len() > MAX_RECTS {
+            let bounds = self
+                .rects
+                .iter()
+                .copied()
+                .reduce(|a, b| Self::union(&a, &b));
+            self.rects.clear();
+            self.rects.extend(bounds);
+        }
+    }
+
+    fn touches(a: &Rectangle<u32>, b: &Rectangle<u32>) -> bool {
+        a.x <= b.x + b.width
+            && b.x <= a.x + a.width
+            && a.y <= b.y + b.height
+            && b.y <= a.y + a.height
+    }
+
+    fn union(a: &Rectangle<u32>, b: &Rectangle<u32>) -> Rectangle<u32> {
+        let x = a.x.min(b.x);
+        let y = a.y.min(b.y);
+        Rectangle {
+            x,
+            y,
+            width: (a.x + a.width).max(b.x + b.width) - x,
+            height: (a.y + a.height).max(b.y + b.height) - y,
+        }
+    }
+}
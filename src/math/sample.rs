@@ -0,0 +1,141 @@
+use image::{Rgba, RgbaImage};
+
+/// 画像を変形して描画する際の補間方法
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Sampling {
+    /// 周囲2x2ピクセルの双線形補間
+    #[default]
+    Bilinear,
+    /// 周囲4x4ピクセルの双三次補間 (Catmull-Rom, 縮小・拡大しても輪郭がぼやけにくい)
+    Bicubic,
+}
+
+impl Sampling {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Sampling::Bilinear => "Bilinear",
+            Sampling::Bicubic => "Bicubic",
+        }
+    }
+
+    /**
+     * ピクセル座標 (x, y) の色をこの補間方法で求める
+     */
+    pub fn sample(&self, x: f32, y: f32, pixel: impl Fn(i32, i32) -> Rgba<u8>) -> Rgba<u8> {
+        match self {
+            Sampling::Bilinear => bilinear(x, y, pixel),
+            Sampling::Bicubic => bicubic(x, y, pixel),
+        }
+    }
+}
+
+/**
+ * 画像のピクセル (x, y) の色 (画像の外は透明)
+ */
+pub fn pixel_or_transparent(image: &RgbaImage, x: i32, y: i32) -> Rgba<u8> {
+    if x < 0 || y < 0 || x >= image.width() as i32 || y >= image.height() as i32 {
+        return Rgba([0, 0, 0, 0]);
+    }
+    *image.get_pixel(x as u32, y as u32)
+}
+
+/**
+ * 正距円筒図法の画像のピクセル (x, y) の色
+ * 経度は画像の左右端で折り返し、緯度は極で止める
+ */
+pub fn equirect_pixel(image: &RgbaImage, x: i32, y: i32) -> Rgba<u8> {
+    let x = x.rem_euclid(image.width() as i32) as u32;
+    let y = y.clamp(0, image.height() as i32 - 1) as u32;
+    *image.get_pixel(x, y)
+}
+
+/**
+ * ピクセル座標 (x, y) (ピクセルの左上が整数になる連続した座標) の色を双線形補間で求める
+ * pixel は整数のピクセル座標の色を返す
+ * 透明なピクセルの色が混ざらないように、色は不透明度で重み付けして補間する
+ */
+pub fn bilinear(x: f32, y: f32, pixel: impl Fn(i32, i32) -> Rgba<u8>) -> Rgba<u8> {
+    // ピクセルの中心を基準にする
+    let x = x - 0.5;
+    let y = y - 0.5;
+    let x0 = x.floor();
+    let y0 = y.floor();
+    let fx = x - x0;
+    let fy = y - y0;
+
+    let weights = [
+        (0, 0, (1.0 - fx) * (1.0 - fy)),
+        (1, 0, fx * (1.0 - fy)),
+        (0, 1, (1.0 - fx) * fy),
+        (1, 1, fx * fy),
+    ];
+    let mut sum = [0.0f32; 4];
+    for (dx, dy, weight) in weights {
+        accumulate(&mut sum, pixel(x0 as i32 + dx, y0 as i32 + dy), weight);
+    }
+
+    unpremultiply(sum)
+}
+
+/**
+ * ピクセル座標 (x, y) の色を周囲4x4ピクセルの双三次補間 (Catmull-Rom) で求める
+ * 引数と色の重み付けは bilinear と同じ
+ */
+pub fn bicubic(x: f32, y: f32, pixel: impl Fn(i32, i32) -> Rgba<u8>) -> Rgba<u8> {
+    let x = x - 0.5;
+    let y = y - 0.5;
+    let x0 = x.floor();
+    let y0 = y.floor();
+    let weights_x = cubic_weights(x - x0);
+    let weights_y = cubic_weights(y - y0);
+
+    let mut sum = [0.0f32; 4];
+    for (dy, weight_y) in (-1..=2).zip(weights_y) {
+        for (dx, weight_x) in (-1..=2).zip(weights_x) {
+            let color = pixel(x0 as i32 + dx, y0 as i32 + dy);
+            accumulate(&mut sum, color, weight_x * weight_y);
+        }
+    }
+
+    unpremultiply(sum)
+}
+
+/**
+ * 隣り合う4ピクセル (-1, 0, 1, 2) に掛ける Catmull-Rom の重み (t は 0 と 1 の間の位置)
+ */
+fn cubic_weights(t: f32) -> [f32; 4] {
+    let t2 = t * t;
+    let t3 = t2 * t;
+    [
+        (-t3 + 2.0 * t2 - t) * 0.5,
+        (3.0 * t3 - 5.0 * t2 + 2.0) * 0.5,
+        (-3.0 * t3 + 4.0 * t2 + t) * 0.5,
+        (t3 - t2) * 0.5,
+    ]
+}
+
+/**
+ * 不透明度を掛けた色と不透明度に weight を掛けて sum に足す
+ */
+pub fn accumulate(sum: &mut [f32; 4], color: Rgba<u8>, weight: f32) {
+    let alpha = color[3] as f32 * weight;
+    for (total, channel) in sum.iter_mut().zip(&color.0[..3]) {
+        *total += *channel as f32 * alpha;
+    }
+    sum[3] += alpha;
+}
+
+/**
+ * accumulate で足した値を、不透明度で割った色に戻す
+ */
+pub fn unpremultiply(sum: [f32; 4]) -> Rgba<u8> {
+    if sum[3] <= 0.0 {
+        return Rgba([0, 0, 0, 0]);
+    }
+    Rgba([
+        (sum[0] / sum[3]).round().clamp(0.0, 255.0) as u8,
+        (sum[1] / sum[3]).round().clamp(0.0, 255.0) as u8,
+        (sum[2] / sum[3]).round().clamp(0.0, 255.0) as u8,
+        sum[3].round().clamp(0.0, 255.0) as u8,
+    ])
+}
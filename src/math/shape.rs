@@ -0,0 +1,640 @@
+use std::f32::consts::{PI, TAU};
+
+use glam::{Mat3, Vec2, Vec3, vec2};
+use iced::Rectangle;
+
+use crate::math::arc;
+use crate::math::projection::SphereProjection;
+
+/// view上の多角形の1辺を球面上の折れ線にする際の分割数
+const VIEW_EDGE_DIVISIONS: usize = 32;
+
+/// 球面上の図形
+#[derive(Debug, Clone, PartialEq)]
+pub enum SphereShape {
+    /// 中心の方向と角半径 (ラジアン) で決まる球冠 (球面上の円)
+    Cap { center: Vec3, radius: f32 },
+    /// 頂点を大円で結んだ多角形 (外から見て、辺を進む向きの左側が内側)
+    Polygon { vertices: Vec<Vec3> },
+}
+
+impl SphereShape {
+    /**
+     * 頂点を大円で結んだ多角形のうち、狭い方を内側にした図形
+     */
+    pub fn polygon(mut vertices: Vec<Vec3>) -> Self {
+        if left_area(&vertices) > TAU {
+            vertices.reverse();
+        }
+        SphereShape::Polygon { vertices }
+    }
+
+    /**
+     * view座標 (0.0 ... 1.0) の2つの角で決まる矩形を球面に射影した図形
+     */
+    pub fn view_rectangle(proj: &SphereProjection, from: Vec2, to: Vec2) -> Self {
+        let corners = [
+            vec2(from.x, from.y),
+            vec2(to.x, from.y),
+            vec2(to.x, to.y),
+            vec2(from.x, to.y),
+        ];
+        Self::view_polygon(proj, &corners)
+    }
+
+    /**
+     * view座標 (0.0 ... 1.0) の頂点を結んだ多角形を球面に射影した図形
+     * 辺はviewでまっすぐに見えるように細かく分割する
+     */
+    pub fn view_polygon(proj: &SphereProjection, corners: &[Vec2]) -> Self {
+        let count = corners.len();
+        let points = (0..count)
+            .flat_map(|i| {
+                let (a, b) = (corners[i], corners[(i + 1) % count]);
+                (0..VIEW_EDGE_DIVISIONS)
+                    .map(move |j| a.lerp(b, j as f32 / VIEW_EDGE_DIVISIONS as f32))
+            })
+            .collect();
+
+        Self::view_path(proj, points)
+    }
+
+    /**
+     * ドラッグした軌跡のように細かく並んだ view座標 (0.0 ... 1.0) の点を結んだ多角形を球面に射影した図形
+     * 広い画角では半球より広くなることもあるので、viewで囲まれている側を内側にする
+     */
+    pub fn view_path(proj: &SphereProjection, points: Vec<Vec2>) -> Self {
+        // viewでの向き (y は下向き) と、viewの中心付近で射影が向きを保つか
+        let view_area: f32 = (0..points.len())
+            .map(|i| points[i].perp_dot(points[(i + 1) % points.len()]))
+            .sum();
+        let projected = [vec2(0.5, 0.5), vec2(0.501, 0.5), vec2(0.5, 0.501)]
+            .map(|view| proj.view_to_direction(view.x, view.y));
+        let preserves = match projected {
+            [Some(a), Some(b), Some(c)] => a.dot(b.cross(c)) > 0.0,
+            _ => true,
+        };
+
+        let mut vertices: Vec<Vec3> = points
+            .iter()
+            .filter_map(|view| proj.view_to_direction(view.x, view.y))
+            .collect();
+        if (view_area > 0.0) != preserves {
+            vertices.reverse();
+        }
+        SphereShape::Polygon { vertices }
+    }
+
+    /**
+     * 直交行列 transform で回転・反転した図形
+     */
+    pub fn transformed(&self, transform: Mat3) -> Self {
+        match self {
+            SphereShape::Cap { center, radius } => SphereShape::Cap {
+                center: transform * *center,
+                radius: *radius,
+            },
+            SphereShape::Polygon { vertices } => {
+                let mut vertices: Vec<Vec3> =
+                    vertices.iter().map(|vertex| transform * *vertex).collect();
+                // 反転すると向きが逆になるので、内側が左のままになるように並べ直す
+                if transform.determinant() < 0.0 {
+                    vertices.reverse();
+                }
+                SphereShape::Polygon { vertices }
+            }
+        }
+    }
+
+    /**
+     * 図形の輪郭を step (ラジアン) 以下の間隔で並べた点 (最初の点は末尾に繰り返さない)
+     */
+    pub fn outline(&self, step: f32) -> Vec<Vec3> {
+        match self {
+            SphereShape::Cap { center, radius } => {
+                let (u, v) = tangent_basis(*center);
+                let (sin_r, cos_r) = radius.clamp(0.0, PI).sin_cos();
+                let count = (TAU * sin_r / step.max(1e-4)).ceil().max(8.0) as usize;
+
+                (0..count)
+                    .map(|i| {
+                        let (sin_t, cos_t) = (i as f32 / count as f32 * TAU).sin_cos();
+                        (*center * cos_r + (u * cos_t + v * sin_t) * sin_r).normalize()
+                    })
+                    .collect()
+            }
+            SphereShape::Polygon { vertices } => {
+                let mut points = Vec::new();
+                for (i, a) in vertices.iter().enumerate() {
+                    let b = vertices[(i + 1) % vertices.len()];
+                    let mut edge = arc::great_circle(*a, b, step);
+                    edge.pop();
+                    points.append(&mut edge);
+                }
+                points
+            }
+        }
+    }
+
+    /**
+     * 球面上の方向 direction が図形の内側にあるか
+     * 多角形は、内側にある基準点から direction までの弧が辺と交わる回数で判定する
+     */
+    pub fn contains(&self, direction: Vec3) -> bool {
+        match self {
+            SphereShape::Cap { center, radius } => direction.dot(*center) >= radius.cos(),
+            SphereShape::Polygon { vertices } => {
+                if vertices.len() < 3 {
+                    return false;
+                }
+
+                // 一番長い辺の中点から少し左に寄せた点は、半球より広くても内側にある
+                let count = vertices.len();
+                let longest = (0..count)
+                    .max_by(|i, j| {
+                        let length = |k: usize| vertices[k].distance(vertices[(k + 1) % count]);
+                        length(*i).total_cmp(&length(*j))
+                    })
+                    .unwrap_or(0);
+                let mut inside = inside_reference(vertices, longest);
+                if inside.dot(direction) <= -1.0 + 1e-6 {
+                    // 正反対の2点を結ぶ弧は決まらないので、別の辺の基準点にする
+                    inside = inside_reference(vertices, (longest + count / 2) % count);
+                }
+
+                let crossings = vertices
+                    .iter()
+                    .enumerate()
+                    .filter(|(i, a)| {
+                        let b = vertices[(i + 1) % count];
+                        arcs_intersect(**a, b, inside, direction)
+                    })
+                    .count();
+                crossings % 2 == 0
+            }
+        }
+    }
+
+    /**
+     * 緯度 lat (ラジアン) の緯線のうち図形に含まれる経度の範囲 (-π ... π で、昇順に重ならない)
+     * 多角形は各辺と緯線の交点を一度だけ求めるので、辺の数に比例する手間で済む
+     */
+    pub fn parallel_spans(&self, lat: f32) -> Vec<(f32, f32)> {
+        let (sin_lat, cos_lat) = lat.sin_cos();
+        match self {
+            SphereShape::Cap { center, radius } => {
+                // cosφ (cx cosλ + cz sinλ) + cy sinφ >= cos r を λ について解く
+                let amplitude = center.x.hypot(center.z) * cos_lat;
+                let threshold = radius.cos() - center.y * sin_lat;
+                if amplitude <= 1e-9 {
+                    return if threshold <= 0.0 {
+                        vec![(-PI, PI)]
+                    } else {
+                        Vec::new()
+                    };
+                }
+
+                let k = threshold / amplitude;
+                if k <= -1.0 {
+                    vec![(-PI, PI)]
+                } else if k > 1.0 {
+                    Vec::new()
+                } else {
+                    let alpha = center.z.atan2(center.x);
+                    let delta = k.acos();
+                    wrap_span(alpha - delta, alpha + delta)
+                }
+            }
+            SphereShape::Polygon { vertices } => {
+                if vertices.len() < 3 {
+                    return Vec::new();
+                }
+
+                // 各辺の大円と緯線の交点のうち、辺の弧の上にあるもの
+                let mut crossings = Vec::new();
+                for (i, a) in vertices.iter().enumerate() {
+                    let b = vertices[(i + 1) % vertices.len()];
+                    let normal = a.cross(b);
+                    let amplitude = normal.x.hypot(normal.z) * cos_lat;
+                    if amplitude <= 1e-9 {
+                        continue;
+                    }
+                    let k = -normal.y * sin_lat / amplitude;
+                    if k.abs() > 1.0 {
+                        continue;
+                    }
+
+                    let alpha = normal.z.atan2(normal.x);
+                    let delta = k.acos();
+                    for lon in [alpha - delta, alpha + delta] {
+                        let point = SphereProjection::latlng_to_direction(lon, lat);
+                        // 頂点で隣り合う辺の両方に数えないように、終点は含めない
+                        if a.cross(point).dot(normal) >= 0.0 && point.cross(b).dot(normal) > 0.0 {
+                            crossings.push((lon + PI).rem_euclid(TAU) - PI);
+                        }
+                    }
+                }
+                crossings.sort_by(f32::total_cmp);
+
+                let mut bounds = vec![-PI];
+                bounds.extend(crossings.iter().copied());
+                bounds.push(PI);
+                let mut spans = Vec::new();
+                if crossings.len() % 2 == 0 {
+                    // 交点を通るたびに内外が入れ替わる
+                    let mut inside = self.contains(SphereProjection::latlng_to_direction(-PI, lat));
+                    for pair in bounds.windows(2) {
+                        if inside {
+                            spans.push((pair[0], pair[1]));
+                        }
+                        inside = !inside;
+                    }
+                } else {
+                    // 辺に接しているなどで数が合わないときは、区間ごとに中点で判定する
+                    for pair in bounds.windows(2) {
+                        let middle = (pair[0] + pair[1]) * 0.5;
+                        if self.contains(SphereProjection::latlng_to_direction(middle, lat)) {
+                            spans.push((pair[0], pair[1]));
+                        }
+                    }
+                }
+                spans.retain(|(from, to)| to > from);
+                spans
+            }
+        }
+    }
+
+    /**
+     * テクスチャの行 y のうち図形に含まれるピクセルと、その割合 (0.0 ... 1.0) を x の昇順に返す
+     * antialias なら行を上下2本の緯線に分け、横方向は覆う長さの割合にする
+     */
+    pub fn row_coverage(
+        &self,
+        y: u32,
+        width: u32,
+        height: u32,
+        antialias: bool,
+    ) -> Vec<(u32, f32)> {
+        let sub_rows: &[f32] = if antialias { &[0.25, 0.75] } else { &[0.5] };
+        let weight = 1.0 / sub_rows.len() as f32;
+        let w = width as f32;
+
+        let mut coverage: Vec<(u32, f32)> = Vec::new();
+        for dy in sub_rows {
+            let lat = (0.5 - (y as f32 + dy) / height as f32) * PI;
+            for (from, to) in self.parallel_spans(lat) {
+                let x0 = (from / TAU + 0.5) * w;
+                let x1 = (to / TAU + 0.5) * w;
+                if antialias {
+                    let start = x0.floor().max(0.0) as u32;
+                    let end = (x1.ceil() as u32).min(width);
+                    for x in start..end {
+                        let overlap = x1.min(x as f32 + 1.0) - x0.max(x as f32);
+                        if overlap > 0.0 {
+                            coverage.push((x, overlap * weight));
+                        }
+                    }
+                } else {
+                    // ピクセルの中心が範囲に入るもの
+                    let start = (x0 - 0.5).ceil().max(0.0) as u32;
+                    let end = ((x1 - 0.5).ceil().max(0.0) as u32).min(width);
+                    coverage.extend((start..end).map(|x| (x, weight)));
+                }
+            }
+        }
+
+        // 上下の緯線や隣り合う範囲で同じピクセルに重なった分を足し合わせる
+        coverage.sort_by_key(|(x, _)| *x);
+        coverage.dedup_by(|next, kept| {
+            if next.0 == kept.0 {
+                kept.1 += next.1;
+                true
+            } else {
+                false
+            }
+        });
+        for (_, amount) in &mut coverage {
+            *amount = amount.min(1.0);
+        }
+        coverage
+    }
+
+    /**
+     * 図形を囲むテクスチャのピクセル座標の範囲
+     * x は左右端で折り返す前の連続した座標で、極を含む図形は行全体になる
+     */
+    pub fn tex_bounds(&self, width: u32, height: u32) -> Rectangle<i32> {
+        let pixel = TAU / width.max(1) as f32;
+        let outline = self.outline(pixel);
+        let Some(first) = outline.first() else {
+            return Rectangle::default();
+        };
+
+        // 経度は隣り合う点との差を足していき、左右端をまたいでも連続させる
+        let (first_lon, first_lat) = SphereProjection::direction_to_latlng(*first);
+        let mut lon = first_lon;
+        let mut prev_lon = first_lon;
+        let (mut min_lon, mut max_lon) = (lon, lon);
+        let (mut min_lat, mut max_lat) = (first_lat, first_lat);
+        for point in &outline[1..] {
+            let (point_lon, point_lat) = SphereProjection::direction_to_latlng(*point);
+            lon += (point_lon - prev_lon + PI).rem_euclid(TAU) - PI;
+            prev_lon = point_lon;
+            min_lon = min_lon.min(lon);
+            max_lon = max_lon.max(lon);
+            min_lat = min_lat.min(point_lat);
+            max_lat = max_lat.max(point_lat);
+        }
+
+        let contains_north = self.contains(Vec3::Y);
+        let contains_south = self.contains(Vec3::NEG_Y);
+        if contains_north {
+            max_lat = PI * 0.5;
+        }
+        if contains_south {
+            min_lat = -PI * 0.5;
+        }
+
+        let w = width as f32;
+        let h = height as f32;
+        let y_min = ((0.5 - max_lat / PI) * h).floor() as i32 - 1;
+        let y_max = ((0.5 - min_lat / PI) * h).ceil() as i32 + 1;
+        let (x_min, x_max) =
+            if contains_north || contains_south || max_lon - min_lon + 2.0 * pixel >= TAU {
+                (0, width as i32)
+            } else {
+                (
+                    ((min_lon / TAU + 0.5) * w).floor() as i32 - 1,
+                    ((max_lon / TAU + 0.5) * w).ceil() as i32 + 1,
+                )
+            };
+
+        Rectangle {
+            x: x_min,
+            y: y_min,
+            width: x_max - x_min,
+            height: y_max - y_min,
+        }
+    }
+}
+
+/**
+ * 外から見て、辺を進む向きの左側にある面積 (ステラジアン)
+ */
+fn left_area(vertices: &[Vec3]) -> f32 {
+    let Some(first) = vertices.first() else {
+        return 0.0;
+    };
+    // 最初の頂点から扇状に分けた三角形の符号付き面積の和
+    let signed: f32 = vertices[1..]
+        .windows(2)
+        .map(|pair| {
+            let (a, b, c) = (*first, pair[0], pair[1]);
+            2.0 * a
+                .dot(b.cross(c))
+                .atan2(1.0 + a.dot(b) + b.dot(c) + c.dot(a))
+        })
+        .sum();
+    signed.rem_euclid(2.0 * TAU)
+}
+
+/**
+ * 多角形の辺 index の中点から、辺の長さの1%だけ左 (内側) に寄せた点
+ */
+fn inside_reference(vertices: &[Vec3], index: usize) -> Vec3 {
+    let a = vertices[index];
+    let b = vertices[(index + 1) % vertices.len()];
+    let middle = (a + b).normalize_or(a);
+    let left = a.cross(b).normalize_or_zero();
+    (middle + left * a.angle_between(b) * 0.01).normalize()
+}
+
+/**
+ * 経度の範囲 from ... to (幅は1周以下) を -π ... π に収まるように左右端で分ける
+ */
+fn wrap_span(from: f32, to: f32) -> Vec<(f32, f32)> {
+    let start = (from + PI).rem_euclid(TAU) - PI;
+    let end = start + (to - from);
+    if end <= PI {
+        vec![(start, end)]
+    } else {
+        vec![(-PI, end - TAU), (start, PI)]
+    }
+}
+
+/**
+ * 大円の弧 a-b と c-d (どちらも半周より短い) が交わるか
+ */
+fn arcs_intersect(a: Vec3, b: Vec3, c: Vec3, d: Vec3) -> bool {
+    let n1 = a.cross(b);
+    let n2 = c.cross(d);
+    // 互いの両端が相手の大円の反対側にある
+    if (n1.dot(c) > 0.0) == (n1.dot(d) > 0.0) || (n2.dot(a) > 0.0) == (n2.dot(b) > 0.0) {
+        return false;
+    }
+
+    // 2つの大円の交点 (±t) のうち、それぞれの弧が通る方が一致する
+    let t = n1.cross(n2);
+    (t.dot(a + b) > 0.0) == (t.dot(c + d) > 0.0)
+}
+
+/**
+ * direction に直交する2つの単位ベクトル
+ */
+pub fn tangent_basis(direction: Vec3) -> (Vec3, Vec3) {
+    let u = Vec3::Y.cross(direction);
+    let u = if u.length_squared() > 1e-12 {
+        u.normalize()
+    } else {
+        Vec3::Z
+    };
+    let v = direction.cross(u).normalize();
+    (u, v)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn latlng(lon_degree: f32, lat_degree: f32) -> Vec3 {
+        SphereProjection::latlng_to_direction(lon_degree.to_radians(), lat_degree.to_radians())
+    }
+
+    #[test]
+    fn cap_outline_is_at_radius() {
+        let center = latlng(30.0, 60.0);
+        let shape = SphereShape::Cap {
+            center,
+            radius: 0.3,
+        };
+        for point in shape.outline(0.01) {
+            assert!((point.angle_between(center) - 0.3).abs() < 1e-3);
+        }
+        assert!(shape.contains(center));
+        assert!(shape.contains(latlng(30.0, 70.0)));
+        assert!(!shape.contains(latlng(30.0, 30.0)));
+    }
+
+    #[test]
+    fn polygon_contains_inside_points_only() {
+        let shape = SphereShape::polygon(vec![
+            latlng(-10.0, -10.0),
+            latlng(10.0, -10.0),
+            latlng(0.0, 10.0),
+        ]);
+        assert!(shape.contains(latlng(0.0, 0.0)));
+        assert!(!shape.contains(latlng(20.0, 0.0)));
+        assert!(!shape.contains(latlng(180.0, 0.0)));
+    }
+
+    #[test]
+    fn polygon_across_seam() {
+        let shape = SphereShape::polygon(vec![
+            latlng(170.0, -10.0),
+            latlng(-170.0, -10.0),
+            latlng(-170.0, 10.0),
+            latlng(170.0, 10.0),
+        ]);
+        assert!(shape.contains(latlng(180.0, 0.0)));
+        assert!(!shape.contains(latlng(0.0, 0.0)));
+
+        // 左右端をまたぐ範囲は折り返す前の連続した座標になる
+        let bounds = shape.tex_bounds(360, 180);
+        assert!(bounds.x < 360 && bounds.x + bounds.width > 360);
+        assert!(bounds.width < 30);
+    }
+
+    #[test]
+    fn polygon_around_pole_covers_whole_rows() {
+        let shape = SphereShape::polygon(vec![
+            latlng(0.0, 70.0),
+            latlng(90.0, 70.0),
+            latlng(180.0, 70.0),
+            latlng(-90.0, 70.0),
+        ]);
+        assert!(shape.contains(Vec3::Y));
+        assert!(!shape.contains(Vec3::NEG_Y));
+
+        let bounds = shape.tex_bounds(360, 180);
+        assert_eq!((bounds.x, bounds.width), (0, 360));
+        assert!(bounds.y <= 0);
+        assert!(bounds.y + bounds.height < 40);
+    }
+
+    #[test]
+    fn row_coverage_matches_contains() {
+        let shapes = [
+            SphereShape::Cap {
+                center: latlng(40.0, 50.0),
+                radius: 0.6,
+            },
+            SphereShape::polygon(vec![
+                latlng(-30.0, -20.0),
+                latlng(25.0, -25.0),
+                latlng(5.0, 35.0),
+            ]),
+            SphereShape::polygon(vec![
+                latlng(0.0, 70.0),
+                latlng(90.0, 70.0),
+                latlng(180.0, 70.0),
+                latlng(-90.0, 70.0),
+            ]),
+        ];
+        let (width, height) = (360, 180);
+        for shape in &shapes {
+            for y in 0..height {
+                let row: Vec<u32> = shape
+                    .row_coverage(y, width, height, false)
+                    .into_iter()
+                    .map(|(x, _)| x)
+                    .collect();
+                let expected: Vec<u32> = (0..width)
+                    .filter(|x| {
+                        shape.contains(SphereProjection::tex_to_direction(
+                            (*x as f32 + 0.5) / width as f32,
+                            (y as f32 + 0.5) / height as f32,
+                        ))
+                    })
+                    .collect();
+                assert_eq!(row, expected, "row {y}");
+            }
+        }
+    }
+
+    #[test]
+    fn row_spans_do_not_depend_on_texture_width() {
+        let proj = SphereProjection::new(
+            crate::math::projection::ViewProjection::Rectilinear,
+            1.5,
+            1.0,
+            Vec3::X,
+            Vec3::Y,
+            Vec3::Z,
+        );
+        let shape = SphereShape::view_rectangle(&proj, vec2(0.05, 0.05), vec2(0.95, 0.95));
+
+        // 1ピクセルずつ判定すると数億回になる大きさでも、行ごとに辺と交わる所を求めるだけで済む
+        let (width, height) = (32768, 16384);
+        let bounds = shape.tex_bounds(width, height);
+        let start = std::time::Instant::now();
+        let mut pixels = 0.0;
+        for y in bounds.y.max(0)..(bounds.y + bounds.height).min(height as i32) {
+            let lat = (0.5 - (y as f32 + 0.5) / height as f32) * PI;
+            for (from, to) in shape.parallel_spans(lat) {
+                pixels += (to - from) / TAU * width as f32;
+            }
+        }
+        assert!(pixels > 1e7);
+        assert!(start.elapsed() < std::time::Duration::from_secs(5));
+    }
+
+    #[test]
+    fn reflected_polygon_keeps_inside() {
+        let shape = SphereShape::polygon(vec![
+            latlng(10.0, 0.0),
+            latlng(30.0, 0.0),
+            latlng(20.0, 15.0),
+        ]);
+        let mirrored = shape.transformed(Mat3::from_diagonal(Vec3::new(1.0, 1.0, -1.0)));
+        assert!(mirrored.contains(latlng(-20.0, 5.0)));
+        assert!(!mirrored.contains(latlng(20.0, 5.0)));
+        assert!(!mirrored.contains(latlng(160.0, -5.0)));
+    }
+
+    #[test]
+    fn wide_fisheye_marquee_covers_more_than_hemisphere() {
+        let proj = SphereProjection::new(
+            crate::math::projection::ViewProjection::Fisheye,
+            300f32.to_radians(),
+            1.0,
+            Vec3::X,
+            Vec3::Y,
+            Vec3::Z,
+        );
+        let shape = SphereShape::view_rectangle(&proj, vec2(0.05, 0.05), vec2(0.95, 0.95));
+        assert!(shape.contains(Vec3::X));
+        assert!(shape.contains(Vec3::Z));
+        assert!(shape.contains(latlng(120.0, 0.0)));
+        assert!(!shape.contains(Vec3::NEG_X));
+
+        // ドラッグする向きによらない
+        let reversed = SphereShape::view_rectangle(&proj, vec2(0.95, 0.05), vec2(0.05, 0.95));
+        assert!(reversed.contains(latlng(120.0, 0.0)));
+        assert!(!reversed.contains(Vec3::NEG_X));
+    }
+
+    #[test]
+    fn view_rectangle_contains_view_center() {
+        let proj = SphereProjection::new(
+            crate::math::projection::ViewProjection::Rectilinear,
+            1.0,
+            1.0,
+            Vec3::X,
+            Vec3::Y,
+            Vec3::Z,
+        );
+        let shape = SphereShape::view_rectangle(&proj, vec2(0.25, 0.25), vec2(0.75, 0.75));
+        assert!(shape.contains(Vec3::X));
+        assert!(!shape.contains(Vec3::Z));
+    }
+}
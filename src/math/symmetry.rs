@@ -0,0 +1,190 @@
+use std::f32::consts::{FRAC_PI_2, TAU};
+
+use glam::{Mat3, Vec3, vec3};
+
+use crate::math::projection::SphereProjection;
+
+/// 放射状の対称で繰り返せる最大の数
+pub const MAX_RADIAL: u32 = 12;
+
+/// 描画ツールのスタンプを球面上で対称な位置にも塗る設定
+///
+/// 鏡映と回転を組み合わせられ、全ての組み合わせで生成される変換ごとにスタンプを複製する。
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Symmetry {
+    /// 経度 meridian の子午線と極を含む平面で左右に反転する
+    pub mirror_meridian: bool,
+    /// 赤道面で上下に反転する
+    pub mirror_equator: bool,
+    /// 縦軸 (極を通る軸) の周りに繰り返す数 (1 なら繰り返さない)
+    pub radial: u32,
+    /// 反転の基準で、放射状に繰り返す起点にもなる子午線の経度 (ラジアン)
+    pub meridian: f32,
+}
+
+impl Default for Symmetry {
+    fn default() -> Self {
+        Self {
+            mirror_meridian: false,
+            mirror_equator: false,
+            radial: 1,
+            meridian: 0.0,
+        }
+    }
+}
+
+impl Symmetry {
+    pub fn is_active(&self) -> bool {
+        self.mirror_meridian || self.mirror_equator || self.radial > 1
+    }
+
+    /**
+     * スタンプを複製する変換 (最初は恒等変換)
+     * 鏡映を含むので回転ではなく直交行列で表す
+     */
+    pub fn transforms(&self) -> Vec<Mat3> {
+        let radial = self.radial.clamp(1, MAX_RADIAL);
+        let rotations: Vec<Mat3> = (0..radial)
+            .map(|i| Mat3::from_rotation_y(i as f32 * TAU / radial as f32))
+            .collect();
+
+        let mut transforms = rotations.clone();
+        if self.mirror_meridian {
+            // 子午線の平面の法線は、その経度の方向と縦軸の両方に直交する
+            let normal = vec3(-self.meridian.sin(), 0.0, self.meridian.cos());
+            let reflection = Mat3::IDENTITY - 2.0 * outer(normal, normal);
+            transforms.extend(rotations.iter().map(|rotation| *rotation * reflection));
+        }
+        if self.mirror_equator {
+            let reflection = Mat3::from_diagonal(vec3(1.0, -1.0, 1.0));
+            let mirrored: Vec<Mat3> = transforms
+                .iter()
+                .map(|transform| reflection * *transform)
+                .collect();
+            transforms.extend(mirrored);
+        }
+
+        transforms
+    }
+
+    /**
+     * viewに表示する対称の基準線 (子午線は極から極まで、赤道は1周)
+     * step (ラジアン) 以下の間隔で点を並べる
+     */
+    pub fn guides(&self, step: f32) -> Vec<Vec<Vec3>> {
+        let mut guides = Vec::new();
+
+        let radial = self.radial.clamp(1, MAX_RADIAL);
+        let mut meridians: Vec<f32> = if radial > 1 {
+            (0..radial)
+                .map(|i| self.meridian + i as f32 * TAU / radial as f32)
+                .collect()
+        } else {
+            Vec::new()
+        };
+        if self.mirror_meridian {
+            // 反転の平面は子午線とその反対側の子午線を含む
+            meridians.push(self.meridian);
+            meridians.push(self.meridian + TAU * 0.5);
+        }
+        let count = (TAU * 0.5 / step.max(1e-4)).ceil() as usize;
+        for lon in meridians {
+            guides.push(
+                (0..=count)
+                    .map(|i| {
+                        let lat = FRAC_PI_2 - i as f32 * TAU * 0.5 / count as f32;
+                        SphereProjection::latlng_to_direction(lon, lat)
+                    })
+                    .collect(),
+            );
+        }
+
+        if self.mirror_equator {
+            let count = (TAU / step.max(1e-4)).ceil() as usize;
+            guides.push(
+                (0..=count)
+                    .map(|i| {
+                        SphereProjection::latlng_to_direction(i as f32 * TAU / count as f32, 0.0)
+                    })
+                    .collect(),
+            );
+        }
+
+        guides
+    }
+}
+
+/**
+ * ベクトルの外積行列 a bᵀ
+ */
+fn outer(a: Vec3, b: Vec3) -> Mat3 {
+    Mat3::from_cols(a * b.x, a * b.y, a * b.z)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn latlng(lon_degree: f32, lat_degree: f32) -> Vec3 {
+        SphereProjection::latlng_to_direction(lon_degree.to_radians(), lat_degree.to_radians())
+    }
+
+    fn combinations() -> Vec<Symmetry> {
+        let mut symmetries = Vec::new();
+        for radial in [1, 2, 3, 6, MAX_RADIAL] {
+            for mirror_meridian in [false, true] {
+                for mirror_equator in [false, true] {
+                    symmetries.push(Symmetry {
+                        mirror_meridian,
+                        mirror_equator,
+                        radial,
+                        meridian: 0.7,
+                    });
+                }
+            }
+        }
+        symmetries
+    }
+
+    #[test]
+    fn transform_count_is_radial_times_mirrors() {
+        for symmetry in combinations() {
+            let mirrors = symmetry.mirror_meridian as u32 + symmetry.mirror_equator as u32;
+            assert_eq!(
+                symmetry.transforms().len() as u32,
+                symmetry.radial * 2u32.pow(mirrors)
+            );
+        }
+        assert_eq!(Symmetry::default().transforms(), vec![Mat3::IDENTITY]);
+    }
+
+    #[test]
+    fn transforms_are_orthogonal() {
+        for symmetry in combinations() {
+            for transform in symmetry.transforms() {
+                let product = transform * transform.transpose();
+                assert!(product.abs_diff_eq(Mat3::IDENTITY, 1e-5));
+                assert!((transform.determinant().abs() - 1.0).abs() < 1e-5);
+            }
+        }
+    }
+
+    #[test]
+    fn meridian_reflection_keeps_meridian() {
+        let meridian_degree: f32 = 40.0;
+        let symmetry = Symmetry {
+            mirror_meridian: true,
+            meridian: meridian_degree.to_radians(),
+            ..Symmetry::default()
+        };
+        let reflection = symmetry.transforms()[1];
+
+        for lat in [-80.0, -30.0, 0.0, 45.0, 89.0] {
+            let direction = latlng(meridian_degree, lat);
+            assert!((reflection * direction).abs_diff_eq(direction, 1e-5));
+        }
+        // 子午線から離れた点は反対側の同じ距離に移る
+        let mirrored = reflection * latlng(meridian_degree + 25.0, 10.0);
+        assert!(mirrored.abs_diff_eq(latlng(meridian_degree - 25.0, 10.0), 1e-5));
+    }
+}
@@ -0,0 +1,320 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use image::Rgba;
+
+use crate::Error;
+
+/// パレットを読み書きするダイアログに表示するフィルタ (名前, 拡張子)
+pub const PALETTE_FILTERS: &[(&str, &[&str])] = &[
+    ("GIMP Palette", &["gpl"]),
+    ("Adobe Swatch Exchange", &["ase"]),
+];
+
+const GPL_HEADER: &str = "GIMP Palette";
+const ASE_SIGNATURE: &[u8; 4] = b"ASEF";
+const ASE_GROUP_START: u16 = 0xc001;
+const ASE_COLOR: u16 = 0x0001;
+/// ASEの色の種類 (0: global, 1: spot, 2: normal)
+const ASE_COLOR_NORMAL: u16 = 2;
+
+/// 名前付きの色
+#[derive(Debug, Clone, PartialEq)]
+pub struct Swatch {
+    pub name: String,
+    pub color: Rgba<u8>,
+}
+
+/// ユーザーが登録した色の一覧
+#[derive(Debug, Clone, PartialEq)]
+pub struct Palette {
+    pub name: String,
+    pub swatches: Vec<Swatch>,
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        Self {
+            name: "Untitled".to_string(),
+            swatches: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum PaletteFormat {
+    Gpl,
+    Ase,
+}
+
+impl PaletteFormat {
+    fn from_path(path: &Path) -> Result<Self, Error> {
+        let extension = path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .map(|ext| ext.to_ascii_lowercase())
+            .unwrap_or_default();
+
+        match extension.as_str() {
+            "gpl" => Ok(PaletteFormat::Gpl),
+            "ase" => Ok(PaletteFormat::Ase),
+            _ => Err(Error::UnsupportedFormat(extension)),
+        }
+    }
+}
+
+/**
+ * 拡張子に応じた形式でパレットを読み込む
+ */
+pub async fn load_palette(path: PathBuf) -> Result<Palette, Error> {
+    let format = PaletteFormat::from_path(&path)?;
+    let bytes = fs::read(&path).map_err(|e| Error::Io(e.to_string()))?;
+    let default_name = path
+        .file_stem()
+        .and_then(|name| name.to_str())
+        .unwrap_or_default()
+        .to_string();
+
+    let mut palette = match format {
+        PaletteFormat::Gpl => read_gpl(&String::from_utf8_lossy(&bytes))?,
+        PaletteFormat::Ase => read_ase(&bytes)?,
+    };
+    if palette.name.is_empty() {
+        palette.name = default_name;
+    }
+
+    Ok(palette)
+}
+
+/**
+ * 拡張子に応じた形式でパレットを保存する
+ */
+pub async fn save_palette(path: PathBuf, palette: Palette) -> Result<PathBuf, Error> {
+    let bytes = match PaletteFormat::from_path(&path)? {
+        PaletteFormat::Gpl => write_gpl(&palette).into_bytes(),
+        PaletteFormat::Ase => write_ase(&palette),
+    };
+    fs::write(&path, bytes).map_err(|e| Error::Io(e.to_string()))?;
+
+    Ok(path)
+}
+
+/**
+ * GIMPのパレット (.gpl) を読み取る
+ * 各行は "R G B 名前" の形式で、アルファは持たない
+ */
+fn read_gpl(text: &str) -> Result<Palette, Error> {
+    let mut lines = text.lines();
+    if lines.next().map(str::trim) != Some(GPL_HEADER) {
+        return Err(Error::Decode("missing GIMP Palette header".to_string()));
+    }
+
+    let mut palette = Palette {
+        name: String::new(),
+        swatches: Vec::new(),
+    };
+    for line in lines {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') || line.starts_with("Columns:") {
+            continue;
+        }
+        if let Some(name) = line.strip_prefix("Name:") {
+            palette.name = name.trim().to_string();
+            continue;
+        }
+
+        let mut fields = line.split_whitespace();
+        let mut channel = || -> Result<u8, Error> {
+            fields
+                .next()
+                .and_then(|field| field.parse::<u8>().ok())
+                .ok_or_else(|| Error::Decode(format!("invalid palette entry: {}", line)))
+        };
+        let color = Rgba([channel()?, channel()?, channel()?, 255]);
+        let name = fields.collect::<Vec<_>>().join(" ");
+        palette.swatches.push(Swatch { name, color });
+    }
+
+    Ok(palette)
+}
+
+fn write_gpl(palette: &Palette) -> String {
+    let mut text = format!("{}\nName: {}\n#\n", GPL_HEADER, palette.name);
+    for swatch in &palette.swatches {
+        let [r, g, b, _] = swatch.color.0;
+        text.push_str(&format!("{:3} {:3} {:3}\t{}\n", r, g, b, swatch.name));
+    }
+    text
+}
+
+/**
+ * Adobe Swatch Exchange (.ase) を読み取る
+ * RGB, Gray, CMYK, LAB の色を sRGB に変換し、グループは平坦にする
+ */
+fn read_ase(bytes: &[u8]) -> Result<Palette, Error> {
+    let mut reader = AseReader { bytes, offset: 0 };
+    if reader.take(4)? != ASE_SIGNATURE {
+        return Err(Error::Decode("missing ASEF signature".to_string()));
+    }
+    let _version = (reader.u16()?, reader.u16()?);
+    let block_count = reader.u32()?;
+
+    let mut palette = Palette {
+        name: String::new(),
+        swatches: Vec::new(),
+    };
+    for _ in 0..block_count {
+        let block_type = reader.u16()?;
+        let length = reader.u32()? as usize;
+        let mut block = AseReader {
+            bytes: reader.take(length)?,
+            offset: 0,
+        };
+
+        match block_type {
+            ASE_GROUP_START => {
+                if palette.name.is_empty() {
+                    palette.name = block.string()?;
+                }
+            }
+            ASE_COLOR => {
+                let name = block.string()?;
+                let model = block.take(4)?;
+                let color = match model {
+                    b"RGB " => [block.f32()?, block.f32()?, block.f32()?],
+                    b"Gray" => {
+                        let gray = block.f32()?;
+                        [gray; 3]
+                    }
+                    b"CMYK" => {
+                        let [c, m, y, k] = [block.f32()?, block.f32()?, block.f32()?, block.f32()?];
+                        [
+                            (1.0 - c) * (1.0 - k),
+                            (1.0 - m) * (1.0 - k),
+                            (1.0 - y) * (1.0 - k),
+                        ]
+                    }
+                    b"LAB " => lab_to_rgb(block.f32()? * 100.0, block.f32()?, block.f32()?),
+                    _ => continue,
+                };
+                let to_u8 = |value: f32| (value * 255.0).round().clamp(0.0, 255.0) as u8;
+                palette.swatches.push(Swatch {
+                    name,
+                    color: Rgba([to_u8(color[0]), to_u8(color[1]), to_u8(color[2]), 255]),
+                });
+            }
+            // グループの終わりなどは読み飛ばす
+            _ => (),
+        }
+    }
+
+    Ok(palette)
+}
+
+fn write_ase(palette: &Palette) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    bytes.extend_from_slice(ASE_SIGNATURE);
+    bytes.extend_from_slice(&1u16.to_be_bytes());
+    bytes.extend_from_slice(&0u16.to_be_bytes());
+    bytes.extend_from_slice(&(palette.swatches.len() as u32).to_be_bytes());
+
+    for swatch in &palette.swatches {
+        let mut block = Vec::new();
+        // 名前は終端のNULを含むUTF-16の文字数とUTF-16BEの文字列
+        let name: Vec<u16> = swatch.name.encode_utf16().chain([0]).collect();
+        block.extend_from_slice(&(name.len() as u16).to_be_bytes());
+        for unit in name {
+            block.extend_from_slice(&unit.to_be_bytes());
+        }
+        block.extend_from_slice(b"RGB ");
+        for channel in &swatch.color.0[..3] {
+            block.extend_from_slice(&(*channel as f32 / 255.0).to_be_bytes());
+        }
+        block.extend_from_slice(&ASE_COLOR_NORMAL.to_be_bytes());
+
+        bytes.extend_from_slice(&ASE_COLOR.to_be_bytes());
+        bytes.extend_from_slice(&(block.len() as u32).to_be_bytes());
+        bytes.extend_from_slice(&block);
+    }
+
+    bytes
+}
+
+/**
+ * CIELAB (D50) を sRGB (0.0 ... 1.0) に変換する
+ */
+fn lab_to_rgb(l: f32, a: f32, b: f32) -> [f32; 3] {
+    let fy = (l + 16.0) / 116.0;
+    let fx = fy + a / 500.0;
+    let fz = fy - b / 200.0;
+    let inverse = |t: f32| {
+        if t > 6.0 / 29.0 {
+            t * t * t
+        } else {
+            3.0 * (6.0f32 / 29.0).powi(2) * (t - 4.0 / 29.0)
+        }
+    };
+    let x = 0.9642 * inverse(fx);
+    let y = inverse(fy);
+    let z = 0.8249 * inverse(fz);
+
+    // D50 の XYZ からリニアな sRGB (Bradford変換を含む行列)
+    let linear = [
+        3.1339 * x - 1.6169 * y - 0.4906 * z,
+        -0.9788 * x + 1.9161 * y + 0.0335 * z,
+        0.0719 * x - 0.2290 * y + 1.4052 * z,
+    ];
+    linear.map(|c| {
+        let c = c.clamp(0.0, 1.0);
+        if c <= 0.0031308 {
+            12.92 * c
+        } else {
+            1.055 * c.powf(1.0 / 2.4) - 0.055
+        }
+    })
+}
+
+/// ASEのビッグエンディアンのデータを先頭から読み出す
+struct AseReader<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> AseReader<'a> {
+    fn take(&mut self, length: usize) -> Result<&'a [u8], Error> {
+        let end = self
+            .offset
+            .checked_add(length)
+            .filter(|end| *end <= self.bytes.len())
+            .ok_or_else(|| Error::Decode("unexpected end of swatch file".to_string()))?;
+        let bytes = &self.bytes[self.offset..end];
+        self.offset = end;
+        Ok(bytes)
+    }
+
+    fn u16(&mut self) -> Result<u16, Error> {
+        let bytes = self.take(2)?;
+        Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn u32(&mut self) -> Result<u32, Error> {
+        let bytes = self.take(4)?;
+        Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    fn f32(&mut self) -> Result<f32, Error> {
+        Ok(f32::from_bits(self.u32()?))
+    }
+
+    /**
+     * UTF-16の文字数に続くUTF-16BEの文字列 (終端のNULを除く)
+     */
+    fn string(&mut self) -> Result<String, Error> {
+        let length = self.u16()? as usize;
+        let units = (0..length)
+            .map(|_| self.u16())
+            .collect::<Result<Vec<_>, _>>()?;
+        let units: Vec<u16> = units.into_iter().take_while(|unit| *unit != 0).collect();
+        Ok(String::from_utf16_lossy(&units))
+    }
+}
@@ -0,0 +1,371 @@
+use std::f32::consts::{PI, TAU};
+
+use glam::{Vec2, Vec3, vec2};
+use iced::Rectangle;
+use image::imageops::{self, FilterType};
+use image::{Rgba, RgbaImage};
+
+use crate::color;
+use crate::math::projection::{SphereProjection, ViewProjection};
+use crate::math::sample::{self, Sampling};
+use crate::widget::sphere_canvas::SphereCanvasState;
+
+/// 切り出す正方形の、極を囲む円の外側に残す余白 (円の半径に対する割合)
+const MARGIN: f32 = 1.25;
+/// ぼかして塗りつぶす際に周りの色を集める帯の幅 (円の半径に対する割合)
+const RING_WIDTH: f32 = 0.15;
+/// 周りの色を集める方位の分割数
+const RING_BINS: usize = 360;
+/// 集めた色を方位方向にぼかす幅 (分割数)
+const RING_BLUR: usize = 6;
+
+/// 切り出す極
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Pole {
+    /// 真下 (三脚が写る側)
+    Nadir,
+    /// 真上
+    Zenith,
+}
+
+impl Pole {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Pole::Nadir => "Nadir",
+            Pole::Zenith => "Zenith",
+        }
+    }
+
+    /**
+     * 極の方向
+     */
+    fn direction(&self) -> Vec3 {
+        match self {
+            Pole::Nadir => Vec3::NEG_Y,
+            Pole::Zenith => Vec3::Y,
+        }
+    }
+
+    /**
+     * 切り出す正方形の上に来る方向 (どちらの極でも経度 0 の側を上にする)
+     */
+    fn up(&self) -> Vec3 {
+        match self {
+            Pole::Nadir => Vec3::X,
+            Pole::Zenith => Vec3::NEG_X,
+        }
+    }
+}
+
+/// 極の付近を真上から見た平らな正方形 (極を中心とする心射方位図法)
+///
+/// 正距円筒図法では極に近い行ほど横に引き伸ばされるので、
+/// 正方形に切り出して編集してから元の画像に射影し直す。
+#[derive(Debug, Clone, Copy)]
+pub struct PolePatch {
+    pub pole: Pole,
+    /// 正方形の1辺のピクセル数
+    pub size: u32,
+    /// 正方形を view とみなした射影
+    pub projection: SphereProjection,
+}
+
+impl PolePatch {
+    /**
+     * 幅 image_width の画像から、極を中心に半径 angle の円を囲む正方形を切り出す
+     * 正方形の中心で元の画像の赤道と同じ解像度になる大きさにする
+     */
+    pub fn new(pole: Pole, angle: f32, image_width: u32) -> Self {
+        let half_aov = (angle.tan() * MARGIN).atan();
+        let size = (2.0 * half_aov.tan() * image_width as f32 / TAU)
+            .ceil()
+            .max(1.0) as u32;
+        let look_at = pole.direction();
+        let up = pole.up();
+        let projection = SphereProjection::new(
+            ViewProjection::Rectilinear,
+            2.0 * half_aov,
+            1.0,
+            look_at,
+            up,
+            look_at.cross(up),
+        );
+
+        Self {
+            pole,
+            size,
+            projection,
+        }
+    }
+
+    /**
+     * 正方形のピクセル座標 (左上が原点の連続した座標) の球面上の方向
+     */
+    fn patch_to_direction(&self, point: Vec2) -> Option<Vec3> {
+        let view = point / self.size as f32;
+        self.projection.view_to_direction(view.x, 1.0 - view.y)
+    }
+
+    /**
+     * 球面上の方向の、正方形のピクセル座標 (正方形の外なら None)
+     */
+    fn direction_to_patch(&self, direction: Vec3) -> Option<Vec2> {
+        let view = self.projection.direction_to_view(direction)?;
+        let point = vec2(view.x, 1.0 - view.y) * self.size as f32;
+        let size = self.size as f32;
+        (point.x >= 0.0 && point.y >= 0.0 && point.x <= size && point.y <= size).then_some(point)
+    }
+
+    /**
+     * 正方形が覆う、正距円筒図法の画像の行の範囲 (開始行, 行数)
+     */
+    fn rows(&self, tex_h: u32) -> (u32, u32) {
+        // 正方形の角の、極からの角度
+        let half_aov = self.projection.aov * 0.5;
+        let corner = (half_aov.tan() * 2f32.sqrt()).atan();
+        let rows = ((corner / PI * tex_h as f32).ceil() as u32 + 1).min(tex_h);
+        match self.pole {
+            Pole::Nadir => (tex_h - rows, rows),
+            Pole::Zenith => (0, rows),
+        }
+    }
+
+    /**
+     * 正距円筒図法の画像から正方形を切り出す
+     */
+    pub fn extract(&self, image: &RgbaImage, sampling: Sampling) -> RgbaImage {
+        let (tex_w, tex_h) = image.dimensions();
+        let mut patch = RgbaImage::new(self.size, self.size);
+        if tex_w == 0 || tex_h == 0 {
+            return patch;
+        }
+
+        for (x, y, pixel) in patch.enumerate_pixels_mut() {
+            let Some(direction) = self.patch_to_direction(vec2(x as f32 + 0.5, y as f32 + 0.5))
+            else {
+                continue;
+            };
+            let tex = SphereProjection::direction_to_tex(direction);
+
+            *pixel = sampling.sample(tex.x * tex_w as f32, tex.y * tex_h as f32, |x, y| {
+                sample::equirect_pixel(image, x, y)
+            });
+        }
+
+        patch
+    }
+
+    /**
+     * 編集した正方形 edited を、アクティブなレイヤーに射影し直す
+     * 切り出した時の正方形 original と同じ色になる部分は書き換えないので、編集していない部分は劣化しない
+     * edited の大きさが違う場合 (外部のエディタで拡大縮小した場合) は正方形全体に合わせる
+     * 変更前の範囲を履歴に保存し、テクスチャの更新範囲に加える
+     */
+    pub fn apply(
+        &self,
+        canvas_state: &mut SphereCanvasState,
+        original: &RgbaImage,
+        edited: &RgbaImage,
+        sampling: Sampling,
+    ) -> bool {
+        let Some(rw_image) = canvas_state.active_image() else {
+            return false;
+        };
+        let Ok(mut image) = rw_image.write() else {
+            return false;
+        };
+        let (tex_w, tex_h) = image.dimensions();
+        if tex_w == 0 || tex_h == 0 || edited.width() == 0 || edited.height() == 0 {
+            return false;
+        }
+
+        let (row, rows) = self.rows(tex_h);
+        let rect = Rectangle {
+            x: 0,
+            y: row,
+            width: tex_w,
+            height: rows,
+        };
+        if let Ok(mut history) = canvas_state.history.write() {
+            history.record(&rw_image, &image, rect);
+        }
+
+        let scale = vec2(
+            edited.width() as f32 / self.size as f32,
+            edited.height() as f32 / self.size as f32,
+        );
+        let mut changed = false;
+        for y in row..row + rows {
+            for x in 0..tex_w {
+                let direction = SphereProjection::tex_to_direction(
+                    (x as f32 + 0.5) / tex_w as f32,
+                    (y as f32 + 0.5) / tex_h as f32,
+                );
+                let Some(point) = self.direction_to_patch(direction) else {
+                    continue;
+                };
+
+                let before =
+                    sampling.sample(point.x, point.y, |x, y| clamped_pixel(original, x, y));
+                let point = point * scale;
+                let after = sampling.sample(point.x, point.y, |x, y| clamped_pixel(edited, x, y));
+                if before == after {
+                    continue;
+                }
+
+                // 選択範囲があれば、その外側は書き換えない
+                let clip = canvas_state.selection.coverage(x, y);
+                if clip <= 0.0 {
+                    continue;
+                }
+                let pixel = *image.get_pixel(x, y);
+                image.put_pixel(x, y, color::mix(pixel, after, clip));
+                changed = true;
+            }
+        }
+
+        // テクスチャの更新範囲
+        if changed {
+            canvas_state.mark_modified_rect(
+                &rw_image,
+                Rectangle {
+                    x: 0,
+                    y: row as i32,
+                    width: tex_w as i32,
+                    height: rows as i32,
+                },
+            );
+        }
+
+        changed
+    }
+
+    /**
+     * 極を囲む円の中心からの、円の縁を 1.0 とした距離と方位 (ラジアン)
+     */
+    fn polar(&self, x: u32, y: u32) -> (f32, f32) {
+        let center = self.size as f32 * 0.5;
+        let offset = vec2(x as f32 + 0.5, y as f32 + 0.5) - Vec2::splat(center);
+        (offset.length() * MARGIN / center, offset.y.atan2(offset.x))
+    }
+
+    /**
+     * 極を囲む円の中を、すぐ外側の色を中心に向かって滑らかに伸ばして塗りつぶす
+     * 方位ごとに周りの色を集めてぼかし、中心では全体の平均色になるように混ぜる
+     */
+    pub fn blur_fill(&self, patch: &mut RgbaImage) {
+        if patch.dimensions() != (self.size, self.size) {
+            return;
+        }
+
+        // 円のすぐ外側の帯の色を方位ごとに集める (不透明度で重み付けする)
+        let mut bins = [[0.0f32; 4]; RING_BINS];
+        for (x, y, pixel) in patch.enumerate_pixels() {
+            let (distance, azimuth) = self.polar(x, y);
+            if !(1.0..1.0 + RING_WIDTH).contains(&distance) {
+                continue;
+            }
+            let bin = azimuth_bin(azimuth).floor() as usize % RING_BINS;
+            let alpha = pixel[3] as f32 / 255.0;
+            for (total, channel) in bins[bin].iter_mut().zip(&pixel.0[..3]) {
+                *total += *channel as f32 * alpha;
+            }
+            bins[bin][3] += alpha;
+        }
+
+        // 方位方向にぼかして、色を集められなかった方位を埋める
+        let ring: Vec<[f32; 4]> = (0..RING_BINS)
+            .map(|bin| {
+                let mut sum = [0.0f32; 4];
+                for offset in 0..=2 * RING_BLUR {
+                    let neighbor = bins[(bin + RING_BINS + offset - RING_BLUR) % RING_BINS];
+                    for (total, value) in sum.iter_mut().zip(neighbor) {
+                        *total += value;
+                    }
+                }
+                sum
+            })
+            .collect();
+        let mut center = [0.0f32; 4];
+        for color in &bins {
+            for (total, value) in center.iter_mut().zip(color) {
+                *total += value;
+            }
+        }
+        if center[3] <= 0.0 {
+            return;
+        }
+        let center = normalize(center);
+        let ring: Vec<[f32; 4]> = ring
+            .into_iter()
+            .map(|sum| if sum[3] > 0.0 { normalize(sum) } else { center })
+            .collect();
+
+        for y in 0..self.size {
+            for x in 0..self.size {
+                let (distance, azimuth) = self.polar(x, y);
+                if distance >= 1.0 {
+                    continue;
+                }
+
+                // 隣り合う方位の色を補間し、中心の平均色と距離で混ぜる
+                let position = azimuth_bin(azimuth);
+                let t = position.fract();
+                let a = ring[position.floor() as usize % RING_BINS];
+                let b = ring[(position.floor() as usize + 1) % RING_BINS];
+                let color: [f32; 4] = std::array::from_fn(|i| {
+                    let edge = a[i] + (b[i] - a[i]) * t;
+                    center[i] + (edge - center[i]) * distance
+                });
+                patch.put_pixel(
+                    x,
+                    y,
+                    Rgba(color.map(|value| value.round().clamp(0.0, 255.0) as u8)),
+                );
+            }
+        }
+    }
+
+    /**
+     * ロゴを極を囲む円に収まる大きさにして、正方形の中央に重ねる
+     */
+    pub fn place_logo(&self, patch: &mut RgbaImage, logo: &RgbaImage) {
+        if logo.width() == 0 || logo.height() == 0 {
+            return;
+        }
+
+        // 円に内接する正方形に収める
+        let diameter = self.size as f32 / MARGIN;
+        let fit = diameter / 2f32.sqrt() / logo.width().max(logo.height()) as f32;
+        let width = ((logo.width() as f32 * fit).round() as u32).max(1);
+        let height = ((logo.height() as f32 * fit).round() as u32).max(1);
+        let resized = imageops::resize(logo, width, height, FilterType::CatmullRom);
+
+        let x = (self.size as i64 - width as i64) / 2;
+        let y = (self.size as i64 - height as i64) / 2;
+        imageops::overlay(patch, &resized, x, y);
+    }
+}
+
+/**
+ * 画像のピクセル (x, y) の色 (画像の外は一番近い端のピクセル)
+ */
+fn clamped_pixel(image: &RgbaImage, x: i32, y: i32) -> Rgba<u8> {
+    let x = x.clamp(0, image.width() as i32 - 1) as u32;
+    let y = y.clamp(0, image.height() as i32 - 1) as u32;
+    *image.get_pixel(x, y)
+}
+
+/**
+ * 方位 (-π ... π) を 0.0 ... RING_BINS の位置にする
+ */
+fn azimuth_bin(azimuth: f32) -> f32 {
+    (azimuth / TAU + 0.5) * RING_BINS as f32
+}
+
+/**
+ * 不透明度で重み付けして足した色を平均の色に戻す (塗りつぶす色は不透明にする)
+ */
+fn normalize(sum: [f32; 4]) -> [f32; 4] {
+    [sum[0] / sum[3], sum[1] / sum[3], sum[2] / sum[3], 255.0]
+}
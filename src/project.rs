@@ -0,0 +1,491 @@
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Cursor, Read, Seek, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+
+use glam::Vec3;
+use image::codecs::png::PngEncoder;
+use image::{ExtendedColorType, ImageEncoder, ImageFormat, ImageReader, Rgba, RgbaImage};
+use serde::{Deserialize, Serialize};
+use zip::write::SimpleFileOptions;
+use zip::{CompressionMethod, ZipArchive, ZipWriter};
+
+use crate::Error;
+use crate::file;
+use crate::history::{ImageSnapshot, StepSnapshot, TileSnapshot};
+use crate::layer::{BlendMode, Layer, LayerStack};
+use crate::math::projection::ViewProjection;
+use crate::math::symmetry::{MAX_RADIAL, Symmetry};
+
+pub const PROJECT_EXTENSION: &str = "pixrium";
+/// プロジェクトファイルの形式のバージョン (形式を変えたら上げて migrate に変換を追加する)
+pub const PROJECT_VERSION: u32 = 1;
+
+const MANIFEST_NAME: &str = "manifest.json";
+
+/// プロジェクトファイルに保存する視点
+#[derive(Debug, Clone, Copy)]
+pub struct ProjectView {
+    pub projection: ViewProjection,
+    pub aov: f32,
+    pub look_at: Vec3,
+    pub up: Vec3,
+    pub right: Vec3,
+}
+
+/// レイヤー・視点・ツール・履歴をまとめた編集中の状態
+#[derive(Debug, Clone)]
+pub struct Project {
+    pub layers: LayerStack,
+    pub view: ProjectView,
+    /// 選択中のツールの名前
+    pub tool: String,
+    /// ツールの名前ごとの設定
+    pub tool_settings: BTreeMap<String, serde_json::Value>,
+    /// 描画色と背景色
+    pub foreground: Rgba<u8>,
+    pub background: Rgba<u8>,
+    /// 描画ツールのスタンプを複製する対称の設定
+    pub symmetry: Symmetry,
+    pub undo_steps: Vec<StepSnapshot>,
+    pub redo_steps: Vec<StepSnapshot>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Manifest {
+    version: u32,
+    width: u32,
+    height: u32,
+    active_layer: usize,
+    /// 下のレイヤーから順に並べる
+    layers: Vec<LayerEntry>,
+    view: ViewEntry,
+    #[serde(default)]
+    tool: ToolEntry,
+    #[serde(default)]
+    colors: ColorEntry,
+    #[serde(default)]
+    symmetry: SymmetryEntry,
+    #[serde(default)]
+    history: HistoryEntry,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct LayerEntry {
+    name: String,
+    file: String,
+    visible: bool,
+    locked: bool,
+    opacity: f32,
+    blend_mode: BlendMode,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct ViewEntry {
+    projection: ViewProjection,
+    aov: f32,
+    look_at: [f32; 3],
+    up: [f32; 3],
+    right: [f32; 3],
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct ToolEntry {
+    current: String,
+    settings: BTreeMap<String, serde_json::Value>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct ColorEntry {
+    foreground: [u8; 4],
+    background: [u8; 4],
+}
+
+impl Default for ColorEntry {
+    fn default() -> Self {
+        Self {
+            foreground: [255, 255, 255, 255],
+            background: [0, 0, 0, 255],
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct SymmetryEntry {
+    mirror_meridian: bool,
+    mirror_equator: bool,
+    radial: u32,
+    meridian: f32,
+}
+
+impl Default for SymmetryEntry {
+    fn default() -> Self {
+        let symmetry = Symmetry::default();
+        Self {
+            mirror_meridian: symmetry.mirror_meridian,
+            mirror_equator: symmetry.mirror_equator,
+            radial: symmetry.radial,
+            meridian: symmetry.meridian,
+        }
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct HistoryEntry {
+    undo: Vec<StepEntry>,
+    redo: Vec<StepEntry>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct StepEntry {
+    images: Vec<StepImageEntry>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct StepImageEntry {
+    /// 変更されたレイヤーの番号
+    layer: usize,
+    tiles: Vec<TileEntry>,
+}
+
+/// 履歴のタイル (ピクセルデータは file に RGBA8 のまま格納する)
+#[derive(Debug, Serialize, Deserialize)]
+struct TileEntry {
+    x: u32,
+    y: u32,
+    width: u32,
+    height: u32,
+    file: String,
+}
+
+/**
+ * プロジェクトファイルの拡張子か
+ */
+pub fn is_project_path(path: &Path) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| ext.eq_ignore_ascii_case(PROJECT_EXTENSION))
+}
+
+/**
+ * プロジェクトをzipコンテナとして保存する
+ * 途中で失敗しても元のファイルが壊れないように、同じフォルダの一時ファイルに書いてから置き換える
+ */
+pub async fn save_project(path: PathBuf, project: Project) -> Result<PathBuf, Error> {
+    let temp_path = temp_path(&path);
+    let result = File::create(&temp_path)
+        .map_err(|e| Error::Io(e.to_string()))
+        .and_then(|file| {
+            write_project(BufWriter::new(&file), &project)?;
+            file.sync_all().map_err(|e| Error::Io(e.to_string()))
+        })
+        .and_then(|()| std::fs::rename(&temp_path, &path).map_err(|e| Error::Io(e.to_string())));
+    if result.is_err() {
+        let _ = std::fs::remove_file(&temp_path);
+    }
+    result?;
+
+    Ok(path)
+}
+
+/**
+ * 保存中に使う、path と同じフォルダの一時ファイルのパス
+ */
+fn temp_path(path: &Path) -> PathBuf {
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    path.with_file_name(format!(".{}.tmp", name))
+}
+
+/**
+ * プロジェクトファイルを読み込む
+ */
+pub fn load_project(path: &Path) -> Result<Project, Error> {
+    let file = File::open(path).map_err(|e| Error::Io(e.to_string()))?;
+    read_project(BufReader::new(file))
+}
+
+fn write_project(writer: impl Write + Seek, project: &Project) -> Result<(), Error> {
+    let mut zip = ZipWriter::new(writer);
+    // レイヤーはPNGで圧縮済みなので無圧縮で格納する
+    let stored = SimpleFileOptions::default().compression_method(CompressionMethod::Stored);
+    let deflated = SimpleFileOptions::default().compression_method(CompressionMethod::Deflated);
+
+    let mut layers = Vec::new();
+    for (index, layer) in project.layers.layers.iter().enumerate() {
+        let file = format!("layers/{}.png", index);
+        let image = layer
+            .image
+            .read()
+            .map_err(|e| Error::Encode(e.to_string()))?;
+
+        zip.start_file(&file, stored).map_err(encode_error)?;
+        PngEncoder::new(&mut zip)
+            .write_image(
+                image.as_raw(),
+                image.width(),
+                image.height(),
+                ExtendedColorType::Rgba8,
+            )
+            .map_err(encode_error)?;
+
+        layers.push(LayerEntry {
+            name: layer.name.clone(),
+            file,
+            visible: layer.visible,
+            locked: layer.locked,
+            opacity: layer.opacity,
+            blend_mode: layer.blend_mode,
+        });
+    }
+
+    let history = HistoryEntry {
+        undo: write_steps(
+            &mut zip,
+            deflated,
+            "undo",
+            &project.undo_steps,
+            &project.layers,
+        )?,
+        redo: write_steps(
+            &mut zip,
+            deflated,
+            "redo",
+            &project.redo_steps,
+            &project.layers,
+        )?,
+    };
+
+    let manifest = Manifest {
+        version: PROJECT_VERSION,
+        width: project.layers.width,
+        height: project.layers.height,
+        active_layer: project.layers.active,
+        layers,
+        view: ViewEntry {
+            projection: project.view.projection,
+            aov: project.view.aov,
+            look_at: project.view.look_at.to_array(),
+            up: project.view.up.to_array(),
+            right: project.view.right.to_array(),
+        },
+        tool: ToolEntry {
+            current: project.tool.clone(),
+            settings: project.tool_settings.clone(),
+        },
+        colors: ColorEntry {
+            foreground: project.foreground.0,
+            background: project.background.0,
+        },
+        symmetry: SymmetryEntry {
+            mirror_meridian: project.symmetry.mirror_meridian,
+            mirror_equator: project.symmetry.mirror_equator,
+            radial: project.symmetry.radial,
+            meridian: project.symmetry.meridian,
+        },
+        history,
+    };
+
+    zip.start_file(MANIFEST_NAME, deflated)
+        .map_err(encode_error)?;
+    serde_json::to_writer_pretty(&mut zip, &manifest).map_err(encode_error)?;
+
+    let mut writer = zip.finish().map_err(encode_error)?;
+    writer.flush().map_err(|e| Error::Io(e.to_string()))
+}
+
+/**
+ * 履歴のタイルを書き出す
+ * 削除済みのレイヤーに対するタイルは復元できないので保存しない
+ */
+fn write_steps(
+    zip: &mut ZipWriter<impl Write + Seek>,
+    options: SimpleFileOptions,
+    name: &str,
+    steps: &[StepSnapshot],
+    layers: &LayerStack,
+) -> Result<Vec<StepEntry>, Error> {
+    let mut entries = Vec::new();
+
+    for (step_index, step) in steps.iter().enumerate() {
+        let mut images = Vec::new();
+        for snapshot in &step.images {
+            let Some(layer) = layers
+                .layers
+                .iter()
+                .position(|layer| Arc::ptr_eq(&layer.image, &snapshot.image))
+            else {
+                continue;
+            };
+
+            let mut tiles = Vec::new();
+            for tile in &snapshot.tiles {
+                let file = format!(
+                    "history/{}/{}/{}_{}_{}.rgba",
+                    name, step_index, layer, tile.x, tile.y
+                );
+                zip.start_file(&file, options).map_err(encode_error)?;
+                zip.write_all(&tile.pixels).map_err(encode_error)?;
+
+                tiles.push(TileEntry {
+                    x: tile.x,
+                    y: tile.y,
+                    width: tile.width,
+                    height: tile.height,
+                    file,
+                });
+            }
+            images.push(StepImageEntry { layer, tiles });
+        }
+        entries.push(StepEntry { images });
+    }
+
+    Ok(entries)
+}
+
+fn read_project(reader: impl Read + Seek) -> Result<Project, Error> {
+    let mut zip = ZipArchive::new(reader).map_err(decode_error)?;
+
+    let manifest: serde_json::Value = {
+        let file = zip.by_name(MANIFEST_NAME).map_err(decode_error)?;
+        serde_json::from_reader(file).map_err(decode_error)?
+    };
+    let manifest: Manifest = serde_json::from_value(migrate(manifest)?).map_err(decode_error)?;
+
+    if manifest.layers.is_empty() {
+        return Err(Error::Decode("project has no layers".to_string()));
+    }
+    file::check_dimensions(manifest.width, manifest.height)?;
+
+    let mut layers = Vec::new();
+    for entry in manifest.layers {
+        let bytes = read_file(&mut zip, &entry.file)?;
+        // 画像を開く時と同じ制限でデコードする (既定の制限では大きなパノラマを開けない)
+        let mut reader = ImageReader::with_format(Cursor::new(&bytes), ImageFormat::Png);
+        reader.limits(file::decode_limits());
+        let image = reader.decode().map_err(decode_error)?.to_rgba8();
+        if image.dimensions() != (manifest.width, manifest.height) {
+            return Err(Error::Decode(format!(
+                "layer {} is {}x{}, expected {}x{}",
+                entry.file,
+                image.width(),
+                image.height(),
+                manifest.width,
+                manifest.height
+            )));
+        }
+
+        let mut layer = Layer::new(entry.name, image);
+        layer.visible = entry.visible;
+        layer.locked = entry.locked;
+        layer.opacity = entry.opacity.clamp(0.0, 1.0);
+        layer.blend_mode = entry.blend_mode;
+        layers.push(layer);
+    }
+
+    let images: Vec<_> = layers.iter().map(|layer| layer.image.clone()).collect();
+    let undo_steps = read_steps(&mut zip, manifest.history.undo, &images)?;
+    let redo_steps = read_steps(&mut zip, manifest.history.redo, &images)?;
+
+    Ok(Project {
+        layers: LayerStack::from_layers(
+            layers,
+            manifest.active_layer,
+            manifest.width,
+            manifest.height,
+        ),
+        view: ProjectView {
+            projection: manifest.view.projection,
+            aov: manifest.view.aov,
+            look_at: Vec3::from_array(manifest.view.look_at),
+            up: Vec3::from_array(manifest.view.up),
+            right: Vec3::from_array(manifest.view.right),
+        },
+        tool: manifest.tool.current,
+        tool_settings: manifest.tool.settings,
+        foreground: Rgba(manifest.colors.foreground),
+        background: Rgba(manifest.colors.background),
+        symmetry: Symmetry {
+            mirror_meridian: manifest.symmetry.mirror_meridian,
+            mirror_equator: manifest.symmetry.mirror_equator,
+            radial: manifest.symmetry.radial.clamp(1, MAX_RADIAL),
+            meridian: manifest.symmetry.meridian,
+        },
+        undo_steps,
+        redo_steps,
+    })
+}
+
+fn read_steps(
+    zip: &mut ZipArchive<impl Read + Seek>,
+    entries: Vec<StepEntry>,
+    images: &[Arc<RwLock<RgbaImage>>],
+) -> Result<Vec<StepSnapshot>, Error> {
+    let mut steps = Vec::new();
+
+    for entry in entries {
+        let mut step = StepSnapshot::default();
+        for image_entry in entry.images {
+            let Some(image) = images.get(image_entry.layer) else {
+                continue;
+            };
+
+            let mut tiles = Vec::new();
+            for tile in image_entry.tiles {
+                tiles.push(TileSnapshot {
+                    x: tile.x,
+                    y: tile.y,
+                    width: tile.width,
+                    height: tile.height,
+                    pixels: read_file(zip, &tile.file)?.into(),
+                });
+            }
+            step.images.push(ImageSnapshot {
+                image: image.clone(),
+                tiles,
+            });
+        }
+        steps.push(step);
+    }
+
+    Ok(steps)
+}
+
+/**
+ * 古いバージョンのマニフェストを現在のバージョンの形式に変換する
+ */
+fn migrate(manifest: serde_json::Value) -> Result<serde_json::Value, Error> {
+    let version = manifest
+        .get("version")
+        .and_then(|version| version.as_u64())
+        .ok_or_else(|| Error::Decode("manifest has no version".to_string()))?;
+
+    // 形式を変えたときは、ここで1つ前のバージョンから順に変換する
+    match version {
+        v if v == PROJECT_VERSION as u64 => Ok(manifest),
+        _ => Err(Error::UnsupportedFormat(format!(
+            "{} version {}",
+            PROJECT_EXTENSION, version
+        ))),
+    }
+}
+
+fn read_file(zip: &mut ZipArchive<impl Read + Seek>, name: &str) -> Result<Vec<u8>, Error> {
+    let mut file = zip.by_name(name).map_err(decode_error)?;
+    let mut bytes = Vec::new();
+    file.read_to_end(&mut bytes)
+        .map_err(|e| Error::Io(e.to_string()))?;
+
+    Ok(bytes)
+}
+
+fn encode_error(error: impl ToString) -> Error {
+    Error::Encode(error.to_string())
+}
+
+fn decode_error(error: impl ToString) -> Error {
+    Error::Decode(error.to_string())
+}
@@ -0,0 +1,246 @@
+use core::fmt;
+use std::sync::Arc;
+
+use iced::Rectangle;
+use image::{GrayImage, Luma};
+use serde::{Deserialize, Serialize};
+
+use crate::math::region::DirtyRegion;
+use crate::math::shape::SphereShape;
+use crate::tool::fill::FillMask;
+
+/// 新しく選択した範囲と今の選択範囲の組み合わせ方
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum SelectionMode {
+    /// 新しい範囲で置き換える
+    #[default]
+    Replace,
+    /// 新しい範囲を加える
+    Union,
+    /// 新しい範囲を除く
+    Subtract,
+    /// 両方に含まれる範囲だけを残す
+    Intersect,
+}
+
+impl SelectionMode {
+    pub const ALL: [SelectionMode; 4] = [
+        SelectionMode::Replace,
+        SelectionMode::Union,
+        SelectionMode::Subtract,
+        SelectionMode::Intersect,
+    ];
+}
+
+impl fmt::Display for SelectionMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            SelectionMode::Replace => "Replace",
+            SelectionMode::Union => "Union",
+            SelectionMode::Subtract => "Subtract",
+            SelectionMode::Intersect => "Intersect",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// 選択範囲 (equirect画像と同じ大きさで、ピクセルごとに選択している度合い 0 ... 255 を持つ)
+///
+/// 何も選択していなければ画像全体を編集できる。
+/// SphereCanvasState はイベントごとに複製されるので、マスクは共有して書き換える時だけ複製する。
+#[derive(Debug, Clone, Default)]
+pub struct Selection {
+    width: u32,
+    height: u32,
+    mask: Option<Arc<GrayImage>>,
+    /// 選択しているピクセルを囲む矩形 (マスクと一緒に更新する)
+    bounds: Rectangle<u32>,
+    /// 変更するたびに増やす (テクスチャの更新に使う)
+    revision: u64,
+}
+
+impl Selection {
+    /**
+     * 画像の大きさを変更する (選択は解除される)
+     */
+    pub fn resize(&mut self, width: u32, height: u32) {
+        self.width = width;
+        self.height = height;
+        self.clear();
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.mask.is_some()
+    }
+
+    pub fn mask(&self) -> Option<&GrayImage> {
+        self.mask.as_deref()
+    }
+
+    pub fn revision(&self) -> u64 {
+        self.revision
+    }
+
+    /**
+     * 編集できるピクセルを囲む矩形
+     * 選択範囲が無ければ画像全体になる
+     */
+    pub fn bounds(&self) -> Rectangle<u32> {
+        match self.mask {
+            Some(_) => self.bounds,
+            None => Rectangle {
+                x: 0,
+                y: 0,
+                width: self.width,
+                height: self.height,
+            },
+        }
+    }
+
+    /**
+     * ピクセルを編集できる度合い (0.0 ... 1.0)
+     * 選択範囲が無ければ画像全体を編集できる
+     */
+    pub fn coverage(&self, x: u32, y: u32) -> f32 {
+        match self.mask.as_ref() {
+            Some(mask) => mask.get_pixel(x, y)[0] as f32 / 255.0,
+            None => 1.0,
+        }
+    }
+
+    /**
+     * 選択を解除する
+     */
+    pub fn clear(&mut self) {
+        self.mask = None;
+        self.revision += 1;
+    }
+
+    pub fn select_all(&mut self) {
+        self.set_mask(GrayImage::from_pixel(self.width, self.height, Luma([255])));
+    }
+
+    /**
+     * 選択範囲を反転する (何も選択していなければ全体を選択する)
+     */
+    pub fn invert(&mut self) {
+        match self.mask.take() {
+            Some(mask) => {
+                let mut mask = Arc::unwrap_or_clone(mask);
+                for pixel in mask.pixels_mut() {
+                    pixel[0] = 255 - pixel[0];
+                }
+                self.set_mask(mask);
+            }
+            None => self.select_all(),
+        }
+    }
+
+    /**
+     * 新しく選択した範囲 mask を今の選択範囲と組み合わせる
+     * 何も選択していない状態からは、除く場合を除いて mask がそのまま選択範囲になる
+     */
+    pub fn combine(&mut self, mode: SelectionMode, mut mask: GrayImage) {
+        if mask.dimensions() != (self.width, self.height) {
+            return;
+        }
+
+        match (mode, self.mask.take()) {
+            (SelectionMode::Replace, _)
+            | (SelectionMode::Union, None)
+            | (SelectionMode::Intersect, None) => (),
+            (SelectionMode::Subtract, None) => {
+                self.clear();
+                return;
+            }
+            (mode, Some(current)) => {
+                for (new, old) in mask.pixels_mut().zip(current.pixels()) {
+                    let (a, b) = (old[0] as u32, new[0] as u32);
+                    new[0] = match mode {
+                        SelectionMode::Union => a.max(b),
+                        SelectionMode::Subtract => a * (255 - b) / 255,
+                        _ => a.min(b),
+                    } as u8;
+                }
+            }
+        }
+
+        self.set_mask(mask);
+    }
+
+    /**
+     * 選択範囲を置き換える (何も選択されていなければ解除する)
+     */
+    fn set_mask(&mut self, mask: GrayImage) {
+        self.bounds = mask_bounds(&mask);
+        self.mask = (self.bounds.width > 0).then(|| Arc::new(mask));
+        self.revision += 1;
+    }
+}
+
+/**
+ * マスクの選択しているピクセルを囲む矩形 (何も選択していなければ大きさ0)
+ */
+fn mask_bounds(mask: &GrayImage) -> Rectangle<u32> {
+    let (mut x_min, mut y_min) = (u32::MAX, u32::MAX);
+    let (mut x_max, mut y_max) = (0, 0);
+    for (x, y, pixel) in mask.enumerate_pixels() {
+        if pixel[0] > 0 {
+            x_min = x_min.min(x);
+            y_min = y_min.min(y);
+            x_max = x_max.max(x + 1);
+            y_max = y_max.max(y + 1);
+        }
+    }
+
+    if x_max == 0 {
+        return Rectangle::default();
+    }
+    Rectangle {
+        x: x_min,
+        y: y_min,
+        width: x_max - x_min,
+        height: y_max - y_min,
+    }
+}
+
+/**
+ * 球面上の図形の内側を選択するマスク
+ */
+pub fn shape_mask(shape: &SphereShape, width: u32, height: u32, antialias: bool) -> GrayImage {
+    let mut mask = GrayImage::new(width, height);
+    if width == 0 || height == 0 {
+        return mask;
+    }
+
+    let mut area = DirtyRegion::new(width, height);
+    area.add(shape.tex_bounds(width, height));
+    let (y_min, y_max) = area.rects().iter().fold((height, 0), |(min, max), rect| {
+        (min.min(rect.y), max.max(rect.y + rect.height))
+    });
+    for y in y_min..y_max {
+        // 1ピクセルずつ判定せず、行ごとに図形が覆う範囲をまとめて求める
+        for (x, coverage) in shape.row_coverage(y, width, height, antialias) {
+            mask.put_pixel(x, y, Luma([(coverage * 255.0).round() as u8]));
+        }
+    }
+
+    mask
+}
+
+/**
+ * 塗りつぶしの範囲を選択するマスク
+ */
+pub fn fill_mask(fill: &FillMask, antialias: bool) -> GrayImage {
+    let mut mask = GrayImage::new(fill.width, fill.height);
+    for rect in fill.area.rects() {
+        for y in rect.y..rect.y + rect.height {
+            for x in rect.x..rect.x + rect.width {
+                let coverage = fill.coverage(x, y, antialias);
+                mask.put_pixel(x, y, Luma([(coverage * 255.0).round() as u8]));
+            }
+        }
+    }
+
+    mask
+}
@@ -0,0 +1,456 @@
+use std::collections::HashMap;
+use std::f32::consts::{PI, TAU};
+use std::sync::{Arc, RwLock};
+
+use glam::{Mat3, Vec3};
+use iced::advanced::graphics::core::event::Status;
+use iced::mouse;
+use image::{Rgba, RgbaImage};
+use serde::{Deserialize, Serialize};
+
+use crate::layer::{self, BlendMode};
+use crate::math::projection::SphereProjection;
+use crate::math::region::DirtyRegion;
+use crate::widget::sphere_canvas::SphereCanvasState;
+
+const EPSILON: f32 = 1e-6;
+
+/// ブラシの設定
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BrushSettings {
+    /// ブラシの半径 (赤道上での画像のピクセル数)
+    pub width: f32,
+    /// 硬さ (0.0 で中心から縁まで減衰し、1.0 で縁まで均一に塗る)
+    pub hardness: f32,
+    /// スタンプの間隔 (ブラシの直径に対する割合)
+    pub spacing: f32,
+    /// ストローク全体で塗り重ねられる不透明度の上限
+    pub opacity: f32,
+    /// 1回のスタンプで塗る量
+    pub flow: f32,
+    /// 縁を1ピクセル分ぼかしてジャギーを抑える
+    pub antialias: bool,
+}
+
+impl Default for BrushSettings {
+    fn default() -> Self {
+        Self {
+            width: 3.0,
+            hardness: 0.8,
+            spacing: 0.25,
+            opacity: 1.0,
+            flow: 1.0,
+            antialias: true,
+        }
+    }
+}
+
+impl BrushSettings {
+    /**
+     * ブラシの半径に相当する球面上の角度
+     */
+    pub fn radius_angle(&self, image_width: u32) -> f32 {
+        self.width * TAU / image_width.max(1) as f32
+    }
+
+    /**
+     * スタンプの間隔に相当する球面上の角度 (1ピクセルの半分より細かくはしない)
+     */
+    pub fn spacing_angle(&self, image_width: u32) -> f32 {
+        let pixel = TAU / image_width.max(1) as f32;
+        (2.0 * self.radius_angle(image_width) * self.spacing).max(pixel * 0.5)
+    }
+
+    /**
+     * 中心からの距離 t (半径を 1.0 とする) での塗る量 (0.0 ... 1.0)
+     * edge はアンチエイリアスでぼかす幅 (t と同じ単位)
+     */
+    pub fn falloff(&self, t: f32, edge: f32) -> f32 {
+        if t >= 1.0 {
+            return 0.0;
+        }
+        let edge = if self.antialias {
+            edge.clamp(0.0, 1.0)
+        } else {
+            0.0
+        };
+        let inner = self.hardness.clamp(0.0, 1.0) * (1.0 - edge);
+        if t <= inner {
+            return 1.0;
+        }
+
+        // 内側の硬い部分から縁まで滑らかに減衰させる
+        let x = (t - inner) / (1.0 - inner);
+        1.0 - x * x * (3.0 - 2.0 * x)
+    }
+}
+
+/// 1回のスタンプで塗るピクセル (x, y, 塗る量) と、その範囲
+#[derive(Debug, Default)]
+pub struct Dab {
+    pub pixels: Vec<(u32, u32, f32)>,
+    pub area: DirtyRegion,
+}
+
+/**
+ * 球面上の方向 center を中心とするスタンプ (球冠) が覆うピクセルを求める
+ * 行ごとに球冠と交わる経度の範囲を計算するので、走査するのは球冠の中のピクセルだけになる
+ * 経度は画像の左右端で折り返し、極付近で行全体が球冠に入る場合も扱う
+ */
+pub fn dab(center: Vec3, settings: &BrushSettings, tex_w: u32, tex_h: u32) -> Dab {
+    let mut pixels = Vec::new();
+    let mut area = DirtyRegion::new(tex_w, tex_h);
+    if tex_w == 0 || tex_h == 0 {
+        return Dab { pixels, area };
+    }
+
+    let width = tex_w as f32;
+    let height = tex_h as f32;
+    let radius = settings.radius_angle(tex_w);
+    let cos_radius = radius.min(PI).cos();
+    // アンチエイリアスでぼかす幅 (1ピクセル分)
+    let edge = 1.0 / settings.width.max(1.0);
+
+    let (center_lon, center_lat) = SphereProjection::direction_to_latlng(center);
+    let (sin_lat_c, cos_lat_c) = center_lat.sin_cos();
+
+    // 球冠が掛かる行の範囲 (ピクセル中心の緯度は (0.5 - (y + 0.5) / height) * π)
+    let y_min = ((0.5 - (center_lat + radius) / PI) * height - 0.5).floor();
+    let y_max = ((0.5 - (center_lat - radius) / PI) * height - 0.5).ceil();
+    let y_min = (y_min.max(0.0) as u32).min(tex_h - 1);
+    let y_max = (y_max.max(0.0) as u32).min(tex_h - 1);
+
+    // 変更範囲 (中心の列からの相対位置で、折り返す前の座標)
+    let center_x = (center_lon / TAU + 0.5) * width - 0.5;
+    let mut min_x = i32::MAX;
+    let mut max_x = i32::MIN;
+    let mut min_y = i32::MAX;
+    let mut max_y = i32::MIN;
+
+    for y in y_min..=y_max {
+        let lat = (0.5 - (y as f32 + 0.5) / height) * PI;
+        let (sin_lat, cos_lat) = lat.sin_cos();
+
+        // 球冠と交わる経度の半幅 (球面三角法の余弦定理から求める)
+        let denominator = cos_lat * cos_lat_c;
+        let half_width = if denominator <= EPSILON {
+            // 中心が極にある場合は緯度だけで決まる
+            if sin_lat * sin_lat_c >= cos_radius {
+                PI
+            } else {
+                continue;
+            }
+        } else {
+            let cos_half_width = (cos_radius - sin_lat * sin_lat_c) / denominator;
+            if cos_half_width >= 1.0 {
+                continue;
+            }
+            cos_half_width.max(-1.0).acos()
+        };
+
+        // 経度の範囲をピクセルの列に変換する (1周を超える場合は行全体)
+        let span = half_width / TAU * width;
+        let (x_start, x_end) = if half_width >= PI || 2.0 * span + 1.0 >= width {
+            let start = (center_x - width * 0.5).floor() as i32;
+            (start, start + tex_w as i32 - 1)
+        } else {
+            (
+                (center_x - span).floor() as i32,
+                (center_x + span).ceil() as i32,
+            )
+        };
+
+        let mut painted = false;
+        for x in x_start..=x_end {
+            let lon = ((x as f32 + 0.5) / width - 0.5) * TAU;
+            let cos_distance = sin_lat * sin_lat_c + denominator * (lon - center_lon).cos();
+            let t = cos_distance.clamp(-1.0, 1.0).acos() / radius;
+
+            let amount = settings.falloff(t, edge);
+            if amount > 0.0 {
+                pixels.push((x.rem_euclid(tex_w as i32) as u32, y, amount));
+                min_x = min_x.min(x);
+                max_x = max_x.max(x);
+                painted = true;
+            }
+        }
+        if painted {
+            min_y = min_y.min(y as i32);
+            max_y = max_y.max(y as i32);
+        }
+    }
+
+    if min_x <= max_x && min_y <= max_y {
+        area.add(iced::Rectangle {
+            x: min_x,
+            y: min_y,
+            width: (max_x - min_x + 1).min(tex_w as i32),
+            height: max_y - min_y + 1,
+        });
+    }
+
+    Dab { pixels, area }
+}
+
+/**
+ * 前回のスタンプ位置からマウスの位置までスタンプを並べ、覆うピクセルごとに paint を呼ぶ
+ * paint には (ストローク, 画像, x, y, 塗る量) を渡す (塗る量には flow を掛けてある)
+ */
+pub fn stroke_to_mouse(
+    canvas_state: &Arc<RwLock<SphereCanvasState>>,
+    stroke: &RwLock<Stroke>,
+    settings: &BrushSettings,
+    mut paint: impl FnMut(&mut Stroke, &mut RgbaImage, u32, u32, f32),
+) -> Status {
+    dabs_to_mouse(
+        canvas_state,
+        stroke,
+        settings,
+        |stroke, image, _center, pixels| {
+            for &(x, y, amount) in pixels {
+                paint(stroke, image, x, y, amount);
+            }
+        },
+    )
+}
+
+/**
+ * 前回のスタンプ位置からマウスの位置までスタンプを並べ、スタンプごとに paint_dab を呼ぶ
+ * paint_dab には (ストローク, 画像, スタンプの中心の方向, スタンプが覆うピクセル (x, y, 塗る量)) を渡す
+ */
+pub fn dabs_to_mouse(
+    canvas_state: &Arc<RwLock<SphereCanvasState>>,
+    stroke: &RwLock<Stroke>,
+    settings: &BrushSettings,
+    paint_dab: impl FnMut(&mut Stroke, &mut RgbaImage, Vec3, &[(u32, u32, f32)]),
+) -> Status {
+    let Ok(mut canvas_state) = canvas_state.try_write() else {
+        return Status::Ignored;
+    };
+    if canvas_state.mouse_button != Some(mouse::Button::Left) {
+        return Status::Ignored;
+    }
+
+    let Ok(mut stroke) = stroke.write() else {
+        return Status::Captured;
+    };
+
+    // view座標(0.0~1.0)から球面上の方向への射影
+    let proj = canvas_state.sphere_projection();
+    let mp = canvas_state.get_mouse_coord_in_view();
+    let Some(direction) = proj.view_to_direction(mp.x, mp.y) else {
+        return Status::Captured;
+    };
+
+    stroke_dabs(
+        &mut canvas_state,
+        &mut stroke,
+        &[direction],
+        settings,
+        paint_dab,
+    );
+
+    Status::Captured
+}
+
+/**
+ * 前回のスタンプ位置から path の点を順に通るようにスタンプを並べ、覆うピクセルごとに paint を呼ぶ
+ * 塗る前に変更範囲を履歴に保存し、テクスチャの更新範囲に加える
+ * 選択範囲があれば、その外側のピクセルには paint を呼ばない
+ */
+pub fn stroke_path(
+    canvas_state: &mut SphereCanvasState,
+    stroke: &mut Stroke,
+    path: &[Vec3],
+    settings: &BrushSettings,
+    mut paint: impl FnMut(&mut Stroke, &mut RgbaImage, u32, u32, f32),
+) {
+    stroke_dabs(
+        canvas_state,
+        stroke,
+        path,
+        settings,
+        |stroke, image, _center, pixels| {
+            for &(x, y, amount) in pixels {
+                paint(stroke, image, x, y, amount);
+            }
+        },
+    );
+}
+
+/**
+ * 前回のスタンプ位置から path の点を順に通るようにスタンプを並べ、スタンプごとに paint_dab を呼ぶ
+ * paint_dab に渡すピクセルは選択範囲の外を除き、塗る量に flow と選択している度合いを掛けてある
+ * 周りのピクセルを混ぜるブラシのように、スタンプが覆う範囲をまとめて扱う場合に使う
+ * 対称の設定があれば、各スタンプを対称な位置にも複製する (複製中は Stroke::replica でその番号が分かる)
+ */
+pub fn stroke_dabs(
+    canvas_state: &mut SphereCanvasState,
+    stroke: &mut Stroke,
+    path: &[Vec3],
+    settings: &BrushSettings,
+    mut paint_dab: impl FnMut(&mut Stroke, &mut RgbaImage, Vec3, &[(u32, u32, f32)]),
+) {
+    let Some(rw_image) = canvas_state.active_image() else {
+        return;
+    };
+    let Ok(mut image) = rw_image.write() else {
+        return;
+    };
+
+    let tex_w = canvas_state.image_width;
+    let tex_h = canvas_state.image_height;
+
+    let spacing = settings.spacing_angle(tex_w);
+    let centers: Vec<Vec3> = path
+        .iter()
+        .flat_map(|point| stroke.advance(*point, spacing))
+        .collect();
+    let mut stroke_area = DirtyRegion::new(tex_w, tex_h);
+    // 対称な位置に複製したスタンプ (複製の番号, 変換, 中心)
+    let transforms = canvas_state.symmetry.transforms();
+    let stamps: Vec<(usize, Mat3, Vec3)> = centers
+        .iter()
+        .flat_map(|center| {
+            transforms
+                .iter()
+                .enumerate()
+                .map(move |(replica, transform)| (replica, *transform, *transform * *center))
+        })
+        .collect();
+
+    for (replica, transform, center) in stamps {
+        stroke.replica = replica;
+        stroke.transform = transform;
+        let dab = dab(center, settings, tex_w, tex_h);
+
+        // 塗る前に変更範囲を履歴に保存する
+        if let Ok(mut history) = canvas_state.history.write() {
+            for area in dab.area.rects() {
+                history.record(&rw_image, &image, *area);
+            }
+        }
+
+        // 選択範囲の外は塗らない
+        let pixels: Vec<(u32, u32, f32)> = dab
+            .pixels
+            .into_iter()
+            .filter_map(|(x, y, amount)| {
+                let clip = canvas_state.selection.coverage(x, y);
+                (clip > 0.0).then_some((x, y, amount * settings.flow * clip))
+            })
+            .collect();
+        paint_dab(stroke, &mut *image, center, &pixels);
+        stroke_area.merge(&dab.area);
+    }
+
+    // テクスチャの更新範囲
+    canvas_state.mark_modified(&rw_image, &stroke_area);
+}
+
+/// 押下から解放までの1回のストローク
+#[derive(Debug, Default)]
+pub struct Stroke {
+    /// 最後にスタンプした方向
+    last: Option<Vec3>,
+    /// ストロークで塗ったピクセルの、塗る前の色と塗り重ねた量
+    pixels: HashMap<(u32, u32), (Rgba<u8>, f32)>,
+    /// 対称の設定で複製している今のスタンプの番号 (0 が元のスタンプ)
+    replica: usize,
+    /// 元のスタンプの位置を今のスタンプの位置に移す変換
+    transform: Mat3,
+}
+
+impl Stroke {
+    /**
+     * 前回のスタンプから to までの大円上に、spacing (ラジアン) ごとのスタンプ位置を返す
+     * 最初の呼び出しでは to にスタンプする
+     */
+    pub fn advance(&mut self, to: Vec3, spacing: f32) -> Vec<Vec3> {
+        let Some(from) = self.last else {
+            self.last = Some(to);
+            return vec![to];
+        };
+
+        let angle = from.angle_between(to);
+        if angle.is_nan() || angle < spacing {
+            return Vec::new();
+        }
+
+        let count = (angle / spacing).floor() as usize;
+        let stamps: Vec<Vec3> = (1..=count)
+            .map(|i| from.slerp(to, i as f32 * spacing / angle).normalize())
+            .collect();
+        self.last = stamps.last().copied();
+
+        stamps
+    }
+
+    /**
+     * 対称の設定で複製している今のスタンプの番号 (0 が元のスタンプ)
+     */
+    pub fn replica(&self) -> usize {
+        self.replica
+    }
+
+    /**
+     * 元のスタンプの位置を今のスタンプの位置に移す変換 (鏡映を含む直交行列)
+     */
+    pub fn transform(&self) -> Mat3 {
+        self.transform
+    }
+
+    /**
+     * ストロークで塗る前のピクセル (x, y) の色 (まだ塗っていなければ今の色)
+     */
+    pub fn original(&self, image: &RgbaImage, x: u32, y: u32) -> Rgba<u8> {
+        match self.pixels.get(&(x, y)) {
+            Some((original, _)) => *original,
+            None => *image.get_pixel(x, y),
+        }
+    }
+
+    /**
+     * ピクセルに amount だけ塗り重ね、塗る前の色に color を合成する
+     * 同じストロークで何度塗っても不透明度は opacity を超えない
+     */
+    pub fn paint(
+        &mut self,
+        image: &mut RgbaImage,
+        x: u32,
+        y: u32,
+        amount: f32,
+        color: Rgba<u8>,
+        opacity: f32,
+    ) {
+        let (original, coverage) = self
+            .pixels
+            .entry((x, y))
+            .or_insert_with(|| (*image.get_pixel(x, y), 0.0));
+        *coverage += (1.0 - *coverage) * amount.clamp(0.0, 1.0);
+
+        image.put_pixel(
+            x,
+            y,
+            layer::composite(*original, color, opacity * *coverage, BlendMode::Normal),
+        );
+    }
+
+    /**
+     * ピクセルを amount だけ消し、塗る前の不透明度を下げる
+     * 同じストロークで何度消しても消す量は opacity を超えない
+     */
+    pub fn erase(&mut self, image: &mut RgbaImage, x: u32, y: u32, amount: f32, opacity: f32) {
+        let (original, coverage) = self
+            .pixels
+            .entry((x, y))
+            .or_insert_with(|| (*image.get_pixel(x, y), 0.0));
+        *coverage += (1.0 - *coverage) * amount.clamp(0.0, 1.0);
+
+        let mut pixel = *original;
+        let alpha = original[3] as f32 * (1.0 - opacity.clamp(0.0, 1.0) * *coverage);
+        pixel[3] = alpha.round().clamp(0.0, 255.0) as u8;
+        image.put_pixel(x, y, pixel);
+    }
+}
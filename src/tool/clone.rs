@@ -0,0 +1,311 @@
+use std::sync::{Arc, RwLock};
+
+use glam::{Mat3, Quat, Vec3};
+use iced::advanced::graphics::core::event::Status;
+use iced::mouse;
+use image::{Rgba, RgbaImage};
+use serde::{Deserialize, Serialize};
+
+use crate::math::projection::SphereProjection;
+use crate::math::sample;
+use crate::math::shape::SphereShape;
+use crate::tool::Tool;
+use crate::tool::brush::{self, BrushSettings, Stroke};
+use crate::tool::line::ARC_STEP;
+use crate::widget::sphere_canvas::SphereCanvasState;
+use crate::widget::sphere_canvas::overlay::OverlayPath;
+
+/// コピースタンプの種類
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CloneKind {
+    /// コピー元の色をそのまま塗る
+    Stamp,
+    /// コピー元の模様を、コピー先の色合いに合わせて塗る
+    Healing,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CloneSettings {
+    #[serde(flatten)]
+    pub brush: BrushSettings,
+    /// ストロークをまたいでコピー元との位置関係を保つ (false ならストロークごとにコピー元から塗り始める)
+    pub aligned: bool,
+}
+
+impl Default for CloneSettings {
+    fn default() -> Self {
+        Self {
+            brush: BrushSettings::default(),
+            aligned: true,
+        }
+    }
+}
+
+/// コピー元
+#[derive(Debug, Clone, Copy, Default)]
+struct Source {
+    /// Altキーを押しながらクリックした方向
+    direction: Option<Vec3>,
+    /// 塗る方向をコピー元の方向に移す球面の回転 (コピー元を決めてから最初に塗った時に決まる)
+    rotation: Option<Quat>,
+}
+
+/// Altキーを押しながらクリックした位置をコピー元にして、球面を回転した位置から写し取るツール
+///
+/// コピー元との位置関係を画像のピクセルのずれではなく球面の回転で持つので、
+/// 極の付近や画像の左右端をまたいでも写した模様が歪まない。
+#[derive(Debug)]
+pub struct CloneTool {
+    pub name: String,
+    pub icon: char,
+    pub kind: CloneKind,
+
+    pub settings: RwLock<CloneSettings>,
+    source: RwLock<Source>,
+    stroke: RwLock<Stroke>,
+}
+
+impl CloneTool {
+    pub fn new(kind: CloneKind) -> Self {
+        let (name, icon) = match kind {
+            CloneKind::Stamp => ("Clone Stamp", '\u{f5ab}'),
+            CloneKind::Healing => ("Healing Brush", '\u{eb7a}'),
+        };
+
+        Self {
+            name: name.to_string(),
+            icon,
+            kind,
+
+            settings: RwLock::new(CloneSettings::default()),
+            source: RwLock::new(Source::default()),
+            stroke: RwLock::new(Stroke::default()),
+        }
+    }
+
+    fn current_settings(&self) -> CloneSettings {
+        self.settings
+            .read()
+            .map(|settings| *settings)
+            .unwrap_or_default()
+    }
+
+    /**
+     * マウスの位置の球面上の方向
+     */
+    fn mouse_direction(canvas_state: &SphereCanvasState) -> Option<Vec3> {
+        let mp = canvas_state.get_mouse_coord_in_view();
+        canvas_state
+            .sphere_projection()
+            .view_to_direction(mp.x, mp.y)
+    }
+
+    /**
+     * 前回のスタンプ位置からマウスの位置までスタンプを並べ、コピー元の色を塗る
+     */
+    fn clone_to_mouse(&self, canvas_state: &Arc<RwLock<SphereCanvasState>>) -> Status {
+        let settings = self.current_settings();
+        let Some(rotation) = self.source.read().ok().and_then(|source| source.rotation) else {
+            return Status::Ignored;
+        };
+        let kind = self.kind;
+
+        brush::dabs_to_mouse(
+            canvas_state,
+            &self.stroke,
+            &settings.brush,
+            |stroke, image, _center, pixels| {
+                // 対称な位置に複製したスタンプでは、コピー元も同じ変換で移した位置になる
+                let transform = stroke.transform();
+                let mapping = transform * Mat3::from_quat(rotation) * transform.transpose();
+                let mut colors: Vec<Rgba<u8>> = pixels
+                    .iter()
+                    .map(|&(x, y, _)| source_color(stroke, image, mapping, x, y))
+                    .collect();
+                if kind == CloneKind::Healing {
+                    match_tone(stroke, image, pixels, &mut colors);
+                }
+
+                for (&(x, y, amount), color) in pixels.iter().zip(colors) {
+                    stroke.paint(image, x, y, amount, color, settings.brush.opacity);
+                }
+            },
+        )
+    }
+
+    /**
+     * マウスの位置に対応するコピー元をブラシの大きさの円で示す
+     */
+    fn show_source(&self, canvas_state: &Arc<RwLock<SphereCanvasState>>) {
+        let Ok(mut canvas_state) = canvas_state.try_write() else {
+            return;
+        };
+        let Ok(source) = self.source.read().map(|source| *source) else {
+            return;
+        };
+        let settings = self.current_settings();
+
+        // 塗っている間と、位置関係を保つ場合は回転した位置、それ以外はコピー元そのもの
+        let painting = canvas_state.mouse_button == Some(mouse::Button::Left);
+        let center = match (source.rotation, Self::mouse_direction(&canvas_state)) {
+            (Some(rotation), Some(direction)) if painting || settings.aligned => {
+                Some(rotation * direction)
+            }
+            _ => source.direction,
+        };
+        let Some(center) = center else {
+            return;
+        };
+
+        let radius = settings.brush.radius_angle(canvas_state.image_width);
+        canvas_state.overlay = vec![OverlayPath {
+            points: SphereShape::Cap { center, radius }.outline(ARC_STEP),
+            closed: true,
+        }];
+    }
+}
+
+/**
+ * 塗るピクセル (x, y) の方向を mapping で移したコピー元の色を、ストロークで塗る前の画像から補間して求める
+ */
+fn source_color(stroke: &Stroke, image: &RgbaImage, mapping: Mat3, x: u32, y: u32) -> Rgba<u8> {
+    let (width, height) = image.dimensions();
+    let direction = SphereProjection::tex_to_direction(
+        (x as f32 + 0.5) / width as f32,
+        (y as f32 + 0.5) / height as f32,
+    );
+    let tex = SphereProjection::direction_to_tex(mapping * direction);
+
+    // 経度は左右端で折り返し、緯度は極で止める
+    sample::bilinear(tex.x * width as f32, tex.y * height as f32, |sx, sy| {
+        let sx = sx.rem_euclid(width as i32) as u32;
+        let sy = sy.clamp(0, height as i32 - 1) as u32;
+        stroke.original(image, sx, sy)
+    })
+}
+
+/**
+ * スタンプが覆う範囲で、コピー元の色の平均をコピー先の平均に揃える
+ * 細かい模様はコピー元のまま、明るさや色味といった緩やかな変化だけをコピー先に合わせる
+ */
+fn match_tone(
+    stroke: &Stroke,
+    image: &RgbaImage,
+    pixels: &[(u32, u32, f32)],
+    colors: &mut [Rgba<u8>],
+) {
+    let mut source_sum = [0.0f32; 4];
+    let mut target_sum = [0.0f32; 4];
+    for (&(x, y, amount), color) in pixels.iter().zip(colors.iter()) {
+        sample::accumulate(&mut source_sum, *color, amount);
+        sample::accumulate(&mut target_sum, stroke.original(image, x, y), amount);
+    }
+    if source_sum[3] <= 0.0 || target_sum[3] <= 0.0 {
+        return;
+    }
+
+    let source = sample::unpremultiply(source_sum);
+    let target = sample::unpremultiply(target_sum);
+    let shift: [f32; 3] = std::array::from_fn(|i| target[i] as f32 - source[i] as f32);
+    for color in colors.iter_mut() {
+        for (channel, shift) in color.0[..3].iter_mut().zip(shift) {
+            *channel = (*channel as f32 + shift).round().clamp(0.0, 255.0) as u8;
+        }
+    }
+}
+
+impl Tool for CloneTool {
+    fn name(&self) -> &str {
+        &self.name
+    }
+    fn icon(&self) -> char {
+        self.icon
+    }
+
+    // Altキーはコピー元を決めるのに使うので、paints() は false のままにしてスポイトに切り替えない
+
+    fn settings(&self) -> Option<serde_json::Value> {
+        let settings = *self.settings.read().ok()?;
+        serde_json::to_value(settings).ok()
+    }
+
+    fn load_settings(&self, settings: &serde_json::Value) {
+        if let Ok(loaded) = CloneSettings::deserialize(settings) {
+            if let Ok(mut settings) = self.settings.write() {
+                *settings = loaded;
+            }
+        }
+    }
+
+    fn brush(&self) -> Option<BrushSettings> {
+        self.settings.read().ok().map(|settings| settings.brush)
+    }
+
+    fn set_brush(&self, brush: BrushSettings) {
+        if let Ok(mut settings) = self.settings.write() {
+            settings.brush = brush;
+        }
+    }
+
+    fn on_mouse_pressed(&self, canvas_state: &Arc<RwLock<SphereCanvasState>>) -> Status {
+        let (alt, direction) = {
+            let Ok(state) = canvas_state.try_read() else {
+                return Status::Ignored;
+            };
+            if state.mouse_button != Some(mouse::Button::Left) {
+                return Status::Ignored;
+            }
+            (state.modifiers.alt(), Self::mouse_direction(&state))
+        };
+        let Some(direction) = direction else {
+            return Status::Captured;
+        };
+        let Ok(mut source) = self.source.write() else {
+            return Status::Captured;
+        };
+
+        // Altキーを押しながらクリックした位置をコピー元にする
+        if alt {
+            *source = Source {
+                direction: Some(direction),
+                rotation: None,
+            };
+            drop(source);
+            self.show_source(canvas_state);
+            return Status::Captured;
+        }
+
+        // コピー元を決めてから最初のストローク (位置関係を保たない場合は毎回) で回転を決める
+        let Some(from) = source.direction else {
+            return Status::Captured;
+        };
+        if source.rotation.is_none() || !self.current_settings().aligned {
+            source.rotation = Some(Quat::from_rotation_arc(direction, from));
+        }
+        drop(source);
+
+        // 新しいストロークを始める
+        if let Ok(mut stroke) = self.stroke.write() {
+            *stroke = Stroke::default();
+        }
+
+        let status = self.clone_to_mouse(canvas_state);
+        self.show_source(canvas_state);
+        status
+    }
+
+    fn on_mouse_released(&self, _canvas_state: &Arc<RwLock<SphereCanvasState>>) -> Status {
+        if let Ok(mut stroke) = self.stroke.write() {
+            *stroke = Stroke::default();
+        }
+
+        Status::Ignored
+    }
+
+    fn on_mouse_moved(&self, canvas_state: &Arc<RwLock<SphereCanvasState>>) -> Status {
+        let status = self.clone_to_mouse(canvas_state);
+        self.show_source(canvas_state);
+        status
+    }
+}
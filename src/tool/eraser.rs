@@ -0,0 +1,111 @@
+use std::sync::{Arc, RwLock};
+
+use iced::advanced::graphics::core::event::Status;
+use serde::{Deserialize, Serialize};
+
+use crate::tool::Tool;
+use crate::tool::brush::{self, BrushSettings, Stroke};
+use crate::widget::sphere_canvas::SphereCanvasState;
+
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct EraserSettings {
+    #[serde(flatten)]
+    pub brush: BrushSettings,
+}
+
+#[derive(Debug)]
+pub struct EraserTool {
+    pub name: String,
+    pub icon: char,
+
+    pub settings: RwLock<EraserSettings>,
+    stroke: RwLock<Stroke>,
+}
+
+impl EraserTool {
+    pub fn new() -> Self {
+        Self {
+            name: "Eraser".to_string(),
+            icon: '\u{eb8b}',
+
+            settings: RwLock::new(EraserSettings::default()),
+            stroke: RwLock::new(Stroke::default()),
+        }
+    }
+
+    /**
+     * 前回のスタンプ位置からマウスの位置までスタンプを並べて、アクティブなレイヤーの不透明度を下げる
+     */
+    fn erase_to_mouse(&self, canvas_state: &Arc<RwLock<SphereCanvasState>>) -> Status {
+        let settings = self
+            .settings
+            .read()
+            .map(|settings| *settings)
+            .unwrap_or_default();
+
+        brush::stroke_to_mouse(
+            canvas_state,
+            &self.stroke,
+            &settings.brush,
+            |stroke, image, x, y, amount| stroke.erase(image, x, y, amount, settings.brush.opacity),
+        )
+    }
+}
+
+impl Tool for EraserTool {
+    fn name(&self) -> &str {
+        &self.name
+    }
+    fn icon(&self) -> char {
+        self.icon
+    }
+
+    fn paints(&self) -> bool {
+        true
+    }
+
+    fn settings(&self) -> Option<serde_json::Value> {
+        let settings = *self.settings.read().ok()?;
+        serde_json::to_value(settings).ok()
+    }
+
+    fn load_settings(&self, settings: &serde_json::Value) {
+        if let Ok(loaded) = EraserSettings::deserialize(settings) {
+            if let Ok(mut settings) = self.settings.write() {
+                *settings = loaded;
+            }
+        }
+    }
+
+    fn brush(&self) -> Option<BrushSettings> {
+        self.settings.read().ok().map(|settings| settings.brush)
+    }
+
+    fn set_brush(&self, brush: BrushSettings) {
+        if let Ok(mut settings) = self.settings.write() {
+            settings.brush = brush;
+        }
+    }
+
+    fn on_mouse_pressed(&self, canvas_state: &Arc<RwLock<SphereCanvasState>>) -> Status {
+        // 新しいストロークを始める
+        if let Ok(mut stroke) = self.stroke.write() {
+            *stroke = Stroke::default();
+        }
+
+        self.erase_to_mouse(canvas_state)
+    }
+
+    fn on_mouse_released(&self, _canvas_state: &Arc<RwLock<SphereCanvasState>>) -> Status {
+        if let Ok(mut stroke) = self.stroke.write() {
+            *stroke = Stroke::default();
+        }
+
+        Status::Ignored
+    }
+
+    fn on_mouse_moved(&self, canvas_state: &Arc<RwLock<SphereCanvasState>>) -> Status {
+        self.erase_to_mouse(canvas_state)
+    }
+}
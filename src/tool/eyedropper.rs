@@ -0,0 +1,217 @@
+use core::fmt;
+use std::sync::{Arc, RwLock};
+
+use glam::Vec3;
+use iced::advanced::graphics::core::event::Status;
+use iced::mouse;
+use image::Rgba;
+use serde::{Deserialize, Serialize};
+
+use crate::layer::LayerStack;
+use crate::math::projection::SphereProjection;
+use crate::tool::Tool;
+use crate::widget::sphere_canvas::SphereCanvasState;
+
+/// 色を平均する範囲 (赤道上のピクセル数)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum SampleSize {
+    #[default]
+    Point,
+    Average3x3,
+    Average5x5,
+}
+
+impl SampleSize {
+    pub const ALL: [SampleSize; 3] = [
+        SampleSize::Point,
+        SampleSize::Average3x3,
+        SampleSize::Average5x5,
+    ];
+
+    /**
+     * 中心から縁までのサンプル数
+     */
+    pub fn radius(&self) -> i32 {
+        match self {
+            SampleSize::Point => 0,
+            SampleSize::Average3x3 => 1,
+            SampleSize::Average5x5 => 2,
+        }
+    }
+}
+
+impl fmt::Display for SampleSize {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            SampleSize::Point => "Point Sample",
+            SampleSize::Average3x3 => "3 x 3 Average",
+            SampleSize::Average5x5 => "5 x 5 Average",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct EyedropperSettings {
+    pub sample_size: SampleSize,
+    /// 全レイヤーを合成した色を取得する (false ならアクティブなレイヤーの色)
+    pub sample_merged: bool,
+}
+
+impl Default for EyedropperSettings {
+    fn default() -> Self {
+        Self {
+            sample_size: SampleSize::Point,
+            sample_merged: true,
+        }
+    }
+}
+
+/**
+ * 球面上の方向 direction の周りの色を平均する
+ * 接平面上に1ピクセル(赤道上)間隔で並べた点で取得するので、極付近でも丸い範囲の平均になる
+ * 描画色に使うので不透明な色を返し、全て透明なら None (色を変えない)
+ */
+pub fn sample(
+    layers: &LayerStack,
+    direction: Vec3,
+    size: SampleSize,
+    merged: bool,
+) -> Option<Rgba<u8>> {
+    if layers.width == 0 || layers.height == 0 {
+        return None;
+    }
+    let active = if merged {
+        None
+    } else {
+        Some(layers.active_layer()?.image.read().ok()?)
+    };
+
+    // 接平面の東向きと北向きの単位ベクトル (極では経度が決まらないので任意の向きにする)
+    let east = Vec3::Y.cross(direction);
+    let east = if east.length_squared() > 1e-12 {
+        east.normalize()
+    } else {
+        Vec3::Z
+    };
+    let north = direction.cross(east).normalize();
+    let step = std::f32::consts::TAU / layers.width as f32;
+
+    let radius = size.radius();
+    let mut sum = [0.0; 3];
+    let mut alpha_sum = 0.0;
+    for j in -radius..=radius {
+        for i in -radius..=radius {
+            let point = (direction + (east * i as f32 + north * j as f32) * step).normalize();
+            let tex = SphereProjection::direction_to_tex(point);
+            let x = ((tex.x.rem_euclid(1.0) * layers.width as f32) as u32).min(layers.width - 1);
+            let y = ((tex.y.clamp(0.0, 1.0) * layers.height as f32) as u32).min(layers.height - 1);
+
+            let pixel = match active.as_ref() {
+                Some(image) => *image.get_pixel(x, y),
+                None => layers.composite_pixel(x, y),
+            };
+            // 透明なピクセルの色が混ざらないように不透明度で重み付けする
+            let alpha = pixel[3] as f32 / 255.0;
+            for c in 0..3 {
+                sum[c] += pixel[c] as f32 * alpha;
+            }
+            alpha_sum += alpha;
+        }
+    }
+
+    if alpha_sum <= 0.0 {
+        return None;
+    }
+    let mut color = [0, 0, 0, 255];
+    for c in 0..3 {
+        color[c] = (sum[c] / alpha_sum).round().clamp(0.0, 255.0) as u8;
+    }
+
+    Some(Rgba(color))
+}
+
+#[derive(Debug)]
+pub struct EyedropperTool {
+    pub name: String,
+    pub icon: char,
+
+    pub settings: RwLock<EyedropperSettings>,
+}
+
+impl EyedropperTool {
+    pub fn new() -> Self {
+        Self {
+            name: "Eyedropper".to_string(),
+            icon: '\u{ebe6}',
+
+            settings: RwLock::new(EyedropperSettings::default()),
+        }
+    }
+
+    /**
+     * マウスの位置の色を描画色にする
+     */
+    fn pick_color(&self, canvas_state: &Arc<RwLock<SphereCanvasState>>) -> Status {
+        let settings = self
+            .settings
+            .read()
+            .map(|settings| *settings)
+            .unwrap_or_default();
+
+        let Ok(mut canvas_state) = canvas_state.try_write() else {
+            return Status::Ignored;
+        };
+        if canvas_state.mouse_button != Some(mouse::Button::Left) {
+            return Status::Ignored;
+        }
+
+        let proj = canvas_state.sphere_projection();
+        let mp = canvas_state.get_mouse_coord_in_view();
+        let Some(direction) = proj.view_to_direction(mp.x, mp.y) else {
+            return Status::Captured;
+        };
+
+        if let Some(color) = sample(
+            &canvas_state.layers,
+            direction,
+            settings.sample_size,
+            settings.sample_merged,
+        ) {
+            canvas_state.foreground = color;
+        }
+
+        Status::Captured
+    }
+}
+
+impl Tool for EyedropperTool {
+    fn name(&self) -> &str {
+        &self.name
+    }
+    fn icon(&self) -> char {
+        self.icon
+    }
+
+    fn settings(&self) -> Option<serde_json::Value> {
+        let settings = *self.settings.read().ok()?;
+        serde_json::to_value(settings).ok()
+    }
+
+    fn load_settings(&self, settings: &serde_json::Value) {
+        if let Ok(loaded) = EyedropperSettings::deserialize(settings) {
+            if let Ok(mut settings) = self.settings.write() {
+                *settings = loaded;
+            }
+        }
+    }
+
+    fn on_mouse_pressed(&self, canvas_state: &Arc<RwLock<SphereCanvasState>>) -> Status {
+        self.pick_color(canvas_state)
+    }
+
+    fn on_mouse_moved(&self, canvas_state: &Arc<RwLock<SphereCanvasState>>) -> Status {
+        self.pick_color(canvas_state)
+    }
+}
@@ -0,0 +1,390 @@
+use std::sync::{Arc, RwLock};
+
+use iced::advanced::graphics::core::event::Status;
+use iced::mouse;
+use image::{Rgba, RgbaImage};
+use serde::{Deserialize, Serialize};
+
+use crate::layer::{self, BlendMode};
+use crate::math::projection::SphereProjection;
+use crate::math::region::DirtyRegion;
+use crate::tool::Tool;
+use crate::widget::sphere_canvas::SphereCanvasState;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FillSettings {
+    /// 同じ色とみなす各チャンネルの差の上限 (0.0 ... 1.0)
+    pub tolerance: f32,
+    /// クリックした位置から繋がっているピクセルだけを塗る (false なら画像全体の似た色を塗る)
+    pub contiguous: bool,
+    /// 塗る範囲の縁を1ピクセル分ぼかす
+    pub antialias: bool,
+    pub opacity: f32,
+}
+
+impl Default for FillSettings {
+    fn default() -> Self {
+        Self {
+            tolerance: 0.1,
+            contiguous: true,
+            antialias: true,
+            opacity: 1.0,
+        }
+    }
+}
+
+/// 塗りつぶす範囲 (画像と同じ大きさのマスク)
+#[derive(Debug, Default)]
+pub struct FillMask {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<bool>,
+    pub area: DirtyRegion,
+}
+
+impl FillMask {
+    /**
+     * 何も含まない範囲
+     */
+    pub fn new(width: u32, height: u32) -> Self {
+        Self {
+            width,
+            height,
+            pixels: vec![false; width as usize * height as usize],
+            area: DirtyRegion::new(width, height),
+        }
+    }
+
+    pub fn contains(&self, x: u32, y: u32) -> bool {
+        self.pixels[(y * self.width + x) as usize]
+    }
+
+    /**
+     * ピクセルを塗る量 (0.0 ... 1.0)
+     * antialias なら範囲の外側で接しているピクセルにも、周囲3x3の範囲内のピクセルの割合だけ塗る
+     */
+    pub fn coverage(&self, x: u32, y: u32, antialias: bool) -> f32 {
+        if self.contains(x, y) {
+            return 1.0;
+        }
+        if !antialias {
+            return 0.0;
+        }
+
+        let mut count = 0;
+        for dy in -1..=1 {
+            let ny = y as i32 + dy;
+            if ny < 0 || ny >= self.height as i32 {
+                continue;
+            }
+            for dx in -1..=1 {
+                // 左右端は折り返す
+                let nx = (x as i32 + dx).rem_euclid(self.width as i32);
+                if self.contains(nx as u32, ny as u32) {
+                    count += 1;
+                }
+            }
+        }
+        count as f32 / 9.0
+    }
+}
+
+/**
+ * seed のピクセルと色の差が tolerance 以内のピクセルを求める
+ * contiguous なら seed から上下左右に繋がっている範囲に限る
+ * 経度±180°の左右端は繋がっており、最上段と最下段はそれぞれ極の1点なので行全体が繋がっている
+ */
+pub fn flood_fill(
+    image: &RgbaImage,
+    seed: (u32, u32),
+    tolerance: f32,
+    contiguous: bool,
+) -> FillMask {
+    let mut mask = FillMask::new(image.width(), image.height());
+    flood_fill_into(
+        &mut mask,
+        &mut Vec::new(),
+        image,
+        seed,
+        tolerance,
+        contiguous,
+    );
+    mask
+}
+
+/**
+ * flood_fill と同じ条件で seed から求めた範囲を mask に加える
+ * visited は繋がりを調べるための作業領域で、複数の seed で使い回せる (空なら画像の大きさで確保する)
+ */
+pub fn flood_fill_into(
+    mask: &mut FillMask,
+    visited: &mut Vec<bool>,
+    image: &RgbaImage,
+    seed: (u32, u32),
+    tolerance: f32,
+    contiguous: bool,
+) {
+    let width = image.width();
+    let height = image.height();
+    if (mask.width, mask.height) != (width, height) || seed.0 >= width || seed.1 >= height {
+        return;
+    }
+
+    let target = *image.get_pixel(seed.0, seed.1);
+    let tolerance = (tolerance.clamp(0.0, 1.0) * 255.0).round() as i32;
+    let matches = |x: u32, y: u32| color_distance(*image.get_pixel(x, y), target) <= tolerance;
+
+    // 変更範囲 (左右端をまたぐ範囲は折り返す前の座標で持つ)
+    let mut min_x = i32::MAX;
+    let mut max_x = i32::MIN;
+    let mut min_y = i32::MAX;
+    let mut max_y = i32::MIN;
+
+    if contiguous {
+        // 他の seed から加えた範囲に遮られないように、繋がりは mask とは別に調べる
+        visited.resize(width as usize * height as usize, false);
+        let mut rest = vec![(seed.0 as i32, seed.1)];
+        let mut pole_filled = [false, false];
+        visited[(seed.1 * width + seed.0) as usize] = true;
+
+        while let Some((px, py)) = rest.pop() {
+            min_x = min_x.min(px);
+            max_x = max_x.max(px);
+            min_y = min_y.min(py as i32);
+            max_y = max_y.max(py as i32);
+
+            let neighbors = [
+                Some((px - 1, py)),
+                Some((px + 1, py)),
+                (py > 0).then(|| (px, py - 1)),
+                (py + 1 < height).then_some((px, py + 1)),
+            ];
+            for (nx, ny) in neighbors.into_iter().flatten() {
+                // 左右端を越えた先も元の列からの続きとして扱う
+                let wrapped = nx.rem_euclid(width as i32) as u32;
+                let index = (ny * width + wrapped) as usize;
+                if !visited[index] && matches(wrapped, ny) {
+                    visited[index] = true;
+                    rest.push((nx, ny));
+                }
+            }
+
+            // 極の行に届いたら、その行全体を隣接ピクセルとする
+            let pole = if py == 0 {
+                Some(0)
+            } else if py == height - 1 {
+                Some(1)
+            } else {
+                None
+            };
+            if let Some(pole) = pole {
+                if !pole_filled[pole] {
+                    pole_filled[pole] = true;
+                    for x in 0..width {
+                        let index = (py * width + x) as usize;
+                        if !visited[index] && matches(x, py) {
+                            visited[index] = true;
+                            rest.push((x as i32, py));
+                        }
+                    }
+                }
+            }
+        }
+    } else {
+        for (x, y, pixel) in image.enumerate_pixels() {
+            if color_distance(*pixel, target) <= tolerance {
+                mask.pixels[(y * width + x) as usize] = true;
+                min_x = min_x.min(x as i32);
+                max_x = max_x.max(x as i32);
+                min_y = min_y.min(y as i32);
+                max_y = max_y.max(y as i32);
+            }
+        }
+    }
+
+    if min_x <= max_x && min_y <= max_y {
+        if contiguous {
+            // 調べた範囲を mask に移し、作業領域を次の seed のために戻す
+            let mut found = DirtyRegion::new(width, height);
+            found.add(iced::Rectangle {
+                x: min_x,
+                y: min_y,
+                width: max_x - min_x + 1,
+                height: max_y - min_y + 1,
+            });
+            for rect in found.rects() {
+                for y in rect.y..rect.y + rect.height {
+                    let row = (y * width) as usize;
+                    for index in row + rect.x as usize..row + (rect.x + rect.width) as usize {
+                        if visited[index] {
+                            visited[index] = false;
+                            mask.pixels[index] = true;
+                        }
+                    }
+                }
+            }
+        }
+
+        // アンチエイリアスで塗る外側の1ピクセルを含める
+        mask.area.add(iced::Rectangle {
+            x: min_x - 1,
+            y: min_y - 1,
+            width: max_x - min_x + 3,
+            height: max_y - min_y + 3,
+        });
+    }
+}
+
+/**
+ * 2色の各チャンネルの差の最大値
+ */
+pub fn color_distance(a: Rgba<u8>, b: Rgba<u8>) -> i32 {
+    a.0.iter()
+        .zip(b.0.iter())
+        .map(|(a, b)| (*a as i32 - *b as i32).abs())
+        .max()
+        .unwrap_or(0)
+}
+
+#[derive(Debug)]
+pub struct FillTool {
+    pub name: String,
+    pub icon: char,
+
+    pub settings: RwLock<FillSettings>,
+}
+
+impl FillTool {
+    pub fn new() -> Self {
+        Self {
+            name: "Fill".to_string(),
+            icon: '\u{ea47}',
+
+            settings: RwLock::new(FillSettings::default()),
+        }
+    }
+}
+
+impl Tool for FillTool {
+    fn name(&self) -> &str {
+        &self.name
+    }
+    fn icon(&self) -> char {
+        self.icon
+    }
+
+    fn paints(&self) -> bool {
+        true
+    }
+
+    fn uses_foreground(&self) -> bool {
+        true
+    }
+
+    fn settings(&self) -> Option<serde_json::Value> {
+        let settings = *self.settings.read().ok()?;
+        serde_json::to_value(settings).ok()
+    }
+
+    fn load_settings(&self, settings: &serde_json::Value) {
+        if let Ok(loaded) = FillSettings::deserialize(settings) {
+            if let Ok(mut settings) = self.settings.write() {
+                *settings = loaded;
+            }
+        }
+    }
+
+    fn on_mouse_pressed(&self, canvas_state: &Arc<RwLock<SphereCanvasState>>) -> Status {
+        let settings = self
+            .settings
+            .read()
+            .map(|settings| *settings)
+            .unwrap_or_default();
+
+        let Ok(mut canvas_state) = canvas_state.try_write() else {
+            return Status::Ignored;
+        };
+        if canvas_state.mouse_button != Some(mouse::Button::Left) {
+            return Status::Ignored;
+        }
+
+        let Some(rw_image) = canvas_state.active_image() else {
+            return Status::Captured;
+        };
+        let Ok(mut image) = rw_image.write() else {
+            return Status::Captured;
+        };
+
+        // クリックした位置の方向
+        let proj = canvas_state.sphere_projection();
+        let mp = canvas_state.get_mouse_coord_in_view();
+        let Some(direction) = proj.view_to_direction(mp.x, mp.y) else {
+            return Status::Captured;
+        };
+        let tex_w = image.width();
+        let tex_h = image.height();
+        if tex_w == 0 || tex_h == 0 {
+            return Status::Captured;
+        }
+
+        // 対称な位置のテクセルからも塗る範囲を求め、塗る前の画像で判定するように全てまとめてから塗る
+        let mut mask = FillMask::new(tex_w, tex_h);
+        let mut visited = Vec::new();
+        for transform in canvas_state.symmetry.transforms() {
+            let tex = SphereProjection::direction_to_tex(transform * direction);
+            let seed = (
+                ((tex.x.rem_euclid(1.0) * tex_w as f32) as u32).min(tex_w - 1),
+                ((tex.y.clamp(0.0, 1.0) * tex_h as f32) as u32).min(tex_h - 1),
+            );
+            // 既に塗る範囲に入っている位置からは、求め直さない
+            if mask.contains(seed.0, seed.1) {
+                continue;
+            }
+            flood_fill_into(
+                &mut mask,
+                &mut visited,
+                &image,
+                seed,
+                settings.tolerance,
+                settings.contiguous,
+            );
+        }
+
+        // 押下から解放までが1つの履歴になるので、塗りつぶし全体が1回で取り消せる
+        if let Ok(mut history) = canvas_state.history.write() {
+            for area in mask.area.rects() {
+                history.record(&rw_image, &image, *area);
+            }
+        }
+
+        let color = canvas_state.foreground;
+        for area in mask.area.rects() {
+            for y in area.y..area.y + area.height {
+                for x in area.x..area.x + area.width {
+                    let amount = mask.coverage(x, y, settings.antialias)
+                        * canvas_state.selection.coverage(x, y);
+                    if amount <= 0.0 {
+                        continue;
+                    }
+                    let pixel = *image.get_pixel(x, y);
+                    image.put_pixel(
+                        x,
+                        y,
+                        layer::composite(
+                            pixel,
+                            color,
+                            settings.opacity * amount,
+                            BlendMode::Normal,
+                        ),
+                    );
+                }
+            }
+        }
+
+        // テクスチャの更新範囲
+        canvas_state.mark_modified(&rw_image, &mask.area);
+
+        Status::Captured
+    }
+}
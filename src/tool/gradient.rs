@@ -0,0 +1,503 @@
+use core::fmt;
+use std::f32::consts::{FRAC_PI_2, TAU};
+use std::sync::{Arc, RwLock};
+
+use glam::{Mat3, Vec3};
+use iced::advanced::graphics::core::event::Status;
+use iced::{Rectangle, mouse};
+use image::Rgba;
+use serde::{Deserialize, Serialize};
+
+use crate::layer::{self, BlendMode};
+use crate::math::arc;
+use crate::math::projection::SphereProjection;
+use crate::math::region::DirtyRegion;
+use crate::math::shape::SphereShape;
+use crate::tool::Tool;
+use crate::tool::line::ARC_STEP;
+use crate::widget::sphere_canvas::SphereCanvasState;
+use crate::widget::sphere_canvas::overlay::OverlayPath;
+
+/// グラデーションの形
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum GradientMode {
+    /// 始点から終点への大円に沿って変化する
+    #[default]
+    Linear,
+    /// 始点からの角距離で変化する (終点までが半径)
+    Radial,
+    /// 始点の周りの方位角で変化する (終点の方向が0)
+    Angular,
+    /// 緯度で変化する (始点と終点の緯度の間)
+    Sky,
+}
+
+impl GradientMode {
+    pub const ALL: [GradientMode; 4] = [
+        GradientMode::Linear,
+        GradientMode::Radial,
+        GradientMode::Angular,
+        GradientMode::Sky,
+    ];
+}
+
+impl fmt::Display for GradientMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            GradientMode::Linear => "Linear",
+            GradientMode::Radial => "Radial",
+            GradientMode::Angular => "Angular",
+            GradientMode::Sky => "Sky",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// グラデーションの位置 (0.0 ... 1.0) での色
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct GradientStop {
+    pub position: f32,
+    pub color: [u8; 4],
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Gradient {
+    pub stops: Vec<GradientStop>,
+}
+
+impl Default for Gradient {
+    fn default() -> Self {
+        Self::new(&[(0.0, [0, 0, 0, 255]), (1.0, [255, 255, 255, 255])])
+    }
+}
+
+impl Gradient {
+    pub fn new(stops: &[(f32, [u8; 4])]) -> Self {
+        Self {
+            stops: stops
+                .iter()
+                .map(|(position, color)| GradientStop {
+                    position: *position,
+                    color: *color,
+                })
+                .collect(),
+        }
+    }
+
+    /**
+     * 位置順に並べた色の区切り
+     * 編集中の区切りの番号が変わらないように、stops 自体は並べ替えない
+     */
+    pub fn sorted_stops(&self) -> Vec<GradientStop> {
+        let mut stops = self.stops.clone();
+        stops.sort_by(|a, b| a.position.total_cmp(&b.position));
+        stops
+    }
+
+    /**
+     * 位置 t (0.0 ... 1.0) の色 (前後の区切りの色を線形に補間する)
+     */
+    pub fn color_at(stops: &[GradientStop], t: f32) -> Rgba<u8> {
+        let (Some(first), Some(last)) = (stops.first(), stops.last()) else {
+            return Rgba([0, 0, 0, 0]);
+        };
+        if t <= first.position {
+            return Rgba(first.color);
+        }
+        if t >= last.position {
+            return Rgba(last.color);
+        }
+
+        let next = stops
+            .iter()
+            .position(|stop| stop.position > t)
+            .unwrap_or(stops.len() - 1);
+        let (a, b) = (stops[next - 1], stops[next]);
+        let s = (t - a.position) / (b.position - a.position).max(1e-6);
+        let mut color = [0u8; 4];
+        for c in 0..4 {
+            color[c] = (a.color[c] as f32 + (b.color[c] as f32 - a.color[c] as f32) * s)
+                .round()
+                .clamp(0.0, 255.0) as u8;
+        }
+        Rgba(color)
+    }
+}
+
+/// 名前を付けて保存したグラデーション
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GradientPreset {
+    pub name: String,
+    pub gradient: Gradient,
+}
+
+/**
+ * 最初から用意しているグラデーション
+ */
+fn default_presets() -> Vec<GradientPreset> {
+    let preset = |name: &str, stops: &[(f32, [u8; 4])]| GradientPreset {
+        name: name.to_string(),
+        gradient: Gradient::new(stops),
+    };
+
+    vec![
+        preset(
+            "Black to White",
+            &[(0.0, [0, 0, 0, 255]), (1.0, [255, 255, 255, 255])],
+        ),
+        preset(
+            "Transparent",
+            &[(0.0, [255, 255, 255, 255]), (1.0, [255, 255, 255, 0])],
+        ),
+        preset(
+            "Clear Sky",
+            &[
+                (0.0, [220, 235, 250, 255]),
+                (0.4, [130, 180, 235, 255]),
+                (1.0, [40, 90, 180, 255]),
+            ],
+        ),
+        preset(
+            "Sunset",
+            &[
+                (0.0, [255, 170, 80, 255]),
+                (0.3, [240, 110, 120, 255]),
+                (1.0, [50, 40, 110, 255]),
+            ],
+        ),
+    ]
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GradientSettings {
+    pub mode: GradientMode,
+    pub gradient: Gradient,
+    pub opacity: f32,
+    /// 色の並びを逆にする
+    pub reverse: bool,
+    pub presets: Vec<GradientPreset>,
+}
+
+impl Default for GradientSettings {
+    fn default() -> Self {
+        Self {
+            mode: GradientMode::default(),
+            gradient: Gradient::default(),
+            opacity: 1.0,
+            reverse: false,
+            presets: default_presets(),
+        }
+    }
+}
+
+/// 球面上の方向からグラデーションの位置を求める
+#[derive(Debug, Clone, Copy)]
+pub struct GradientField {
+    mode: GradientMode,
+    start: Vec3,
+    /// 始点の接平面で終点に向かう単位ベクトル
+    toward: Vec3,
+    /// 始点を軸に toward から90°回した単位ベクトル
+    side: Vec3,
+    /// 始点から終点までの角度
+    length: f32,
+    start_lat: f32,
+    end_lat: f32,
+}
+
+impl GradientField {
+    pub fn new(mode: GradientMode, start: Vec3, end: Vec3) -> Self {
+        let toward = end - start * start.dot(end);
+        let toward = if toward.length_squared() > 1e-12 {
+            toward.normalize()
+        } else {
+            // 終点が始点と同じ (か正反対) なら向きは任意に決める
+            let east = Vec3::Y.cross(start);
+            if east.length_squared() > 1e-12 {
+                east.normalize()
+            } else {
+                Vec3::Z
+            }
+        };
+
+        Self {
+            mode,
+            start,
+            toward,
+            side: start.cross(toward),
+            length: start.angle_between(end),
+            start_lat: SphereProjection::direction_to_latlng(start).1,
+            end_lat: SphereProjection::direction_to_latlng(end).1,
+        }
+    }
+
+    /**
+     * 方向 direction でのグラデーションの位置 (0.0 ... 1.0)
+     */
+    pub fn parameter(&self, direction: Vec3) -> f32 {
+        let t = match self.mode {
+            GradientMode::Linear => {
+                // 始点と終点を通る大円の上での、始点からの角度
+                let angle = direction.dot(self.toward).atan2(direction.dot(self.start));
+                angle / self.length.max(1e-6)
+            }
+            GradientMode::Radial => self.start.angle_between(direction) / self.length.max(1e-6),
+            GradientMode::Angular => {
+                let angle = direction.dot(self.side).atan2(direction.dot(self.toward));
+                angle.rem_euclid(TAU) / TAU
+            }
+            GradientMode::Sky => {
+                let lat = SphereProjection::direction_to_latlng(direction).1;
+                let range = self.end_lat - self.start_lat;
+                if range.abs() <= 1e-6 {
+                    if lat >= self.start_lat { 1.0 } else { 0.0 }
+                } else {
+                    (lat - self.start_lat) / range
+                }
+            }
+        };
+        t.clamp(0.0, 1.0)
+    }
+
+    /**
+     * 始点と終点を示すプレビュー
+     */
+    pub fn overlay(&self, end: Vec3) -> Vec<OverlayPath> {
+        let line = OverlayPath {
+            points: arc::great_circle(self.start, end, ARC_STEP),
+            closed: false,
+        };
+        let circle = |center: Vec3, radius: f32| OverlayPath {
+            points: SphereShape::Cap { center, radius }.outline(ARC_STEP),
+            closed: true,
+        };
+
+        match self.mode {
+            GradientMode::Linear | GradientMode::Angular => vec![line],
+            GradientMode::Radial => vec![line, circle(self.start, self.length)],
+            // 始点と終点の緯線
+            GradientMode::Sky => vec![
+                circle(Vec3::Y, FRAC_PI_2 - self.start_lat),
+                circle(Vec3::Y, FRAC_PI_2 - self.end_lat),
+            ],
+        }
+    }
+}
+
+/**
+ * アクティブなレイヤーにグラデーションを描画する
+ * 選択範囲があれば、その内側だけを塗る
+ * 対称の設定があれば、各ピクセルを始点が最も近い複製のグラデーションで塗る
+ */
+pub fn draw_gradient(
+    canvas_state: &mut SphereCanvasState,
+    field: &GradientField,
+    settings: &GradientSettings,
+) {
+    let Some(rw_image) = canvas_state.active_image() else {
+        return;
+    };
+    let Ok(mut image) = rw_image.write() else {
+        return;
+    };
+    let tex_w = image.width();
+    let tex_h = image.height();
+
+    // 選択範囲があれば、それを囲む範囲だけを塗って履歴に保存する
+    let bounds = canvas_state.selection.bounds();
+    let mut area = DirtyRegion::new(tex_w, tex_h);
+    area.add(Rectangle {
+        x: bounds.x as i32,
+        y: bounds.y as i32,
+        width: bounds.width as i32,
+        height: bounds.height as i32,
+    });
+
+    // 塗る前に変更範囲を履歴に保存する
+    if let Ok(mut history) = canvas_state.history.write() {
+        for rect in area.rects() {
+            history.record(&rw_image, &image, *rect);
+        }
+    }
+
+    let stops = settings.gradient.sorted_stops();
+    let selection = &canvas_state.selection;
+    // 複製した図形を元に戻す逆変換 (直交行列なので転置)
+    let inverses: Vec<Mat3> = canvas_state
+        .symmetry
+        .transforms()
+        .into_iter()
+        .map(|transform| transform.transpose())
+        .collect();
+    let row_bytes = tex_w as usize * 4;
+    let threads = std::thread::available_parallelism().map_or(1, |count| count.get());
+    let pixels: &mut [u8] = &mut image;
+    for rect in area.rects() {
+        // 行をスレッドの数に分けて並列に塗る
+        let rows =
+            &mut pixels[rect.y as usize * row_bytes..(rect.y + rect.height) as usize * row_bytes];
+        let chunk_rows = (rect.height as usize).div_ceil(threads).max(1);
+        std::thread::scope(|scope| {
+            for (i, chunk) in rows.chunks_mut(chunk_rows * row_bytes).enumerate() {
+                let stops = &stops;
+                let inverses = &inverses;
+                scope.spawn(move || {
+                    for (j, row) in chunk.chunks_mut(row_bytes).enumerate() {
+                        let y = rect.y + (i * chunk_rows + j) as u32;
+                        let v = (y as f32 + 0.5) / tex_h as f32;
+                        for x in rect.x..rect.x + rect.width {
+                            // 選択範囲の外は塗らない
+                            let clip = selection.coverage(x, y);
+                            if clip <= 0.0 {
+                                continue;
+                            }
+                            let direction = SphereProjection::tex_to_direction(
+                                (x as f32 + 0.5) / tex_w as f32,
+                                v,
+                            );
+                            let direction = inverses
+                                .iter()
+                                .map(|inverse| *inverse * direction)
+                                .max_by(|a, b| a.dot(field.start).total_cmp(&b.dot(field.start)))
+                                .unwrap_or(direction);
+                            let mut t = field.parameter(direction);
+                            if settings.reverse {
+                                t = 1.0 - t;
+                            }
+
+                            let pixel = &mut row[x as usize * 4..x as usize * 4 + 4];
+                            let color = layer::composite(
+                                Rgba([pixel[0], pixel[1], pixel[2], pixel[3]]),
+                                Gradient::color_at(stops, t),
+                                settings.opacity * clip,
+                                BlendMode::Normal,
+                            );
+                            pixel.copy_from_slice(&color.0);
+                        }
+                    }
+                });
+            }
+        });
+    }
+
+    // テクスチャの更新範囲
+    canvas_state.mark_modified(&rw_image, &area);
+}
+
+#[derive(Debug)]
+pub struct GradientTool {
+    pub name: String,
+    pub icon: char,
+
+    pub settings: RwLock<GradientSettings>,
+    // ドラッグ中の始点と終点
+    drag: RwLock<Option<(Vec3, Vec3)>>,
+}
+
+impl GradientTool {
+    pub fn new() -> Self {
+        Self {
+            name: "Gradient".to_string(),
+            icon: '\u{f3ab}',
+
+            settings: RwLock::new(GradientSettings::default()),
+            drag: RwLock::new(None),
+        }
+    }
+
+    fn mode(&self) -> GradientMode {
+        self.settings
+            .read()
+            .map(|settings| settings.mode)
+            .unwrap_or_default()
+    }
+
+    /**
+     * マウスの位置を終点にして、プレビューを更新する
+     */
+    fn drag_to_mouse(&self, canvas_state: &Arc<RwLock<SphereCanvasState>>, start: bool) -> Status {
+        let Ok(mut canvas_state) = canvas_state.try_write() else {
+            return Status::Ignored;
+        };
+        if canvas_state.mouse_button != Some(mouse::Button::Left) {
+            return Status::Ignored;
+        }
+        let Ok(mut drag) = self.drag.write() else {
+            return Status::Captured;
+        };
+
+        let proj = canvas_state.sphere_projection();
+        let mp = canvas_state.get_mouse_coord_in_view();
+        let direction = proj.view_to_direction(mp.x, mp.y);
+
+        *drag = match (*drag, direction) {
+            (_, Some(direction)) if start => Some((direction, direction)),
+            (Some((from, _)), Some(direction)) => Some((from, direction)),
+            (drag, _) => drag,
+        };
+
+        if let Some((from, to)) = *drag {
+            canvas_state.overlay = GradientField::new(self.mode(), from, to).overlay(to);
+        }
+
+        Status::Captured
+    }
+}
+
+impl Tool for GradientTool {
+    fn name(&self) -> &str {
+        &self.name
+    }
+    fn icon(&self) -> char {
+        self.icon
+    }
+
+    fn paints(&self) -> bool {
+        true
+    }
+
+    fn settings(&self) -> Option<serde_json::Value> {
+        let settings = self.settings.read().ok()?.clone();
+        serde_json::to_value(settings).ok()
+    }
+
+    fn load_settings(&self, settings: &serde_json::Value) {
+        if let Ok(loaded) = GradientSettings::deserialize(settings) {
+            if let Ok(mut settings) = self.settings.write() {
+                *settings = loaded;
+            }
+        }
+    }
+
+    fn on_mouse_pressed(&self, canvas_state: &Arc<RwLock<SphereCanvasState>>) -> Status {
+        self.drag_to_mouse(canvas_state, true)
+    }
+
+    fn on_mouse_released(&self, canvas_state: &Arc<RwLock<SphereCanvasState>>) -> Status {
+        let Some((from, to)) = self.drag.write().ok().and_then(|mut drag| drag.take()) else {
+            return Status::Ignored;
+        };
+        let Ok(mut canvas_state) = canvas_state.write() else {
+            return Status::Captured;
+        };
+        canvas_state.overlay.clear();
+
+        // ドラッグせずに離した場合は向きが決まらないので描画しない
+        if from.angle_between(to) <= 1e-6 {
+            return Status::Captured;
+        }
+        let Ok(settings) = self.settings.read().map(|settings| settings.clone()) else {
+            return Status::Captured;
+        };
+
+        let field = GradientField::new(settings.mode, from, to);
+        draw_gradient(&mut canvas_state, &field, &settings);
+
+        Status::Captured
+    }
+
+    fn on_mouse_moved(&self, canvas_state: &Arc<RwLock<SphereCanvasState>>) -> Status {
+        self.drag_to_mouse(canvas_state, false)
+    }
+}
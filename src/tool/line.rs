@@ -0,0 +1,181 @@
+use std::f32::consts::{PI, TAU};
+use std::sync::{Arc, RwLock};
+
+use glam::Vec3;
+use iced::advanced::graphics::core::event::Status;
+use iced::mouse;
+use serde::{Deserialize, Serialize};
+
+use crate::math::arc;
+use crate::math::projection::SphereProjection;
+use crate::tool::Tool;
+use crate::tool::brush::{self, BrushSettings, Stroke};
+use crate::widget::sphere_canvas::SphereCanvasState;
+use crate::widget::sphere_canvas::overlay::OverlayPath;
+
+/// 線を折れ線で近似する間隔 (ラジアン)
+pub const ARC_STEP: f32 = PI / 360.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LineSettings {
+    #[serde(flatten)]
+    pub brush: BrushSettings,
+}
+
+/**
+ * start から end までの線が通る点を返す
+ * constrain なら緯線 (東西) と経線 (南北) のうち、end に向かう距離が長い方に沿わせる
+ */
+pub fn line_path(start: Vec3, end: Vec3, constrain: bool) -> Vec<Vec3> {
+    if !constrain {
+        return arc::great_circle(start, end, ARC_STEP);
+    }
+
+    let (start_lon, start_lat) = SphereProjection::direction_to_latlng(start);
+    let (end_lon, end_lat) = SphereProjection::direction_to_latlng(end);
+    let d_lon = (end_lon - start_lon + PI).rem_euclid(TAU) - PI;
+
+    if (d_lon * start_lat.cos()).abs() >= (end_lat - start_lat).abs() {
+        arc::parallel(start_lat, start_lon, end_lon, ARC_STEP)
+    } else {
+        let end = SphereProjection::latlng_to_direction(start_lon, end_lat);
+        arc::great_circle(start, end, ARC_STEP)
+    }
+}
+
+#[derive(Debug)]
+pub struct LineTool {
+    pub name: String,
+    pub icon: char,
+
+    pub settings: RwLock<LineSettings>,
+    // ドラッグ中の線の始点と終点
+    drag: RwLock<Option<(Vec3, Vec3)>>,
+}
+
+impl LineTool {
+    pub fn new() -> Self {
+        Self {
+            name: "Line".to_string(),
+            icon: '\u{ec40}',
+
+            settings: RwLock::new(LineSettings::default()),
+            drag: RwLock::new(None),
+        }
+    }
+
+    /**
+     * マウスの位置を線の終点にして、プレビューを更新する
+     */
+    fn drag_to_mouse(&self, canvas_state: &Arc<RwLock<SphereCanvasState>>, start: bool) -> Status {
+        let Ok(mut canvas_state) = canvas_state.try_write() else {
+            return Status::Ignored;
+        };
+        if canvas_state.mouse_button != Some(mouse::Button::Left) {
+            return Status::Ignored;
+        }
+        let Ok(mut drag) = self.drag.write() else {
+            return Status::Captured;
+        };
+
+        let proj = canvas_state.sphere_projection();
+        let mp = canvas_state.get_mouse_coord_in_view();
+        let direction = proj.view_to_direction(mp.x, mp.y);
+
+        *drag = match (*drag, direction) {
+            (_, Some(direction)) if start => Some((direction, direction)),
+            (Some((from, _)), Some(direction)) => Some((from, direction)),
+            (drag, _) => drag,
+        };
+
+        if let Some((from, to)) = *drag {
+            let constrain = canvas_state.modifiers.shift();
+            canvas_state.overlay = vec![OverlayPath {
+                points: line_path(from, to, constrain),
+                closed: false,
+            }];
+        }
+
+        Status::Captured
+    }
+}
+
+impl Tool for LineTool {
+    fn name(&self) -> &str {
+        &self.name
+    }
+    fn icon(&self) -> char {
+        self.icon
+    }
+
+    fn paints(&self) -> bool {
+        true
+    }
+
+    fn uses_foreground(&self) -> bool {
+        true
+    }
+
+    fn settings(&self) -> Option<serde_json::Value> {
+        let settings = *self.settings.read().ok()?;
+        serde_json::to_value(settings).ok()
+    }
+
+    fn load_settings(&self, settings: &serde_json::Value) {
+        if let Ok(loaded) = LineSettings::deserialize(settings) {
+            if let Ok(mut settings) = self.settings.write() {
+                *settings = loaded;
+            }
+        }
+    }
+
+    fn brush(&self) -> Option<BrushSettings> {
+        self.settings.read().ok().map(|settings| settings.brush)
+    }
+
+    fn set_brush(&self, brush: BrushSettings) {
+        if let Ok(mut settings) = self.settings.write() {
+            settings.brush = brush;
+        }
+    }
+
+    fn on_mouse_pressed(&self, canvas_state: &Arc<RwLock<SphereCanvasState>>) -> Status {
+        self.drag_to_mouse(canvas_state, true)
+    }
+
+    fn on_mouse_released(&self, canvas_state: &Arc<RwLock<SphereCanvasState>>) -> Status {
+        let Some((from, to)) = self.drag.write().ok().and_then(|mut drag| drag.take()) else {
+            return Status::Ignored;
+        };
+        let settings = self
+            .settings
+            .read()
+            .map(|settings| *settings)
+            .unwrap_or_default();
+
+        let Ok(mut canvas_state) = canvas_state.write() else {
+            return Status::Captured;
+        };
+        canvas_state.overlay.clear();
+
+        // 線全体を1回のストロークとして塗る
+        let path = line_path(from, to, canvas_state.modifiers.shift());
+        let color = canvas_state.foreground;
+        brush::stroke_path(
+            &mut canvas_state,
+            &mut Stroke::default(),
+            &path,
+            &settings.brush,
+            |stroke, image, x, y, amount| {
+                stroke.paint(image, x, y, amount, color, settings.brush.opacity)
+            },
+        );
+
+        Status::Captured
+    }
+
+    fn on_mouse_moved(&self, canvas_state: &Arc<RwLock<SphereCanvasState>>) -> Status {
+        self.drag_to_mouse(canvas_state, false)
+    }
+}
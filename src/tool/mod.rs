@@ -3,10 +3,23 @@ use iced::advanced::graphics::core::event::Status;
 use std::sync::Arc;
 use std::sync::RwLock;
 
+use crate::tool::brush::BrushSettings;
 use crate::widget::sphere_canvas::SphereCanvasState;
 
+pub mod brush;
+pub mod clone;
+pub mod eraser;
+pub mod eyedropper;
+pub mod fill;
+pub mod gradient;
+pub mod line;
 pub mod pan;
 pub mod pen;
+pub mod retouch;
+pub mod select;
+pub mod shape;
+pub mod text;
+pub mod transform;
 pub mod zoom;
 
 pub trait Tool {
@@ -28,6 +41,49 @@ pub trait Tool {
     fn on_wheel(&self, _canvas_state: &Arc<RwLock<SphereCanvasState>>) -> Status {
         Status::Ignored
     }
+
+    /**
+     * 画像に描画するツールか (Altキーを押している間はスポイトに切り替える)
+     */
+    fn paints(&self) -> bool {
+        false
+    }
+
+    /**
+     * 描画色で塗るツールか (使った色を最近使った色に加える)
+     */
+    fn uses_foreground(&self) -> bool {
+        false
+    }
+
+    /**
+     * プロジェクトファイルに保存するツールの設定 (設定が無いツールは None)
+     */
+    fn settings(&self) -> Option<serde_json::Value> {
+        None
+    }
+
+    /**
+     * settings() で保存した設定を復元する
+     */
+    fn load_settings(&self, _settings: &serde_json::Value) {}
+
+    /**
+     * オプションのパネルで編集するブラシの設定 (ブラシで描かないツールは None)
+     */
+    fn brush(&self) -> Option<BrushSettings> {
+        None
+    }
+
+    /**
+     * brush() で取得して編集したブラシの設定を反映する
+     */
+    fn set_brush(&self, _brush: BrushSettings) {}
+
+    /**
+     * 別のツールに切り替えるときに、描画途中の状態を捨てる
+     */
+    fn deactivate(&self) {}
 }
 
 #[derive(Clone)]
@@ -0,0 +1,308 @@
+use std::f32::consts::TAU;
+use std::sync::{Arc, RwLock};
+
+use glam::{Quat, Vec3};
+use iced::advanced::graphics::core::event::Status;
+use image::{Rgba, RgbaImage};
+use serde::{Deserialize, Serialize};
+
+use crate::color;
+use crate::math::projection::SphereProjection;
+use crate::math::sample;
+use crate::math::shape;
+use crate::tool::Tool;
+use crate::tool::brush::{self, BrushSettings, Stroke};
+use crate::widget::sphere_canvas::SphereCanvasState;
+
+/// ぼかしで混ぜる点を並べる格子の、中心から端までの点の数 (格子の角は使わず円形にする)
+const KERNEL_STEPS: i32 = 2;
+/// 覆い焼き・焼き込みで明るさを変えるガンマ値 (強さ 1.0 の場合)
+const TONE_GAMMA: f32 = 2.0;
+
+/// レタッチブラシの種類
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RetouchKind {
+    /// 前のスタンプの位置の色を引きずる
+    Smudge,
+    /// 周りの色と混ぜてぼかす
+    Blur,
+    /// 周りの色との差を強める
+    Sharpen,
+    /// 明るくする (覆い焼き)
+    Dodge,
+    /// 暗くする (焼き込み)
+    Burn,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RetouchSettings {
+    #[serde(flatten)]
+    pub brush: BrushSettings,
+    /// 1回のスタンプで効果を掛ける強さ (0.0 ... 1.0)
+    pub strength: f32,
+    /// ぼかし・シャープで混ぜる範囲の半径 (赤道上での画像のピクセル数)
+    pub kernel: f32,
+}
+
+impl Default for RetouchSettings {
+    fn default() -> Self {
+        Self {
+            brush: BrushSettings {
+                width: 10.0,
+                hardness: 0.5,
+                ..BrushSettings::default()
+            },
+            strength: 0.5,
+            kernel: 2.0,
+        }
+    }
+}
+
+/// スタンプが覆うピクセルの色を、周りの色から求めた色に近づけるブラシ
+///
+/// 周りの色は画像のピクセルの並びではなく球面上の角度で決まる範囲から集めるので、
+/// 極の付近でも効果が横に伸びず、viewで見て丸い範囲に掛かる。
+#[derive(Debug)]
+pub struct RetouchTool {
+    pub name: String,
+    pub icon: char,
+    pub kind: RetouchKind,
+
+    pub settings: RwLock<RetouchSettings>,
+    stroke: RwLock<Stroke>,
+    /// 指先ツールで最後にスタンプした方向 (対称な位置に複製したスタンプごと)
+    last_centers: RwLock<Vec<Option<Vec3>>>,
+}
+
+impl RetouchTool {
+    pub fn new(kind: RetouchKind) -> Self {
+        let (name, icon) = match kind {
+            RetouchKind::Smudge => ("Smudge", '\u{ee94}'),
+            RetouchKind::Blur => ("Blur", '\u{ef8c}'),
+            RetouchKind::Sharpen => ("Sharpen", '\u{f6d7}'),
+            RetouchKind::Dodge => ("Dodge", '\u{eb7e}'),
+            RetouchKind::Burn => ("Burn", '\u{eb7d}'),
+        };
+
+        Self {
+            name: name.to_string(),
+            icon,
+            kind,
+
+            settings: RwLock::new(RetouchSettings::default()),
+            stroke: RwLock::new(Stroke::default()),
+            last_centers: RwLock::new(Vec::new()),
+        }
+    }
+
+    fn current_settings(&self) -> RetouchSettings {
+        self.settings
+            .read()
+            .map(|settings| *settings)
+            .unwrap_or_default()
+    }
+
+    /**
+     * 前回のスタンプ位置からマウスの位置までスタンプを並べて効果を掛ける
+     */
+    fn retouch_to_mouse(&self, canvas_state: &Arc<RwLock<SphereCanvasState>>) -> Status {
+        let settings = self.current_settings();
+        // ブラシの不透明度も効果の掛かり方に含める
+        let strength = settings.strength.clamp(0.0, 1.0) * settings.brush.opacity.clamp(0.0, 1.0);
+
+        brush::dabs_to_mouse(
+            canvas_state,
+            &self.stroke,
+            &settings.brush,
+            |stroke, image, center, pixels| {
+                let (width, height) = image.dimensions();
+
+                // 指先ツールは、今のスタンプの各ピクセルを前のスタンプの同じ位置に移す回転で色を拾う
+                let smudge = if self.kind == RetouchKind::Smudge {
+                    let from = self.last_centers.write().ok().and_then(|mut last| {
+                        let replica = stroke.replica();
+                        if last.len() <= replica {
+                            last.resize(replica + 1, None);
+                        }
+                        last[replica].replace(center)
+                    });
+                    match from {
+                        Some(from) => Some(Quat::from_rotation_arc(center, from)),
+                        None => return,
+                    }
+                } else {
+                    None
+                };
+                let kernel = kernel(settings.kernel.max(0.5) * TAU / width as f32);
+
+                // 全てのピクセルの色を求めてから書き込む
+                let colors: Vec<Rgba<u8>> = pixels
+                    .iter()
+                    .map(|&(x, y, _)| {
+                        let color = *image.get_pixel(x, y);
+                        let direction = SphereProjection::tex_to_direction(
+                            (x as f32 + 0.5) / width as f32,
+                            (y as f32 + 0.5) / height as f32,
+                        );
+                        match (self.kind, smudge) {
+                            (RetouchKind::Smudge, Some(rotation)) => {
+                                sample_direction(image, rotation * direction)
+                            }
+                            (RetouchKind::Blur, _) => blur(image, direction, &kernel),
+                            (RetouchKind::Sharpen, _) => {
+                                sharpen(color, blur(image, direction, &kernel))
+                            }
+                            (RetouchKind::Dodge, _) => tone(color, 1.0 / TONE_GAMMA),
+                            (RetouchKind::Burn, _) => tone(color, TONE_GAMMA),
+                            _ => color,
+                        }
+                    })
+                    .collect();
+
+                for (&(x, y, amount), target) in pixels.iter().zip(colors) {
+                    let pixel = *image.get_pixel(x, y);
+                    image.put_pixel(x, y, color::mix(pixel, target, amount * strength));
+                }
+            },
+        )
+    }
+}
+
+/**
+ * 球面上の方向 direction の色を補間して求める
+ */
+fn sample_direction(image: &RgbaImage, direction: Vec3) -> Rgba<u8> {
+    let tex = SphereProjection::direction_to_tex(direction);
+    sample::bilinear(
+        tex.x * image.width() as f32,
+        tex.y * image.height() as f32,
+        |x, y| sample::equirect_pixel(image, x, y),
+    )
+}
+
+/**
+ * 角度 radius までの円の中に並べた、接平面上の点 (u, v) と重み
+ * 重みは中心からの角度に応じたガウス関数 (半径を標準偏差の2倍とする) で、合計が 1.0 になる
+ */
+fn kernel(radius: f32) -> Vec<(f32, f32, f32)> {
+    let step = radius / KERNEL_STEPS as f32;
+    let limit = KERNEL_STEPS * KERNEL_STEPS;
+
+    let mut points = Vec::new();
+    for j in -KERNEL_STEPS..=KERNEL_STEPS {
+        for i in -KERNEL_STEPS..=KERNEL_STEPS {
+            let distance = i * i + j * j;
+            if distance <= limit {
+                let weight = (-2.0 * distance as f32 / limit as f32).exp();
+                points.push((i as f32 * step, j as f32 * step, weight));
+            }
+        }
+    }
+
+    let total: f32 = points.iter().map(|(_, _, weight)| weight).sum();
+    for (_, _, weight) in points.iter_mut() {
+        *weight /= total;
+    }
+    points
+}
+
+/**
+ * direction の周りの色を kernel の重みで平均する
+ * 接平面上の点を球面に戻して色を集めるので、画像のどこでも同じ形の範囲になる
+ */
+fn blur(image: &RgbaImage, direction: Vec3, kernel: &[(f32, f32, f32)]) -> Rgba<u8> {
+    let (u, v) = shape::tangent_basis(direction);
+
+    let mut sum = [0.0f32; 4];
+    for &(du, dv, weight) in kernel {
+        let point = (direction + u * du + v * dv).normalize();
+        sample::accumulate(&mut sum, sample_direction(image, point), weight);
+    }
+    sample::unpremultiply(sum)
+}
+
+/**
+ * ぼかした色との差を足して輪郭を強める (アンシャープマスク)
+ */
+fn sharpen(color: Rgba<u8>, blurred: Rgba<u8>) -> Rgba<u8> {
+    let mut sharpened = color;
+    for (channel, blurred) in sharpened.0[..3].iter_mut().zip(&blurred.0[..3]) {
+        let value = 2.0 * *channel as f32 - *blurred as f32;
+        *channel = value.round().clamp(0.0, 255.0) as u8;
+    }
+    sharpened
+}
+
+/**
+ * 明るさをガンマ値 gamma で変える (1.0 より小さいと明るく、大きいと暗くなる)
+ * 白と黒はそのままで、中間の明るさほど大きく変わる
+ */
+fn tone(color: Rgba<u8>, gamma: f32) -> Rgba<u8> {
+    let mut toned = color;
+    for channel in toned.0[..3].iter_mut() {
+        let value = (*channel as f32 / 255.0).powf(gamma) * 255.0;
+        *channel = value.round().clamp(0.0, 255.0) as u8;
+    }
+    toned
+}
+
+impl Tool for RetouchTool {
+    fn name(&self) -> &str {
+        &self.name
+    }
+    fn icon(&self) -> char {
+        self.icon
+    }
+
+    fn paints(&self) -> bool {
+        true
+    }
+
+    fn settings(&self) -> Option<serde_json::Value> {
+        let settings = *self.settings.read().ok()?;
+        serde_json::to_value(settings).ok()
+    }
+
+    fn load_settings(&self, settings: &serde_json::Value) {
+        if let Ok(loaded) = RetouchSettings::deserialize(settings) {
+            if let Ok(mut settings) = self.settings.write() {
+                *settings = loaded;
+            }
+        }
+    }
+
+    fn brush(&self) -> Option<BrushSettings> {
+        self.settings.read().ok().map(|settings| settings.brush)
+    }
+
+    fn set_brush(&self, brush: BrushSettings) {
+        if let Ok(mut settings) = self.settings.write() {
+            settings.brush = brush;
+        }
+    }
+
+    fn on_mouse_pressed(&self, canvas_state: &Arc<RwLock<SphereCanvasState>>) -> Status {
+        // 新しいストロークを始める
+        if let Ok(mut stroke) = self.stroke.write() {
+            *stroke = Stroke::default();
+        }
+        if let Ok(mut last_centers) = self.last_centers.write() {
+            last_centers.clear();
+        }
+
+        self.retouch_to_mouse(canvas_state)
+    }
+
+    fn on_mouse_released(&self, _canvas_state: &Arc<RwLock<SphereCanvasState>>) -> Status {
+        if let Ok(mut stroke) = self.stroke.write() {
+            *stroke = Stroke::default();
+        }
+
+        Status::Ignored
+    }
+
+    fn on_mouse_moved(&self, canvas_state: &Arc<RwLock<SphereCanvasState>>) -> Status {
+        self.retouch_to_mouse(canvas_state)
+    }
+}
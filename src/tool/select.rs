@@ -0,0 +1,293 @@
+use std::sync::{Arc, RwLock};
+
+use glam::Vec2;
+use iced::advanced::graphics::core::event::Status;
+use iced::mouse;
+use serde::{Deserialize, Serialize};
+
+use crate::math::shape::SphereShape;
+use crate::selection::{self, SelectionMode};
+use crate::tool::Tool;
+use crate::tool::fill;
+use crate::tool::line::ARC_STEP;
+use crate::widget::sphere_canvas::SphereCanvasState;
+use crate::widget::sphere_canvas::overlay::OverlayPath;
+
+/// 投げ縄の頂点を加える、前の頂点からの距離 (viewのピクセル数)
+const LASSO_SPACING: f32 = 4.0;
+
+/// 選択ツールの種類
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SelectKind {
+    /// 押した位置と離した位置を角とするview上の矩形
+    Marquee,
+    /// ドラッグした軌跡を辺とする多角形
+    Lasso,
+    /// クリックした位置と似た色の範囲
+    MagicWand,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SelectSettings {
+    /// 今の選択範囲との組み合わせ方 (Shift で加える, Alt で除く, 両方で重なる範囲にする)
+    pub mode: SelectionMode,
+    /// 選択範囲の縁をぼかす
+    pub antialias: bool,
+    /// 自動選択で同じ色とみなす各チャンネルの差の上限 (0.0 ... 1.0)
+    pub tolerance: f32,
+    /// 自動選択でクリックした位置から繋がっているピクセルだけを選択する
+    pub contiguous: bool,
+}
+
+impl Default for SelectSettings {
+    fn default() -> Self {
+        Self {
+            mode: SelectionMode::Replace,
+            antialias: true,
+            tolerance: 0.1,
+            contiguous: true,
+        }
+    }
+}
+
+/// ドラッグ中の選択範囲
+#[derive(Debug, Clone, Default)]
+enum Drag {
+    #[default]
+    None,
+    /// 角の view座標
+    Marquee { from: Vec2, to: Vec2 },
+    /// 軌跡の view座標
+    Lasso { points: Vec<Vec2> },
+}
+
+#[derive(Debug)]
+pub struct SelectTool {
+    pub name: String,
+    pub icon: char,
+    pub kind: SelectKind,
+
+    pub settings: RwLock<SelectSettings>,
+    drag: RwLock<Drag>,
+}
+
+impl SelectTool {
+    pub fn new(kind: SelectKind) -> Self {
+        let (name, icon) = match kind {
+            SelectKind::Marquee => ("Marquee", '\u{eeae}'),
+            SelectKind::Lasso => ("Lasso", '\u{efac}'),
+            SelectKind::MagicWand => ("Magic Wand", '\u{ebcb}'),
+        };
+
+        Self {
+            name: name.to_string(),
+            icon,
+            kind,
+
+            settings: RwLock::new(SelectSettings::default()),
+            drag: RwLock::new(Drag::None),
+        }
+    }
+
+    fn current_settings(&self) -> SelectSettings {
+        self.settings
+            .read()
+            .map(|settings| *settings)
+            .unwrap_or_default()
+    }
+
+    /**
+     * 修飾キーで切り替えた組み合わせ方
+     */
+    fn mode(&self, canvas_state: &SphereCanvasState) -> SelectionMode {
+        let modifiers = canvas_state.modifiers;
+        match (modifiers.shift(), modifiers.alt()) {
+            (true, true) => SelectionMode::Intersect,
+            (true, false) => SelectionMode::Union,
+            (false, true) => SelectionMode::Subtract,
+            (false, false) => self.current_settings().mode,
+        }
+    }
+
+    /**
+     * ドラッグ中の範囲を球面上の図形にする
+     */
+    fn drag_shape(canvas_state: &SphereCanvasState, drag: &Drag) -> Option<SphereShape> {
+        let proj = canvas_state.sphere_projection();
+        match drag {
+            Drag::None => None,
+            Drag::Marquee { from, to } => Some(SphereShape::view_rectangle(&proj, *from, *to)),
+            Drag::Lasso { points } => Some(SphereShape::view_path(&proj, points.clone())),
+        }
+    }
+
+    /**
+     * ドラッグ中の範囲のプレビュー
+     */
+    fn drag_overlay(canvas_state: &SphereCanvasState, drag: &Drag) -> Vec<OverlayPath> {
+        Self::drag_shape(canvas_state, drag)
+            .map(|shape| OverlayPath {
+                points: shape.outline(ARC_STEP),
+                closed: true,
+            })
+            .into_iter()
+            .collect()
+    }
+
+    /**
+     * クリックした位置と似た色の範囲を選択する
+     */
+    fn select_color(&self, canvas_state: &mut SphereCanvasState) {
+        let settings = self.current_settings();
+        let mode = self.mode(canvas_state);
+
+        let proj = canvas_state.sphere_projection();
+        let mp = canvas_state.get_mouse_coord_in_view();
+        let Some(tex) = proj.proj(mp.x, mp.y) else {
+            return;
+        };
+        let Some(rw_image) = canvas_state.active_image() else {
+            return;
+        };
+        let Ok(image) = rw_image.read() else {
+            return;
+        };
+        let tex_w = image.width();
+        let tex_h = image.height();
+        if tex_w == 0 || tex_h == 0 {
+            return;
+        }
+        let seed = (
+            ((tex.x.rem_euclid(1.0) * tex_w as f32) as u32).min(tex_w - 1),
+            ((tex.y.clamp(0.0, 1.0) * tex_h as f32) as u32).min(tex_h - 1),
+        );
+
+        let fill = fill::flood_fill(&image, seed, settings.tolerance, settings.contiguous);
+        drop(image);
+        canvas_state
+            .selection
+            .combine(mode, selection::fill_mask(&fill, settings.antialias));
+    }
+}
+
+impl Tool for SelectTool {
+    fn name(&self) -> &str {
+        &self.name
+    }
+    fn icon(&self) -> char {
+        self.icon
+    }
+
+    fn settings(&self) -> Option<serde_json::Value> {
+        let settings = *self.settings.read().ok()?;
+        serde_json::to_value(settings).ok()
+    }
+
+    fn load_settings(&self, settings: &serde_json::Value) {
+        if let Ok(loaded) = SelectSettings::deserialize(settings) {
+            if let Ok(mut settings) = self.settings.write() {
+                *settings = loaded;
+            }
+        }
+    }
+
+    fn on_mouse_pressed(&self, canvas_state: &Arc<RwLock<SphereCanvasState>>) -> Status {
+        let Ok(mut canvas_state) = canvas_state.try_write() else {
+            return Status::Ignored;
+        };
+        if canvas_state.mouse_button != Some(mouse::Button::Left) {
+            return Status::Ignored;
+        }
+        let Ok(mut drag) = self.drag.write() else {
+            return Status::Captured;
+        };
+
+        let mp = canvas_state.get_mouse_coord_in_view();
+        match self.kind {
+            SelectKind::Marquee => *drag = Drag::Marquee { from: mp, to: mp },
+            SelectKind::Lasso => *drag = Drag::Lasso { points: vec![mp] },
+            SelectKind::MagicWand => self.select_color(&mut canvas_state),
+        }
+
+        Status::Captured
+    }
+
+    fn on_mouse_released(&self, canvas_state: &Arc<RwLock<SphereCanvasState>>) -> Status {
+        let Some(drag) = self
+            .drag
+            .write()
+            .ok()
+            .map(|mut drag| std::mem::take(&mut *drag))
+        else {
+            return Status::Ignored;
+        };
+        if matches!(drag, Drag::None) {
+            return Status::Ignored;
+        }
+        let Ok(mut canvas_state) = canvas_state.write() else {
+            return Status::Captured;
+        };
+        canvas_state.overlay.clear();
+
+        let settings = self.current_settings();
+        let mode = self.mode(&canvas_state);
+
+        // ドラッグせずにクリックしたら選択を解除する
+        let clicked = match &drag {
+            Drag::Marquee { from, to } => from == to,
+            Drag::Lasso { points } => points.len() < 3,
+            Drag::None => true,
+        };
+        if clicked {
+            if mode == SelectionMode::Replace {
+                canvas_state.selection.clear();
+            }
+            return Status::Captured;
+        }
+
+        if let Some(shape) = Self::drag_shape(&canvas_state, &drag) {
+            let mask = selection::shape_mask(
+                &shape,
+                canvas_state.image_width,
+                canvas_state.image_height,
+                settings.antialias,
+            );
+            canvas_state.selection.combine(mode, mask);
+        }
+
+        Status::Captured
+    }
+
+    fn on_mouse_moved(&self, canvas_state: &Arc<RwLock<SphereCanvasState>>) -> Status {
+        let Ok(mut canvas_state) = canvas_state.try_write() else {
+            return Status::Ignored;
+        };
+        if canvas_state.mouse_button != Some(mouse::Button::Left) {
+            return Status::Ignored;
+        }
+        let Ok(mut drag) = self.drag.write() else {
+            return Status::Ignored;
+        };
+
+        let mp = canvas_state.get_mouse_coord_in_view();
+        match &mut *drag {
+            Drag::None => return Status::Ignored,
+            Drag::Marquee { to, .. } => *to = mp,
+            Drag::Lasso { points } => {
+                let bounds = canvas_state.viewport_bounds;
+                let to_pixel =
+                    |view: Vec2| Vec2::new(view.x * bounds.width, view.y * bounds.height);
+                let far = points
+                    .last()
+                    .is_none_or(|last| to_pixel(*last).distance(to_pixel(mp)) >= LASSO_SPACING);
+                if far {
+                    points.push(mp);
+                }
+            }
+        }
+        canvas_state.overlay = Self::drag_overlay(&canvas_state, &drag);
+
+        Status::Captured
+    }
+}
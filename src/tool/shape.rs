@@ -0,0 +1,431 @@
+use std::sync::{Arc, RwLock};
+
+use glam::{Vec2, Vec3};
+use iced::advanced::graphics::core::event::Status;
+use iced::mouse;
+use serde::{Deserialize, Serialize};
+
+use crate::layer::{self, BlendMode};
+use crate::math::arc;
+use crate::math::projection::SphereProjection;
+use crate::math::region::DirtyRegion;
+use crate::math::shape::SphereShape;
+use crate::tool::Tool;
+use crate::tool::brush::{self, BrushSettings, Stroke};
+use crate::tool::line::ARC_STEP;
+use crate::widget::sphere_canvas::SphereCanvasState;
+use crate::widget::sphere_canvas::overlay::OverlayPath;
+
+/// 多角形の最初の頂点をクリックして閉じる距離 (viewのピクセル数)
+const CLOSE_DISTANCE: f32 = 8.0;
+
+/// 図形の種類
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShapeKind {
+    /// 押した位置を中心に、離した位置までを半径とする球冠
+    Circle,
+    /// 押した位置と離した位置を角とするview上の矩形
+    Rectangle,
+    /// クリックした位置を頂点とし、辺を大円で結んだ多角形
+    Polygon,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ShapeSettings {
+    /// 輪郭を描画色で描く
+    pub stroke: bool,
+    /// 内側を背景色で塗る
+    pub fill: bool,
+    /// 輪郭を描くブラシ (不透明度とアンチエイリアスは塗りにも使う)
+    #[serde(flatten)]
+    pub brush: BrushSettings,
+}
+
+impl Default for ShapeSettings {
+    fn default() -> Self {
+        Self {
+            stroke: true,
+            fill: false,
+            brush: BrushSettings::default(),
+        }
+    }
+}
+
+/// 描画中の図形
+#[derive(Debug, Clone, Default)]
+enum Drawing {
+    #[default]
+    None,
+    Circle {
+        center: Vec3,
+        edge: Vec3,
+    },
+    /// 角の view座標
+    Rectangle {
+        from: Vec2,
+        to: Vec2,
+    },
+    /// クリックした頂点と、次の頂点の候補 (マウスの位置)
+    Polygon {
+        vertices: Vec<Vec3>,
+        cursor: Vec3,
+    },
+}
+
+impl Drawing {
+    fn shape(&self, proj: &SphereProjection) -> Option<SphereShape> {
+        match self {
+            Drawing::None => None,
+            Drawing::Circle { center, edge } => Some(SphereShape::Cap {
+                center: *center,
+                radius: center.angle_between(*edge),
+            }),
+            Drawing::Rectangle { from, to } => Some(SphereShape::view_rectangle(proj, *from, *to)),
+            Drawing::Polygon { vertices, .. } => Some(SphereShape::polygon(vertices.clone())),
+        }
+    }
+
+    /**
+     * 描画中の図形のプレビュー
+     */
+    fn overlay(&self, proj: &SphereProjection) -> Vec<OverlayPath> {
+        match self {
+            Drawing::None => Vec::new(),
+            // 多角形は閉じる前なので、頂点とマウスの位置を順に結ぶ
+            Drawing::Polygon { vertices, cursor } => {
+                let mut points = Vec::new();
+                for (a, b) in vertices.iter().zip(vertices.iter().skip(1).chain([cursor])) {
+                    points.append(&mut arc::great_circle(*a, *b, ARC_STEP));
+                }
+                vec![OverlayPath {
+                    points,
+                    closed: false,
+                }]
+            }
+            _ => self
+                .shape(proj)
+                .map(|shape| OverlayPath {
+                    points: shape.outline(ARC_STEP),
+                    closed: true,
+                })
+                .into_iter()
+                .collect(),
+        }
+    }
+}
+
+/**
+ * 図形をアクティブなレイヤーに描画する
+ * 内側を背景色で塗ってから、輪郭を描画色のブラシで描く
+ * 対称の設定があれば、対称な位置の図形も塗る (輪郭はブラシのスタンプごとに複製される)
+ */
+pub fn draw_shape(
+    canvas_state: &mut SphereCanvasState,
+    shape: &SphereShape,
+    settings: &ShapeSettings,
+) {
+    let tex_w = canvas_state.image_width;
+    let tex_h = canvas_state.image_height;
+    if tex_w == 0 || tex_h == 0 {
+        return;
+    }
+
+    if settings.fill {
+        let Some(rw_image) = canvas_state.active_image() else {
+            return;
+        };
+        let Ok(mut image) = rw_image.write() else {
+            return;
+        };
+
+        let shapes: Vec<SphereShape> = canvas_state
+            .symmetry
+            .transforms()
+            .into_iter()
+            .map(|transform| shape.transformed(transform))
+            .collect();
+        let mut area = DirtyRegion::new(tex_w, tex_h);
+        for shape in &shapes {
+            area.add(shape.tex_bounds(tex_w, tex_h));
+        }
+
+        // 塗る前に変更範囲を履歴に保存する
+        if let Ok(mut history) = canvas_state.history.write() {
+            for rect in area.rects() {
+                history.record(&rw_image, &image, *rect);
+            }
+        }
+
+        let color = canvas_state.background;
+        let (y_min, y_max) = area.rects().iter().fold((tex_h, 0), |(min, max), rect| {
+            (min.min(rect.y), max.max(rect.y + rect.height))
+        });
+        for y in y_min..y_max {
+            // 行ごとに図形が覆う範囲を求め、重なった図形を二重に塗らないように最も多く覆う図形の分だけ塗る
+            let mut row: Vec<(u32, f32)> = shapes
+                .iter()
+                .flat_map(|shape| shape.row_coverage(y, tex_w, tex_h, settings.brush.antialias))
+                .collect();
+            row.sort_by_key(|(x, _)| *x);
+            row.dedup_by(|next, kept| {
+                if next.0 == kept.0 {
+                    kept.1 = kept.1.max(next.1);
+                    true
+                } else {
+                    false
+                }
+            });
+
+            for (x, coverage) in row {
+                let amount = coverage * canvas_state.selection.coverage(x, y);
+                if amount <= 0.0 {
+                    continue;
+                }
+                let pixel = *image.get_pixel(x, y);
+                image.put_pixel(
+                    x,
+                    y,
+                    layer::composite(
+                        pixel,
+                        color,
+                        settings.brush.opacity * amount,
+                        BlendMode::Normal,
+                    ),
+                );
+            }
+        }
+
+        // テクスチャの更新範囲
+        canvas_state.mark_modified(&rw_image, &area);
+    }
+
+    if settings.stroke {
+        let mut path = shape.outline(ARC_STEP);
+        if let Some(first) = path.first().copied() {
+            path.push(first);
+        }
+        let color = canvas_state.foreground;
+        brush::stroke_path(
+            canvas_state,
+            &mut Stroke::default(),
+            &path,
+            &settings.brush,
+            |stroke, image, x, y, amount| {
+                stroke.paint(image, x, y, amount, color, settings.brush.opacity)
+            },
+        );
+    }
+}
+
+#[derive(Debug)]
+pub struct ShapeTool {
+    pub name: String,
+    pub icon: char,
+    pub kind: ShapeKind,
+
+    pub settings: RwLock<ShapeSettings>,
+    drawing: RwLock<Drawing>,
+}
+
+impl ShapeTool {
+    pub fn new(kind: ShapeKind) -> Self {
+        let (name, icon) = match kind {
+            ShapeKind::Circle => ("Circle", '\u{ea6b}'),
+            ShapeKind::Rectangle => ("Rectangle", '\u{eb2c}'),
+            ShapeKind::Polygon => ("Polygon", '\u{efd0}'),
+        };
+
+        Self {
+            name: name.to_string(),
+            icon,
+            kind,
+
+            settings: RwLock::new(ShapeSettings::default()),
+            drawing: RwLock::new(Drawing::None),
+        }
+    }
+
+    /**
+     * 描画中の図形を画像に描画して終える
+     */
+    fn commit(&self, canvas_state: &mut SphereCanvasState, drawing: &mut Drawing) {
+        let settings = self
+            .settings
+            .read()
+            .map(|settings| *settings)
+            .unwrap_or_default();
+
+        let proj = canvas_state.sphere_projection();
+        if let Some(shape) = std::mem::take(drawing).shape(&proj) {
+            draw_shape(canvas_state, &shape, &settings);
+        }
+        canvas_state.overlay.clear();
+    }
+}
+
+impl Tool for ShapeTool {
+    fn name(&self) -> &str {
+        &self.name
+    }
+    fn icon(&self) -> char {
+        self.icon
+    }
+
+    fn paints(&self) -> bool {
+        true
+    }
+
+    fn uses_foreground(&self) -> bool {
+        // 塗りだけなら背景色しか使わない
+        self.settings
+            .read()
+            .map(|settings| settings.stroke)
+            .unwrap_or(true)
+    }
+
+    fn settings(&self) -> Option<serde_json::Value> {
+        let settings = *self.settings.read().ok()?;
+        serde_json::to_value(settings).ok()
+    }
+
+    fn load_settings(&self, settings: &serde_json::Value) {
+        if let Ok(loaded) = ShapeSettings::deserialize(settings) {
+            if let Ok(mut settings) = self.settings.write() {
+                *settings = loaded;
+            }
+        }
+    }
+
+    fn brush(&self) -> Option<BrushSettings> {
+        self.settings.read().ok().map(|settings| settings.brush)
+    }
+
+    fn set_brush(&self, brush: BrushSettings) {
+        if let Ok(mut settings) = self.settings.write() {
+            settings.brush = brush;
+        }
+    }
+
+    fn deactivate(&self) {
+        // 閉じていない多角形は破棄する
+        if let Ok(mut drawing) = self.drawing.write() {
+            *drawing = Drawing::None;
+        }
+    }
+
+    fn on_mouse_pressed(&self, canvas_state: &Arc<RwLock<SphereCanvasState>>) -> Status {
+        let Ok(mut canvas_state) = canvas_state.try_write() else {
+            return Status::Ignored;
+        };
+        let Ok(mut drawing) = self.drawing.write() else {
+            return Status::Ignored;
+        };
+
+        // 右クリックで多角形を閉じる (頂点が足りなければ取り消す)
+        if canvas_state.mouse_button == Some(mouse::Button::Right) {
+            let Drawing::Polygon { vertices, .. } = &*drawing else {
+                return Status::Ignored;
+            };
+            if vertices.len() >= 3 {
+                self.commit(&mut canvas_state, &mut drawing);
+            } else {
+                *drawing = Drawing::None;
+                canvas_state.overlay.clear();
+            }
+            return Status::Captured;
+        }
+        if canvas_state.mouse_button != Some(mouse::Button::Left) {
+            return Status::Ignored;
+        }
+
+        let proj = canvas_state.sphere_projection();
+        let mp = canvas_state.get_mouse_coord_in_view();
+        let Some(direction) = proj.view_to_direction(mp.x, mp.y) else {
+            return Status::Captured;
+        };
+
+        match (self.kind, &mut *drawing) {
+            (ShapeKind::Polygon, Drawing::Polygon { vertices, cursor }) => {
+                // 最初の頂点の近くをクリックしたら閉じる
+                let bounds = canvas_state.viewport_bounds;
+                let to_pixel =
+                    |view: Vec2| Vec2::new(view.x * bounds.width, view.y * bounds.height);
+                let close = vertices.len() >= 3
+                    && proj.direction_to_view(vertices[0]).is_some_and(|first| {
+                        to_pixel(first).distance(to_pixel(mp)) <= CLOSE_DISTANCE
+                    });
+                if close {
+                    self.commit(&mut canvas_state, &mut drawing);
+                    return Status::Captured;
+                }
+                vertices.push(direction);
+                *cursor = direction;
+            }
+            (ShapeKind::Polygon, _) => {
+                *drawing = Drawing::Polygon {
+                    vertices: vec![direction],
+                    cursor: direction,
+                }
+            }
+            (ShapeKind::Circle, _) => {
+                *drawing = Drawing::Circle {
+                    center: direction,
+                    edge: direction,
+                }
+            }
+            (ShapeKind::Rectangle, _) => *drawing = Drawing::Rectangle { from: mp, to: mp },
+        }
+        canvas_state.overlay = drawing.overlay(&proj);
+
+        Status::Captured
+    }
+
+    fn on_mouse_released(&self, canvas_state: &Arc<RwLock<SphereCanvasState>>) -> Status {
+        let Ok(mut drawing) = self.drawing.write() else {
+            return Status::Ignored;
+        };
+        match &*drawing {
+            Drawing::None => Status::Ignored,
+            // 多角形はクリックごとに頂点を加え、閉じるまで描画を続ける
+            Drawing::Polygon { .. } => Status::Captured,
+            _ => {
+                if let Ok(mut canvas_state) = canvas_state.write() {
+                    self.commit(&mut canvas_state, &mut drawing);
+                }
+                Status::Captured
+            }
+        }
+    }
+
+    fn on_mouse_moved(&self, canvas_state: &Arc<RwLock<SphereCanvasState>>) -> Status {
+        let Ok(mut canvas_state) = canvas_state.try_write() else {
+            return Status::Ignored;
+        };
+        let Ok(mut drawing) = self.drawing.write() else {
+            return Status::Ignored;
+        };
+
+        let proj = canvas_state.sphere_projection();
+        let mp = canvas_state.get_mouse_coord_in_view();
+        let pressed = canvas_state.mouse_button == Some(mouse::Button::Left);
+        match &mut *drawing {
+            Drawing::None => return Status::Ignored,
+            Drawing::Circle { edge, .. } if pressed => {
+                if let Some(direction) = proj.view_to_direction(mp.x, mp.y) {
+                    *edge = direction;
+                }
+            }
+            Drawing::Rectangle { to, .. } if pressed => *to = mp,
+            Drawing::Polygon { cursor, .. } => {
+                if let Some(direction) = proj.view_to_direction(mp.x, mp.y) {
+                    *cursor = direction;
+                }
+            }
+            _ => return Status::Ignored,
+        }
+        canvas_state.overlay = drawing.overlay(&proj);
+
+        Status::Captured
+    }
+}
@@ -0,0 +1,754 @@
+use core::fmt;
+use std::f32::consts::{FRAC_PI_2, PI, TAU};
+use std::sync::{Arc, RwLock};
+
+use glam::{Mat3, Vec2, Vec3, vec2};
+use iced::advanced::graphics::core::event::Status;
+use iced::advanced::graphics::text::cosmic_text::{
+    self, Attrs, Buffer, Family, Metrics, Shaping, SwashCache,
+};
+use iced::advanced::graphics::text::font_system;
+use iced::mouse;
+use iced::widget::image::Handle;
+use iced::{Rectangle, Size};
+use image::{Rgba, RgbaImage};
+use serde::{Deserialize, Serialize};
+
+use crate::font;
+use crate::layer::{self, BlendMode};
+use crate::math::projection::{SphereProjection, ViewProjection};
+use crate::math::region::DirtyRegion;
+use crate::math::sample;
+use crate::math::shape::SphereShape;
+use crate::selection::Selection;
+use crate::tool::Tool;
+use crate::widget::sphere_canvas::SphereCanvasState;
+
+const EPSILON: f32 = 1e-6;
+/// 文字の大きさに対する行の高さ
+const LINE_HEIGHT: f32 = 1.2;
+/// 描画した文字の周りに空ける余白 (ピクセル数)
+const PADDING: u32 = 2;
+
+/// 球面に置いた文字の向き
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum TextOrientation {
+    /// 置いた位置で視点の方を向いた平面に書く
+    #[default]
+    FacingViewer,
+    /// 置いた位置の緯線に沿って曲げて書く
+    AlongLatitude,
+}
+
+impl TextOrientation {
+    pub const ALL: [TextOrientation; 2] = [
+        TextOrientation::FacingViewer,
+        TextOrientation::AlongLatitude,
+    ];
+}
+
+impl fmt::Display for TextOrientation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            TextOrientation::FacingViewer => "Facing Viewer",
+            TextOrientation::AlongLatitude => "Along Latitude",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TextSettings {
+    /// 書く文字列 (改行で複数行になる)
+    #[serde(skip)]
+    pub text: String,
+    /// フォントのファミリー名
+    pub family: String,
+    /// 文字の大きさ (赤道上での画像のピクセル数)
+    pub size: f32,
+    pub orientation: TextOrientation,
+}
+
+impl Default for TextSettings {
+    fn default() -> Self {
+        Self {
+            text: String::new(),
+            family: font::FONT_NAME.to_string(),
+            size: 48.0,
+            orientation: TextOrientation::default(),
+        }
+    }
+}
+
+/// 球面に置いた文字の位置と向き
+#[derive(Debug, Clone, Copy)]
+struct Placement {
+    /// 文字の中心の方向
+    anchor: Vec3,
+    /// anchor に接する平面上の、文字の右と上の方向 (置いた時の viewで正立する向き)
+    right: Vec3,
+    up: Vec3,
+}
+
+impl Placement {
+    fn new(anchor: Vec3, view_up: Vec3) -> Self {
+        let up = view_up - anchor * view_up.dot(anchor);
+        let up = if up.length_squared() > EPSILON {
+            up.normalize()
+        } else {
+            // 視点の上方向が anchor と平行な場合
+            anchor.any_orthogonal_vector().normalize()
+        };
+        let right = anchor.cross(up).normalize();
+        Self { anchor, right, up }
+    }
+
+    /**
+     * 直交行列 transform で回転・反転した位置と向き (反転すると文字も裏返る)
+     */
+    fn transformed(&self, transform: Mat3) -> Self {
+        Self {
+            anchor: transform * self.anchor,
+            right: transform * self.right,
+            up: transform * self.up,
+        }
+    }
+}
+
+/// viewの画像を描き直すかを決める、最後に描画した時の状態
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct ViewKey {
+    proj: SphereProjection,
+    width: u32,
+    height: u32,
+    selection: u64,
+}
+
+/// 確定前の文字を viewに重ねて表示するためのプレビュー
+///
+/// レイヤーには描画せず、viewのピクセルから逆に射影した画像を表示する。
+/// 射影や大きさが変わった時だけ描き直す。
+#[derive(Debug, Clone)]
+pub struct TextPreview {
+    text: Arc<RgbaImage>,
+    placement: Placement,
+    orientation: TextOrientation,
+    /// 文字の大きさと選択範囲の座標を決める、正距円筒図法の画像の大きさ
+    tex_w: u32,
+    tex_h: u32,
+    /// 最後に描画した viewの画像と、それを置く viewの範囲
+    view: Arc<RwLock<Option<(ViewKey, Rectangle, Handle)>>>,
+}
+
+impl TextPreview {
+    /**
+     * 大きさ size のviewに射影した文字の画像と、それを置く viewの範囲 (viewに写らなければ None)
+     * 選択範囲があれば、確定した時と同じくその外側は表示しない
+     */
+    pub fn view_image(
+        &self,
+        proj: &SphereProjection,
+        size: Size,
+        selection: &Selection,
+    ) -> Option<(Rectangle, Handle)> {
+        let key = ViewKey {
+            proj: *proj,
+            width: size.width.round() as u32,
+            height: size.height.round() as u32,
+            selection: selection.revision(),
+        };
+        if let Ok(view) = self.view.read() {
+            if let Some((view_key, bounds, handle)) = view.as_ref() {
+                if *view_key == key {
+                    return Some((*bounds, handle.clone()));
+                }
+            }
+        }
+
+        let rect = self.view_bounds(proj, key.width, key.height)?;
+        let angle = pixel_angle(self.tex_w);
+        let text_size = vec2(self.text.width() as f32, self.text.height() as f32);
+        let mut image = RgbaImage::new(rect.width, rect.height);
+        for y in 0..rect.height {
+            for x in 0..rect.width {
+                let view_x = (rect.x + x) as f32 + 0.5;
+                let view_y = (rect.y + y) as f32 + 0.5;
+                let Some(direction) = proj
+                    .view_to_direction(view_x / key.width as f32, 1.0 - view_y / key.height as f32)
+                else {
+                    continue;
+                };
+                let Some(local) = text_position(
+                    direction,
+                    &self.text,
+                    &self.placement,
+                    self.orientation,
+                    angle,
+                ) else {
+                    continue;
+                };
+                if local.x < 0.0 || local.y < 0.0 || local.x > text_size.x || local.y > text_size.y
+                {
+                    continue;
+                }
+
+                let mut color = sample::bilinear(local.x, local.y, |x, y| {
+                    sample::pixel_or_transparent(&self.text, x, y)
+                });
+                let tex = SphereProjection::direction_to_tex(direction);
+                let tex_x = ((tex.x * self.tex_w as f32) as u32).min(self.tex_w.saturating_sub(1));
+                let tex_y = ((tex.y * self.tex_h as f32) as u32).min(self.tex_h.saturating_sub(1));
+                let clip = selection.coverage(tex_x, tex_y);
+                color[3] = (color[3] as f32 * clip).round() as u8;
+                image.put_pixel(x, y, color);
+            }
+        }
+
+        let bounds = Rectangle {
+            x: rect.x as f32,
+            y: rect.y as f32,
+            width: rect.width as f32,
+            height: rect.height as f32,
+        };
+        let handle = Handle::from_rgba(rect.width, rect.height, image.into_raw());
+        if let Ok(mut view) = self.view.write() {
+            *view = Some((key, bounds, handle.clone()));
+        }
+        Some((bounds, handle))
+    }
+
+    /**
+     * 文字が写る viewのピクセルの範囲
+     * 文字の輪郭を射影して求め、輪郭が途切れる場合は view全体にする
+     */
+    fn view_bounds(
+        &self,
+        proj: &SphereProjection,
+        width: u32,
+        height: u32,
+    ) -> Option<Rectangle<u32>> {
+        let full = Rectangle {
+            x: 0,
+            y: 0,
+            width,
+            height,
+        };
+        if width == 0 || height == 0 {
+            return None;
+        }
+        // 正距円筒図法は左右の端と極で途切れる
+        if proj.kind == ViewProjection::Equirectangular {
+            return Some(full);
+        }
+        let Some(outline) = text_outline(
+            &self.text,
+            &self.placement,
+            self.orientation,
+            pixel_angle(self.tex_w),
+        ) else {
+            return Some(full);
+        };
+
+        let mut min = vec2(f32::MAX, f32::MAX);
+        let mut max = vec2(f32::MIN, f32::MIN);
+        for direction in outline {
+            let Some(view) = proj.direction_to_view(direction) else {
+                return Some(full);
+            };
+            let point = vec2(view.x * width as f32, (1.0 - view.y) * height as f32);
+            min = min.min(point);
+            max = max.max(point);
+        }
+
+        // 輪郭の間の膨らみと、補間に使う隣のピクセルの分だけ広げる
+        let x0 = (min.x.floor() as i64 - 2).clamp(0, width as i64) as u32;
+        let y0 = (min.y.floor() as i64 - 2).clamp(0, height as i64) as u32;
+        let x1 = (max.x.ceil() as i64 + 2).clamp(0, width as i64) as u32;
+        let y1 = (max.y.ceil() as i64 + 2).clamp(0, height as i64) as u32;
+        if x1 <= x0 || y1 <= y0 {
+            return None;
+        }
+        Some(Rectangle {
+            x: x0,
+            y: y0,
+            width: x1 - x0,
+            height: y1 - y0,
+        })
+    }
+}
+
+/// viewをクリックした位置に文字を置き、確定するまで描画を更新し続けるツール
+///
+/// 確定前の文字は SphereCanvasState のプレビューとして viewに重ねて表示し、確定した時だけレイヤーに描画する。
+#[derive(Debug)]
+pub struct TextTool {
+    pub name: String,
+    pub icon: char,
+
+    pub settings: RwLock<TextSettings>,
+    placement: RwLock<Option<Placement>>,
+    /// 最後に描画した文字 (設定と色が同じなら描画し直さない)
+    rendered: RwLock<Option<(TextSettings, Rgba<u8>, Arc<RgbaImage>)>>,
+}
+
+impl TextTool {
+    pub fn new() -> Self {
+        Self {
+            name: "Text".to_string(),
+            icon: '\u{ebc5}',
+
+            settings: RwLock::new(TextSettings::default()),
+            placement: RwLock::new(None),
+            rendered: RwLock::new(None),
+        }
+    }
+
+    /**
+     * 確定前の文字を置いているか
+     */
+    pub fn is_placed(&self) -> bool {
+        self.placement
+            .read()
+            .is_ok_and(|placement| placement.is_some())
+    }
+
+    /**
+     * 今の設定で文字を描画した画像 (文字が無ければ None)
+     */
+    fn render(&self, color: Rgba<u8>) -> Option<Arc<RgbaImage>> {
+        let settings = self.settings.read().ok()?.clone();
+        if let Ok(rendered) = self.rendered.read() {
+            if let Some((key, key_color, image)) = rendered.as_ref() {
+                if *key == settings && *key_color == color {
+                    return Some(image.clone());
+                }
+            }
+        }
+
+        let image = Arc::new(rasterize(
+            &settings.text,
+            &settings.family,
+            settings.size,
+            color,
+        )?);
+        if let Ok(mut rendered) = self.rendered.write() {
+            *rendered = Some((settings, color, image.clone()));
+        }
+        Some(image)
+    }
+
+    /**
+     * 置いた文字を今の設定でプレビューし直す (レイヤーには描画しない)
+     */
+    pub fn refresh(&self, canvas_state: &mut SphereCanvasState) {
+        canvas_state.text_preview = None;
+
+        let Some(placement) = self.placement.read().ok().and_then(|placement| *placement) else {
+            return;
+        };
+        let Some(text) = self.render(canvas_state.foreground) else {
+            return;
+        };
+        let orientation = self
+            .settings
+            .read()
+            .map(|settings| settings.orientation)
+            .unwrap_or_default();
+
+        canvas_state.text_preview = Some(TextPreview {
+            text,
+            placement,
+            orientation,
+            tex_w: canvas_state.image_width,
+            tex_h: canvas_state.image_height,
+            view: Arc::new(RwLock::new(None)),
+        });
+    }
+
+    /**
+     * 置いた文字をアクティブなレイヤーに確定する
+     * 対称の設定があれば、対称な位置と向きにも同じ文字を描画する
+     * 描画する前の範囲を履歴に保存する (履歴の区切りは呼び出し側で行う)
+     */
+    pub fn commit(&self, canvas_state: &mut SphereCanvasState) -> bool {
+        canvas_state.text_preview = None;
+
+        let Some(placement) = self
+            .placement
+            .write()
+            .ok()
+            .and_then(|mut placement| placement.take())
+        else {
+            return false;
+        };
+        let Some(text) = self.render(canvas_state.foreground) else {
+            return false;
+        };
+        let orientation = self
+            .settings
+            .read()
+            .map(|settings| settings.orientation)
+            .unwrap_or_default();
+        let Some(rw_image) = canvas_state.active_image() else {
+            return false;
+        };
+        let Ok(mut image) = rw_image.write() else {
+            return false;
+        };
+
+        let (tex_w, tex_h) = image.dimensions();
+        let replicas: Vec<(Placement, DirtyRegion)> = canvas_state
+            .symmetry
+            .transforms()
+            .into_iter()
+            .map(|transform| {
+                let replica = placement.transformed(transform);
+                let area = text_area(&text, &replica, orientation, tex_w, tex_h);
+                (replica, area)
+            })
+            .collect();
+        let mut area = DirtyRegion::new(tex_w, tex_h);
+        for (_, replica_area) in &replicas {
+            area.merge(replica_area);
+        }
+
+        if let Ok(mut history) = canvas_state.history.write() {
+            for rect in area.rects() {
+                history.record(&rw_image, &image, *rect);
+            }
+        }
+        for (replica, replica_area) in &replicas {
+            draw_text(
+                &mut image,
+                &text,
+                replica,
+                orientation,
+                replica_area,
+                &canvas_state.selection,
+            );
+        }
+
+        // テクスチャの更新範囲
+        canvas_state.mark_modified(&rw_image, &area);
+
+        true
+    }
+
+    /**
+     * 置いた文字を取り消す
+     */
+    pub fn cancel(&self, canvas_state: &mut SphereCanvasState) {
+        canvas_state.text_preview = None;
+        if let Ok(mut placement) = self.placement.write() {
+            *placement = None;
+        }
+    }
+
+    /**
+     * マウスの位置に文字を置く
+     */
+    fn place_at_mouse(&self, canvas_state: &Arc<RwLock<SphereCanvasState>>) -> Status {
+        let Ok(mut canvas_state) = canvas_state.try_write() else {
+            return Status::Ignored;
+        };
+        if canvas_state.mouse_button != Some(mouse::Button::Left) {
+            return Status::Ignored;
+        }
+
+        let mp = canvas_state.get_mouse_coord_in_view();
+        let Some(anchor) = canvas_state
+            .sphere_projection()
+            .view_to_direction(mp.x, mp.y)
+        else {
+            return Status::Captured;
+        };
+        if let Ok(mut placement) = self.placement.write() {
+            *placement = Some(Placement::new(anchor, canvas_state.up));
+        }
+        self.refresh(&mut canvas_state);
+
+        Status::Captured
+    }
+}
+
+/**
+ * 文字列を font のファミリー family, 大きさ size (ピクセル), 色 color で描画する
+ * 描画には iced と同じフォントの一覧を使うので、同梱のフォントとシステムのフォントを選べる
+ */
+fn rasterize(text: &str, family: &str, size: f32, color: Rgba<u8>) -> Option<RgbaImage> {
+    if text.trim().is_empty() || size <= 0.0 {
+        return None;
+    }
+    let mut font_system = font_system().write().ok()?;
+    let font_system = font_system.raw();
+
+    let line_height = (size * LINE_HEIGHT).ceil();
+    let mut buffer = Buffer::new(font_system, Metrics::new(size, line_height));
+    buffer.set_size(font_system, None, None);
+    buffer.set_text(
+        font_system,
+        text,
+        Attrs::new().family(Family::Name(family)),
+        Shaping::Advanced,
+    );
+    buffer.shape_until_scroll(font_system, false);
+
+    let text_width = buffer
+        .layout_runs()
+        .map(|run| run.line_w)
+        .fold(0.0, f32::max);
+    let lines = buffer.layout_runs().count();
+    if text_width <= 0.0 || lines == 0 {
+        return None;
+    }
+    let width = text_width.ceil() as u32 + 2 * PADDING;
+    let height = (lines as f32 * line_height).ceil() as u32 + 2 * PADDING;
+
+    let mut image = RgbaImage::new(width, height);
+    let mut cache = SwashCache::new();
+    let [r, g, b, a] = color.0;
+    buffer.draw(
+        font_system,
+        &mut cache,
+        cosmic_text::Color::rgba(r, g, b, a),
+        |x, y, w, h, color| {
+            let source = Rgba([color.r(), color.g(), color.b(), color.a()]);
+            if source[3] == 0 {
+                return;
+            }
+            for py in y..y + h as i32 {
+                for px in x..x + w as i32 {
+                    let px = px + PADDING as i32;
+                    let py = py + PADDING as i32;
+                    if px < 0 || py < 0 || px >= width as i32 || py >= height as i32 {
+                        continue;
+                    }
+                    let pixel = image.get_pixel_mut(px as u32, py as u32);
+                    *pixel = layer::composite(*pixel, source, 1.0, BlendMode::Normal);
+                }
+            }
+        },
+    );
+
+    Some(image)
+}
+
+/**
+ * 文字の画像の1ピクセルに相当する球面上の角度 (文字の大きさは赤道上のピクセル数で決める)
+ */
+fn pixel_angle(tex_w: u32) -> f32 {
+    TAU / tex_w.max(1) as f32
+}
+
+/**
+ * 球面上の方向 direction に対応する、文字の画像のピクセル座標 (連続した座標)
+ */
+fn text_position(
+    direction: Vec3,
+    text: &RgbaImage,
+    placement: &Placement,
+    orientation: TextOrientation,
+    pixel: f32,
+) -> Option<Vec2> {
+    let half = vec2(text.width() as f32, text.height() as f32) * 0.5;
+    let offset = match orientation {
+        TextOrientation::FacingViewer => {
+            // anchor に接する平面に中心から射影する
+            let t = direction.dot(placement.anchor);
+            if t <= EPSILON {
+                return None;
+            }
+            let point = direction / t - placement.anchor;
+            vec2(point.dot(placement.right), -point.dot(placement.up))
+        }
+        TextOrientation::AlongLatitude => {
+            // 置いた位置の緯度で文字の形が変わらないように経度の差を縮める
+            let (lon0, lat0) = SphereProjection::direction_to_latlng(placement.anchor);
+            let (lon, lat) = SphereProjection::direction_to_latlng(direction);
+            let d_lon = (lon - lon0 + PI).rem_euclid(TAU) - PI;
+            vec2(d_lon * lat0.cos(), lat0 - lat)
+        }
+    };
+
+    Some(offset / pixel + half)
+}
+
+/**
+ * 文字の範囲の輪郭を、辺ごとに区切った球面上の点で返す
+ * 緯線に沿った文字が極をまたぐ場合は輪郭で囲めないので None
+ */
+fn text_outline(
+    text: &RgbaImage,
+    placement: &Placement,
+    orientation: TextOrientation,
+    pixel: f32,
+) -> Option<Vec<Vec3>> {
+    const SEGMENTS: usize = 16;
+    let half = vec2(text.width() as f32, text.height() as f32) * 0.5 * pixel;
+    let corners: [(f32, f32); 4] = [(-1.0, 1.0), (1.0, 1.0), (1.0, -1.0), (-1.0, -1.0)];
+    let edge_points = |point: &dyn Fn(f32, f32) -> Vec3| {
+        let mut points = Vec::with_capacity(corners.len() * SEGMENTS);
+        for (i, &(x0, y0)) in corners.iter().enumerate() {
+            let (x1, y1) = corners[(i + 1) % corners.len()];
+            for j in 0..SEGMENTS {
+                let t = j as f32 / SEGMENTS as f32;
+                points.push(point(x0 + (x1 - x0) * t, y0 + (y1 - y0) * t));
+            }
+        }
+        points
+    };
+
+    match orientation {
+        TextOrientation::FacingViewer => Some(edge_points(&|x, y| {
+            (placement.anchor + placement.right * half.x * x + placement.up * half.y * y)
+                .normalize()
+        })),
+        TextOrientation::AlongLatitude => {
+            let (lon0, lat0) = SphereProjection::direction_to_latlng(placement.anchor);
+            let cos_lat = lat0.cos();
+            if cos_lat <= EPSILON || lat0.abs() + half.y >= FRAC_PI_2 {
+                return None;
+            }
+            let half_lon = (half.x / cos_lat).min(PI);
+            Some(edge_points(&|x, y| {
+                SphereProjection::latlng_to_direction(lon0 + half_lon * x, lat0 + half.y * y)
+            }))
+        }
+    }
+}
+
+/**
+ * 文字が覆う、正距円筒図法の画像の範囲
+ */
+fn text_area(
+    text: &RgbaImage,
+    placement: &Placement,
+    orientation: TextOrientation,
+    tex_w: u32,
+    tex_h: u32,
+) -> DirtyRegion {
+    let pixel = pixel_angle(tex_w);
+    let half = vec2(text.width() as f32, text.height() as f32) * 0.5 * pixel;
+    let mut area = DirtyRegion::new(tex_w, tex_h);
+
+    match orientation {
+        TextOrientation::FacingViewer => {
+            let vertices = [(-1.0, 1.0), (1.0, 1.0), (1.0, -1.0), (-1.0, -1.0)]
+                .map(|(x, y)| {
+                    (placement.anchor + placement.right * half.x * x + placement.up * half.y * y)
+                        .normalize()
+                })
+                .to_vec();
+            area.add(SphereShape::polygon(vertices).tex_bounds(tex_w, tex_h));
+        }
+        TextOrientation::AlongLatitude => {
+            let (lon0, lat0) = SphereProjection::direction_to_latlng(placement.anchor);
+            let cos_lat = lat0.cos();
+            // 極をまたぐ場合は経度の全体に掛かる
+            let half_lon = if cos_lat > EPSILON && lat0.abs() + half.y < FRAC_PI_2 {
+                (half.x / cos_lat).min(PI)
+            } else {
+                PI
+            };
+            let to_x = |lon: f32| (lon / TAU + 0.5) * tex_w as f32;
+            let to_y = |lat: f32| (0.5 - lat / PI) * tex_h as f32;
+            let x0 = to_x(lon0 - half_lon).floor() as i32 - 1;
+            let x1 = to_x(lon0 + half_lon).ceil() as i32 + 1;
+            let y0 = to_y(lat0 + half.y).floor() as i32 - 1;
+            let y1 = to_y(lat0 - half.y).ceil() as i32 + 1;
+            area.add(Rectangle {
+                x: x0,
+                y: y0,
+                width: x1 - x0,
+                height: y1 - y0,
+            });
+        }
+    }
+
+    area
+}
+
+/**
+ * 文字の画像を、area の各ピクセルから逆に射影して重ねる
+ * 選択範囲があれば、その外側には描画しない
+ */
+fn draw_text(
+    image: &mut RgbaImage,
+    text: &RgbaImage,
+    placement: &Placement,
+    orientation: TextOrientation,
+    area: &DirtyRegion,
+    selection: &Selection,
+) {
+    let (tex_w, tex_h) = image.dimensions();
+    let angle = pixel_angle(tex_w);
+    let size = vec2(text.width() as f32, text.height() as f32);
+
+    for rect in area.rects() {
+        for y in rect.y..rect.y + rect.height {
+            for x in rect.x..rect.x + rect.width {
+                let direction = SphereProjection::tex_to_direction(
+                    (x as f32 + 0.5) / tex_w as f32,
+                    (y as f32 + 0.5) / tex_h as f32,
+                );
+                let Some(local) = text_position(direction, text, placement, orientation, angle)
+                else {
+                    continue;
+                };
+                if local.x < 0.0 || local.y < 0.0 || local.x > size.x || local.y > size.y {
+                    continue;
+                }
+
+                let mut color = sample::bilinear(local.x, local.y, |x, y| {
+                    sample::pixel_or_transparent(text, x, y)
+                });
+                let clip = selection.coverage(x, y);
+                color[3] = (color[3] as f32 * clip).round() as u8;
+                if color[3] == 0 {
+                    continue;
+                }
+                let pixel = *image.get_pixel(x, y);
+                image.put_pixel(x, y, layer::composite(pixel, color, 1.0, BlendMode::Normal));
+            }
+        }
+    }
+}
+
+impl Tool for TextTool {
+    fn name(&self) -> &str {
+        &self.name
+    }
+    fn icon(&self) -> char {
+        self.icon
+    }
+
+    fn settings(&self) -> Option<serde_json::Value> {
+        let settings = self.settings.read().ok()?.clone();
+        serde_json::to_value(settings).ok()
+    }
+
+    fn load_settings(&self, settings: &serde_json::Value) {
+        if let Ok(loaded) = TextSettings::deserialize(settings) {
+            if let Ok(mut settings) = self.settings.write() {
+                // 書きかけの文字列は残す
+                *settings = TextSettings {
+                    text: std::mem::take(&mut settings.text),
+                    ..loaded
+                };
+            }
+        }
+    }
+
+    fn on_mouse_pressed(&self, canvas_state: &Arc<RwLock<SphereCanvasState>>) -> Status {
+        self.place_at_mouse(canvas_state)
+    }
+
+    fn on_mouse_moved(&self, canvas_state: &Arc<RwLock<SphereCanvasState>>) -> Status {
+        // ドラッグして置いた文字を動かす
+        if !self.is_placed() {
+            return Status::Ignored;
+        }
+        self.place_at_mouse(canvas_state)
+    }
+}
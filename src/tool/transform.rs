@@ -0,0 +1,138 @@
+use std::sync::{Arc, RwLock};
+
+use glam::Vec2;
+use iced::advanced::graphics::core::event::Status;
+use iced::mouse;
+
+use crate::tool::Tool;
+use crate::widget::sphere_canvas::SphereCanvasState;
+
+/// 貼り付けた画像の角をつかんで拡大縮小できる距離 (viewのピクセル数)
+const HANDLE_DISTANCE: f32 = 8.0;
+
+/// 貼り付けた画像をドラッグしている操作
+#[derive(Debug, Clone, Copy, Default)]
+enum Drag {
+    #[default]
+    None,
+    /// 画像の内側をつかんで動かす
+    Move { offset: Vec2 },
+    /// 角をつかんで、中心からの距離に合わせて拡大縮小する
+    Scale { distance: f32, scale: f32 },
+    /// 画像の外側をつかんで、中心の周りに回す
+    Rotate { angle: f32, rotation: f32 },
+}
+
+/// 貼り付けた画像 (SphereCanvasState の floating) を動かす・拡大縮小する・回すツール
+#[derive(Debug)]
+pub struct TransformTool {
+    pub name: String,
+    pub icon: char,
+
+    drag: RwLock<Drag>,
+}
+
+impl TransformTool {
+    pub fn new() -> Self {
+        Self {
+            name: "Transform".to_string(),
+            icon: '\u{f38e}',
+
+            drag: RwLock::new(Drag::None),
+        }
+    }
+
+    /**
+     * マウスの viewのピクセル座標 (左上が原点)
+     */
+    fn mouse_in_view(canvas_state: &SphereCanvasState) -> Vec2 {
+        let bounds = canvas_state.viewport_bounds;
+        canvas_state.mouse_point - Vec2::new(bounds.x, bounds.y)
+    }
+}
+
+impl Tool for TransformTool {
+    fn name(&self) -> &str {
+        &self.name
+    }
+    fn icon(&self) -> char {
+        self.icon
+    }
+
+    fn on_mouse_pressed(&self, canvas_state: &Arc<RwLock<SphereCanvasState>>) -> Status {
+        let Ok(canvas_state) = canvas_state.try_read() else {
+            return Status::Ignored;
+        };
+        if canvas_state.mouse_button != Some(mouse::Button::Left) {
+            return Status::Ignored;
+        }
+        let Some(floating) = canvas_state.floating.as_ref() else {
+            return Status::Ignored;
+        };
+        let Ok(mut drag) = self.drag.write() else {
+            return Status::Captured;
+        };
+
+        let mouse = Self::mouse_in_view(&canvas_state);
+        let local = floating.to_local(mouse);
+        let size = floating.image_size();
+        let on_corner = floating
+            .corners()
+            .iter()
+            .any(|corner| corner.distance(mouse) <= HANDLE_DISTANCE);
+        let inside = local.x >= 0.0 && local.y >= 0.0 && local.x <= size.x && local.y <= size.y;
+
+        let from_center = mouse - floating.center;
+        *drag = if on_corner {
+            Drag::Scale {
+                distance: from_center.length().max(1.0),
+                scale: floating.scale,
+            }
+        } else if inside {
+            Drag::Move {
+                offset: floating.center - mouse,
+            }
+        } else {
+            Drag::Rotate {
+                angle: from_center.to_angle(),
+                rotation: floating.rotation,
+            }
+        };
+
+        Status::Captured
+    }
+
+    fn on_mouse_released(&self, _canvas_state: &Arc<RwLock<SphereCanvasState>>) -> Status {
+        match self.drag.write().map(|mut drag| std::mem::take(&mut *drag)) {
+            Ok(Drag::None) | Err(_) => Status::Ignored,
+            Ok(_) => Status::Captured,
+        }
+    }
+
+    fn on_mouse_moved(&self, canvas_state: &Arc<RwLock<SphereCanvasState>>) -> Status {
+        let Ok(mut canvas_state) = canvas_state.try_write() else {
+            return Status::Ignored;
+        };
+        let Ok(drag) = self.drag.read() else {
+            return Status::Ignored;
+        };
+
+        let mouse = Self::mouse_in_view(&canvas_state);
+        let Some(floating) = canvas_state.floating.as_mut() else {
+            return Status::Ignored;
+        };
+        let from_center = mouse - floating.center;
+        match *drag {
+            Drag::None => return Status::Ignored,
+            Drag::Move { offset } => floating.center = mouse + offset,
+            Drag::Scale { distance, scale } => {
+                floating.scale = (scale * from_center.length() / distance).max(0.01)
+            }
+            Drag::Rotate { angle, rotation } => {
+                floating.rotation = rotation + from_center.to_angle() - angle
+            }
+        }
+
+        Status::Captured
+    }
+}
@@ -5,6 +5,9 @@ use iced::advanced::graphics::core::event::Status;
 use crate::tool::Tool;
 use crate::widget::sphere_canvas::SphereCanvasState;
 
+/// ズームできる最小の画角
+const MIN_AOV: f32 = 0.01;
+
 #[derive(Debug)]
 pub struct ZoomTool {
     pub name: String,
@@ -30,7 +33,9 @@ impl Tool for ZoomTool {
 
     fn on_wheel(&self, canvas_state: &Arc<std::sync::RwLock<SphereCanvasState>>) -> Status {
         if let Ok(mut canvas_state) = canvas_state.try_write() {
-            canvas_state.aov = canvas_state.aov - canvas_state.mouse_wheel_delta / 10.0;
+            let max_aov = canvas_state.projection.max_aov();
+            canvas_state.aov =
+                (canvas_state.aov - canvas_state.mouse_wheel_delta / 10.0).clamp(MIN_AOV, max_aov);
             return Status::Captured;
         };
         Status::Ignored
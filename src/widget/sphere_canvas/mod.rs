@@ -1,12 +1,34 @@
+pub mod overlay;
+mod tiling;
+
 use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use glam::{Vec2, Vec3, vec2, vec3};
 use iced::advanced::graphics::core::event;
 use iced::mouse::Button;
+use iced::time::Instant;
 use iced::widget::shader;
 use iced::widget::shader::wgpu;
-use iced::{Rectangle, mouse};
-use image::{EncodableLayout, RgbaImage};
+use iced::{Rectangle, keyboard, mouse, window};
+use image::imageops::{self, FilterType};
+use image::{EncodableLayout, Rgba, RgbaImage};
+
+use crate::floating::FloatingImage;
+use crate::history::History;
+use crate::layer::{LayerStack, MAX_LAYERS};
+use crate::math::projection::{SphereProjection, ViewProjection};
+use crate::math::region::DirtyRegion;
+use crate::math::symmetry::Symmetry;
+use crate::selection::Selection;
+use crate::tool::text::TextPreview;
+use crate::widget::sphere_canvas::overlay::OverlayPath;
+use crate::widget::sphere_canvas::tiling::TextureTiling;
+
+/// 選択範囲の境界線 (marching ants) を動かす間隔
+const ANTS_INTERVAL: Duration = Duration::from_millis(100);
+/// 境界線の縞模様の周期 (viewのピクセル数, sphere.wgsl の ANTS_PERIOD と一致させること)
+const ANTS_PERIOD: u128 = 8;
 
 pub fn sphere_canvas<'a, Message>(
     state: Arc<RwLock<SphereCanvasState>>,
@@ -69,6 +91,15 @@ impl<'a, Message> shader::Program<Message> for SphereCanvas<'a, Message> {
                 look_at: state.look_at,
                 up: state.up,
                 right: state.right,
+                projection: state.projection.shader_index(),
+                aspect: if bounds.width > 0.0 {
+                    bounds.height / bounds.width
+                } else {
+                    1.0
+                },
+                selection: state.selection.is_active() as u32,
+                view_size: vec2(bounds.width, bounds.height),
+                ants_phase: ants_phase(),
                 ..Default::default()
             },
             self.state.clone(), // TODO: draw blank if image is None.
@@ -90,6 +121,11 @@ impl<'a, Message> shader::Program<Message> for SphereCanvas<'a, Message> {
             read_state.clone_into(state);
         }
 
+        // 選択範囲があれば境界線を動かし続ける
+        if state.selection.is_active() {
+            shell.request_redraw(window::RedrawRequest::At(Instant::now() + ANTS_INTERVAL));
+        }
+
         if state.viewport_bounds != bounds {
             if let Some(f) = self.on_event.as_ref() {
                 shell.publish(f(SphereCanvasMessage::BoundsChanged(bounds)))
@@ -142,18 +178,48 @@ impl<'a, Message> shader::Program<Message> for SphereCanvas<'a, Message> {
     }
 }
 
+/**
+ * 選択範囲の境界線の縞模様をずらす量 (ANTS_INTERVAL ごとに1ピクセル進む)
+ */
+fn ants_phase() -> f32 {
+    let millis = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_millis())
+        .unwrap_or_default();
+    (millis / ANTS_INTERVAL.as_millis() % ANTS_PERIOD) as f32
+}
+
 #[derive(Debug, Clone)]
 pub struct SphereCanvasState {
-    pub image: Option<Arc<RwLock<RgbaImage>>>,
+    pub layers: LayerStack,
     pub image_width: u32,
     pub image_height: u32,
-    pub modified_area: Option<Rectangle>,
+    pub history: Arc<RwLock<History>>,
+    /// レイヤーの画像ごとの、テクスチャに転送していない変更範囲
+    modified: Vec<(Arc<RwLock<RgbaImage>>, DirtyRegion)>,
+    /// 描画ツールが塗る色
+    pub foreground: Rgba<u8>,
+    /// 背景色 (描画色と入れ替えて使う)
+    pub background: Rgba<u8>,
     pub mouse_button: Option<Button>,
     pub mouse_point: Vec2,
     pub mouse_point_prev: Vec2,
     pub mouse_delta: Vec2,
     pub mouse_wheel_delta: f32,
+    /// 押されている修飾キー (ツールの動作の切り替えに使う)
+    pub modifiers: keyboard::Modifiers,
+    /// ツールが画像に重ねて表示する線 (描画中の図形のプレビューなど)
+    pub overlay: Vec<OverlayPath>,
+    /// 描画ツールが塗れる範囲
+    pub selection: Selection,
+    /// 貼り付けて、まだ確定していない画像
+    pub floating: Option<FloatingImage>,
+    /// 置いて、まだ確定していない文字
+    pub text_preview: Option<TextPreview>,
+    /// 描画ツールのスタンプを複製する対称の設定
+    pub symmetry: Symmetry,
     pub viewport_bounds: Rectangle,
+    pub projection: ViewProjection,
     pub aov: f32,
     pub look_at: Vec3,
     pub up: Vec3,
@@ -168,9 +234,137 @@ impl SphereCanvasState {
     }
 
     pub fn set_image(&mut self, image: image::DynamicImage) {
-        self.image = Some(Arc::new(RwLock::new(image.to_rgba8())));
-        self.image_width = image.width();
-        self.image_height = image.height();
+        self.set_layers(LayerStack::new(image.to_rgba8()));
+    }
+
+    pub fn set_layers(&mut self, layers: LayerStack) {
+        self.image_width = layers.width;
+        self.image_height = layers.height;
+        self.layers = layers;
+        self.history = Arc::new(RwLock::new(History::new()));
+        // 新しいレイヤーはテクスチャを作り直す時に全体を転送する
+        self.modified.clear();
+        self.selection.resize(self.image_width, self.image_height);
+        self.floating = None;
+        self.text_preview = None;
+    }
+
+    /**
+     * ツールが描画する画像 (アクティブなレイヤー)
+     */
+    pub fn active_image(&self) -> Option<Arc<RwLock<RgbaImage>>> {
+        self.layers.active_image()
+    }
+
+    /**
+     * レイヤーの画像 image を変更した範囲を、テクスチャの更新範囲に加える
+     */
+    pub fn mark_modified(&mut self, image: &Arc<RwLock<RgbaImage>>, area: &DirtyRegion) {
+        self.modified_region(image).merge(area);
+    }
+
+    /**
+     * レイヤーの画像 image を変更した矩形を、テクスチャの更新範囲に加える
+     * x は画像の幅を超えたり負になっても良く、左右端で折り返す
+     */
+    pub fn mark_modified_rect(&mut self, image: &Arc<RwLock<RgbaImage>>, area: Rectangle<i32>) {
+        self.modified_region(image).add(area);
+    }
+
+    fn modified_region(&mut self, image: &Arc<RwLock<RgbaImage>>) -> &mut DirtyRegion {
+        let index = match self
+            .modified
+            .iter()
+            .position(|(target, _)| Arc::ptr_eq(target, image))
+        {
+            Some(index) => index,
+            None => {
+                let region = DirtyRegion::new(self.image_width, self.image_height);
+                self.modified.push((image.clone(), region));
+                self.modified.len() - 1
+            }
+        };
+        &mut self.modified[index].1
+    }
+
+    /**
+     * テクスチャに転送していない変更があるか
+     */
+    pub fn is_modified(&self) -> bool {
+        self.modified.iter().any(|(_, area)| !area.is_empty())
+    }
+
+    /**
+     * テクスチャに転送していない変更範囲を、レイヤーの画像ごとに取り出す
+     */
+    fn take_modified(&mut self) -> Vec<(Arc<RwLock<RgbaImage>>, Vec<Rectangle<u32>>)> {
+        self.modified
+            .drain(..)
+            .map(|(image, mut area)| (image, area.take()))
+            .filter(|(_, areas)| !areas.is_empty())
+            .collect()
+    }
+
+    /**
+     * 直前の編集を取り消す
+     */
+    pub fn undo(&mut self) -> bool {
+        self.apply_history(|history| history.undo())
+    }
+
+    /**
+     * 取り消した編集をやり直す
+     */
+    pub fn redo(&mut self) -> bool {
+        self.apply_history(|history| history.redo())
+    }
+
+    fn apply_history(
+        &mut self,
+        f: impl FnOnce(&mut History) -> Option<Vec<(Arc<RwLock<RgbaImage>>, Rectangle<u32>)>>,
+    ) -> bool {
+        let areas = match self.history.write() {
+            Ok(mut history) => f(&mut history),
+            _ => None,
+        };
+
+        // 履歴で書き戻した範囲をテクスチャの更新範囲にする
+        if let Some(areas) = areas {
+            for (image, area) in areas {
+                self.mark_modified_rect(
+                    &image,
+                    Rectangle {
+                        x: area.x as i32,
+                        y: area.y as i32,
+                        width: area.width as i32,
+                        height: area.height as i32,
+                    },
+                );
+            }
+            true
+        } else {
+            false
+        }
+    }
+
+    /**
+     * 現在の視点と射影方式での view座標 ⇔ テクスチャ座標 の射影
+     */
+    pub fn sphere_projection(&self) -> SphereProjection {
+        let aspect = if self.viewport_bounds.width > 0.0 {
+            self.viewport_bounds.height / self.viewport_bounds.width
+        } else {
+            1.0
+        };
+
+        SphereProjection::new(
+            self.projection,
+            self.aov,
+            aspect,
+            self.look_at,
+            self.up,
+            self.right,
+        )
     }
 
     pub fn get_mouse_coord_in_view(&self) -> Vec2 {
@@ -183,16 +377,26 @@ impl SphereCanvasState {
 impl Default for SphereCanvasState {
     fn default() -> Self {
         Self {
-            image: None,
+            layers: LayerStack::default(),
             image_width: 0,
             image_height: 0,
-            modified_area: None,
+            history: Arc::new(RwLock::new(History::new())),
+            modified: Vec::new(),
+            foreground: Rgba([255, 255, 255, 255]),
+            background: Rgba([0, 0, 0, 255]),
             mouse_button: None,
             mouse_point: vec2(0., 0.),
             mouse_point_prev: vec2(0., 0.),
             mouse_delta: vec2(0., 0.),
             mouse_wheel_delta: 0.0,
+            modifiers: keyboard::Modifiers::default(),
+            overlay: Vec::new(),
+            selection: Selection::default(),
+            floating: None,
+            text_preview: None,
+            symmetry: Symmetry::default(),
             viewport_bounds: Rectangle::default(),
+            projection: ViewProjection::default(),
             aov: 1.0,
             look_at: vec3(1., 0., 0.),
             up: vec3(0., 1., 0.),
@@ -204,50 +408,71 @@ impl Default for SphereCanvasState {
 pub struct SphereCanvasPipeline {
     pipeline: wgpu::RenderPipeline,
     uniform_buffer: wgpu::Buffer,
+    uniform_bind_group_layout: wgpu::BindGroupLayout,
     uniform_bind_group: wgpu::BindGroup,
-    image: Arc<RwLock<RgbaImage>>,
+    // テクスチャ配列に格納しているレイヤーの画像 (添字がスロット番号, 削除したレイヤーのスロットは空けておく)
+    images: Vec<Option<Arc<RwLock<RgbaImage>>>>,
     image_width: u32,
     image_height: u32,
+    tiling: TextureTiling,
     texture: wgpu::Texture,
     texture_view: wgpu::TextureView,
     sampler: wgpu::Sampler,
+    // 選択範囲のマスク (テクスチャサイズの上限を超える画像では縮小して持つ)
+    selection_texture: wgpu::Texture,
+    selection_view: wgpu::TextureView,
+    // テクスチャに転送した選択範囲の版 (未転送なら None)
+    selection_revision: Option<u64>,
 }
 
 impl SphereCanvasPipeline {
     pub fn new(
         device: &wgpu::Device,
         format: wgpu::TextureFormat,
-        image: Arc<RwLock<RgbaImage>>,
+        images: Vec<Arc<RwLock<RgbaImage>>>,
         image_width: u32,
         image_height: u32,
     ) -> Self {
         let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
             label: Some("Sphere Sampler"),
-            address_mode_u: wgpu::AddressMode::Repeat,
-            address_mode_v: wgpu::AddressMode::Repeat,
-            address_mode_w: wgpu::AddressMode::Repeat,
+            // タイルの境界は隣接ピクセルで補間するので端で折り返さない
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
             mag_filter: wgpu::FilterMode::Linear,
             min_filter: wgpu::FilterMode::Nearest,
             mipmap_filter: wgpu::FilterMode::Nearest,
             ..Default::default()
         });
 
-        let texture = device.create_texture(&wgpu::TextureDescriptor {
-            label: Some("Sphere Texture"),
+        // テクスチャサイズの上限を超える画像はタイルに分割してテクスチャ配列に格納する
+        // レイヤーごとにタイル数分の配列要素を使う
+        let tiling = TextureTiling::new(
+            image_width,
+            image_height,
+            device.limits().max_texture_dimension_2d,
+        );
+
+        let (texture, texture_view) = Self::create_layer_texture(device, &tiling, images.len());
+
+        // 選択範囲は境界線の表示にしか使わないので、分割せずに縮小する
+        let max_dimension = device.limits().max_texture_dimension_2d;
+        let selection_scale = image_width.max(image_height).div_ceil(max_dimension).max(1);
+        let selection_texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Sphere Selection Texture"),
             size: wgpu::Extent3d {
-                width: image_width,
-                height: image_height,
+                width: image_width.div_ceil(selection_scale).max(1),
+                height: image_height.div_ceil(selection_scale).max(1),
                 depth_or_array_layers: 1,
             },
             mip_level_count: 1,
             sample_count: 1,
             dimension: wgpu::TextureDimension::D2,
-            format: wgpu::TextureFormat::Rgba8UnormSrgb,
+            format: wgpu::TextureFormat::R8Unorm,
             usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
             view_formats: &[],
         });
-
-        let texture_view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let selection_view = selection_texture.create_view(&wgpu::TextureViewDescriptor::default());
 
         let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
             label: Some("Sphere Shader"),
@@ -280,7 +505,7 @@ impl SphereCanvasPipeline {
                         visibility: wgpu::ShaderStages::FRAGMENT,
                         ty: wgpu::BindingType::Texture {
                             sample_type: { wgpu::TextureSampleType::Float { filterable: true } },
-                            view_dimension: wgpu::TextureViewDimension::D2,
+                            view_dimension: wgpu::TextureViewDimension::D2Array,
                             multisampled: false,
                         },
                         count: None,
@@ -291,27 +516,27 @@ impl SphereCanvasPipeline {
                         ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
                         count: None,
                     },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 3,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Texture {
+                            sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                            view_dimension: wgpu::TextureViewDimension::D2,
+                            multisampled: false,
+                        },
+                        count: None,
+                    },
                 ],
             });
 
-        let uniform_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            label: Some("shader_quad uniform bind group"),
-            layout: &uniform_bind_group_layout,
-            entries: &[
-                wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: uniform_buffer.as_entire_binding(),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 1,
-                    resource: wgpu::BindingResource::TextureView(&texture_view),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 2,
-                    resource: wgpu::BindingResource::Sampler(&sampler),
-                },
-            ],
-        });
+        let uniform_bind_group = Self::create_bind_group(
+            device,
+            &uniform_bind_group_layout,
+            &uniform_buffer,
+            &texture_view,
+            &sampler,
+            &selection_view,
+        );
 
         let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
             label: Some("Sphere Pipeline Layout"),
@@ -345,18 +570,306 @@ impl SphereCanvasPipeline {
         Self {
             pipeline,
             uniform_buffer,
+            uniform_bind_group_layout,
             uniform_bind_group,
-            image,
+            images: images.into_iter().map(Some).collect(),
             image_width,
             image_height,
+            tiling,
             texture,
             texture_view,
             sampler,
+            selection_texture,
+            selection_view,
+            selection_revision: None,
+        }
+    }
+
+    /**
+     * slots 枚のレイヤーを格納するテクスチャ配列 (レイヤーごとにタイル数分の配列要素を使う)
+     */
+    fn create_layer_texture(
+        device: &wgpu::Device,
+        tiling: &TextureTiling,
+        slots: usize,
+    ) -> (wgpu::Texture, wgpu::TextureView) {
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Sphere Texture"),
+            size: wgpu::Extent3d {
+                width: tiling.layer_width(),
+                height: tiling.layer_height(),
+                depth_or_array_layers: tiling.layer_count() * slots.max(1) as u32,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            // レイヤーの合成は画像と同じガンマ空間で行い、最後にリニアへ変換する
+            format: wgpu::TextureFormat::Rgba8Unorm,
+            // 配列を広げる時に、転送済みのレイヤーはGPU上で複製する
+            usage: wgpu::TextureUsages::TEXTURE_BINDING
+                | wgpu::TextureUsages::COPY_DST
+                | wgpu::TextureUsages::COPY_SRC,
+            view_formats: &[],
+        });
+
+        let texture_view = texture.create_view(&wgpu::TextureViewDescriptor {
+            dimension: Some(wgpu::TextureViewDimension::D2Array),
+            ..Default::default()
+        });
+
+        (texture, texture_view)
+    }
+
+    fn create_bind_group(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        uniform_buffer: &wgpu::Buffer,
+        texture_view: &wgpu::TextureView,
+        sampler: &wgpu::Sampler,
+        selection_view: &wgpu::TextureView,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("shader_quad uniform bind group"),
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: uniform_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(texture_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::Sampler(sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::TextureView(selection_view),
+                },
+            ],
+        })
+    }
+
+    fn slot_of(&self, image: &Arc<RwLock<RgbaImage>>) -> Option<usize> {
+        self.images
+            .iter()
+            .position(|slot| slot.as_ref().is_some_and(|slot| Arc::ptr_eq(slot, image)))
+    }
+
+    /**
+     * テクスチャ配列のスロットをレイヤーの構成に合わせる
+     * 削除したレイヤーのスロットは空けるだけで、追加したレイヤーだけを空きスロットに転送する
+     * 空きが無ければ配列を広げ、転送済みのレイヤーはGPU上で複製する (CPUから転送し直さない)
+     */
+    fn sync_images(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, layers: &LayerStack) {
+        for slot in self.images.iter_mut() {
+            if slot.as_ref().is_some_and(|image| {
+                !layers
+                    .layers
+                    .iter()
+                    .any(|layer| Arc::ptr_eq(&layer.image, image))
+            }) {
+                *slot = None;
+            }
+        }
+
+        let added: Vec<Arc<RwLock<RgbaImage>>> = layers
+            .layers
+            .iter()
+            .filter(|layer| self.slot_of(&layer.image).is_none())
+            .map(|layer| layer.image.clone())
+            .collect();
+        if added.is_empty() {
+            return;
+        }
+
+        let free = self.images.iter().filter(|slot| slot.is_none()).count();
+        if added.len() > free {
+            self.grow(device, queue, self.images.len() + added.len() - free);
+        }
+
+        let full = Rectangle {
+            x: 0,
+            y: 0,
+            width: self.image_width,
+            height: self.image_height,
+        };
+        for image in added {
+            let Some(slot) = self.images.iter().position(|slot| slot.is_none()) else {
+                break;
+            };
+            if let Ok(pixels) = image.read() {
+                self.write_area(queue, slot, &pixels, full);
+            }
+            self.images[slot] = Some(image);
+        }
+    }
+
+    /**
+     * テクスチャ配列を slots 枚分に広げ、今のスロットの内容を複製する
+     */
+    fn grow(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, slots: usize) {
+        let (texture, texture_view) = Self::create_layer_texture(device, &self.tiling, slots);
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Sphere Texture Grow"),
+        });
+        let used = self.images.len() as u32 * self.tiling.layer_count();
+        if used > 0 {
+            encoder.copy_texture_to_texture(
+                self.texture.as_image_copy(),
+                texture.as_image_copy(),
+                wgpu::Extent3d {
+                    width: self.tiling.layer_width(),
+                    height: self.tiling.layer_height(),
+                    depth_or_array_layers: used,
+                },
+            );
+        }
+        queue.submit([encoder.finish()]);
+
+        self.uniform_bind_group = Self::create_bind_group(
+            device,
+            &self.uniform_bind_group_layout,
+            &self.uniform_buffer,
+            &texture_view,
+            &self.sampler,
+            &self.selection_view,
+        );
+        self.texture = texture;
+        self.texture_view = texture_view;
+        self.images.resize(slots, None);
+    }
+
+    fn update(
+        &mut self,
+        queue: &wgpu::Queue,
+        uniforms: &SphereCanvasUniforms,
+        layers: &LayerStack,
+    ) {
+        // 下のレイヤーから順に (不透明度, 合成方法, スロット番号) を渡す
+        let mut layer_params = [[0.0; 4]; MAX_LAYERS];
+        let mut layer_count = 0;
+        for layer in layers.layers.iter().take(MAX_LAYERS) {
+            let Some(slot) = self.slot_of(&layer.image) else {
+                continue;
+            };
+            let opacity = if layer.visible { layer.opacity } else { 0.0 };
+            layer_params[layer_count] = [
+                opacity,
+                layer.blend_mode.shader_index() as f32,
+                slot as f32,
+                0.0,
+            ];
+            layer_count += 1;
         }
+
+        let uniforms = SphereCanvasUniforms {
+            layer_count: layer_count as u32,
+            layers: layer_params,
+            image_size: vec2(
+                self.tiling.image_width as f32,
+                self.tiling.image_height as f32,
+            ),
+            tile_size: vec2(
+                self.tiling.tile_width as f32,
+                self.tiling.tile_height as f32,
+            ),
+            tile_grid: vec2(self.tiling.columns as f32, self.tiling.rows as f32),
+            layer_size: vec2(
+                self.tiling.layer_width() as f32,
+                self.tiling.layer_height() as f32,
+            ),
+            ..*uniforms
+        };
+        queue.write_buffer(&self.uniform_buffer, 0, bytemuck::bytes_of(&uniforms));
     }
 
-    fn update(&mut self, queue: &wgpu::Queue, uniforms: &SphereCanvasUniforms) {
-        queue.write_buffer(&self.uniform_buffer, 0, bytemuck::bytes_of(uniforms));
+    /**
+     * レイヤー画像の一部の矩形(ピクセル座標)だけをテクスチャのスロットに転送する
+     * タイルの隣接ピクセルに含まれる場合は隣のタイルも更新する
+     */
+    fn write_area(
+        &self,
+        queue: &wgpu::Queue,
+        slot: usize,
+        image: &RgbaImage,
+        area: Rectangle<u32>,
+    ) {
+        if area.width == 0
+            || area.height == 0
+            || area.x + area.width > self.image_width
+            || area.y + area.height > self.image_height
+        {
+            return;
+        }
+
+        for copy in self.tiling.copies(area) {
+            // 元画像の行幅のまま、転送元の左上のピクセルから読み出す
+            let offset =
+                (copy.source_y as u64 * self.image_width as u64 + copy.source_x as u64) * 4;
+            queue.write_texture(
+                wgpu::ImageCopyTexture {
+                    texture: &self.texture,
+                    mip_level: 0,
+                    origin: wgpu::Origin3d {
+                        x: copy.layer_x,
+                        y: copy.layer_y,
+                        z: slot as u32 * self.tiling.layer_count() + copy.layer,
+                    },
+                    aspect: wgpu::TextureAspect::All,
+                },
+                image.as_bytes(),
+                wgpu::ImageDataLayout {
+                    offset,
+                    bytes_per_row: Some(4 * self.image_width),
+                    rows_per_image: Some(copy.height),
+                },
+                wgpu::Extent3d {
+                    width: copy.width,
+                    height: copy.height,
+                    depth_or_array_layers: 1,
+                },
+            );
+        }
+    }
+
+    /**
+     * 選択範囲のマスクをテクスチャに転送する
+     * テクスチャの大きさが画像と異なる場合は縮小して転送する
+     */
+    fn write_selection(&mut self, queue: &wgpu::Queue, selection: &Selection) {
+        self.selection_revision = Some(selection.revision());
+        let Some(mask) = selection.mask() else {
+            return;
+        };
+
+        let size = self.selection_texture.size();
+        let scaled;
+        let mask = if mask.dimensions() == (size.width, size.height) {
+            mask
+        } else {
+            scaled = imageops::resize(mask, size.width, size.height, FilterType::Triangle);
+            &scaled
+        };
+
+        queue.write_texture(
+            wgpu::ImageCopyTexture {
+                texture: &self.selection_texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d::ZERO,
+                aspect: wgpu::TextureAspect::All,
+            },
+            mask.as_bytes(),
+            wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: Some(size.width),
+                rows_per_image: Some(size.height),
+            },
+            size,
+        );
     }
 
     pub fn render(
@@ -407,6 +920,18 @@ pub struct SphereCanvasUniforms {
     _padding3: [f32; 1],
     right: glam::Vec3,
     _padding4: [f32; 1],
+    image_size: glam::Vec2,
+    tile_size: glam::Vec2,
+    tile_grid: glam::Vec2,
+    layer_size: glam::Vec2,
+    projection: u32,
+    aspect: f32,
+    layer_count: u32,
+    selection: u32,
+    view_size: glam::Vec2,
+    ants_phase: f32,
+    _padding5: [f32; 1],
+    layers: [[f32; 4]; MAX_LAYERS],
 }
 
 impl Default for SphereCanvasUniforms {
@@ -416,11 +941,23 @@ impl Default for SphereCanvasUniforms {
             look_at: glam::vec3(1.0, 0.0, 0.0),
             up: glam::vec3(0.0, 1.0, 0.0),
             right: glam::vec3(0.0, 0.0, 1.0),
+            image_size: glam::vec2(1.0, 1.0),
+            tile_size: glam::vec2(1.0, 1.0),
+            tile_grid: glam::vec2(1.0, 1.0),
+            layer_size: glam::vec2(3.0, 3.0),
+            projection: 0,
+            aspect: 1.0,
+            layer_count: 0,
+            selection: 0,
+            view_size: glam::vec2(1.0, 1.0),
+            ants_phase: 0.0,
+            layers: [[0.0; 4]; MAX_LAYERS],
 
             _padding1: [0.0; 3],
             _padding2: [0.0; 1],
             _padding3: [0.0; 1],
             _padding4: [0.0; 1],
+            _padding5: [0.0; 1],
         }
     }
 }
@@ -455,69 +992,70 @@ impl shader::Primitive for SphereCanvasPrimitive {
         viewport: &shader::Viewport,
     ) {
         if let Ok(mut state) = self.canvas_state.write() {
-            if let Some(ptr_image) = state.image.clone() {
-                if let Ok(image) = ptr_image.read() {
-                    if storage.has::<SphereCanvasPipeline>() {
-                        let pipeline = storage.get_mut::<SphereCanvasPipeline>().unwrap();
-                        if Arc::ptr_eq(&pipeline.image, &ptr_image) == false {
-                            let new_pipeline = SphereCanvasPipeline::new(
-                                device,
-                                format,
-                                ptr_image.clone(),
-                                state.image_width,
-                                state.image_height,
-                            );
-                            state.modified_area = Some(Rectangle {
-                                x: 0.,
-                                y: 0.,
-                                width: state.image_width as f32,
-                                height: state.image_height as f32,
-                            });
-                            storage.store(new_pipeline)
-                        }
-                    } else {
-                        let pipeline = SphereCanvasPipeline::new(
-                            device,
-                            format,
-                            ptr_image.clone(),
-                            state.image_width,
-                            state.image_height,
-                        );
-                        state.modified_area = Some(Rectangle {
-                            x: 0.,
-                            y: 0.,
-                            width: state.image_width as f32,
-                            height: state.image_height as f32,
-                        });
-                        storage.store(pipeline);
-                    }
+            if state.layers.layers.is_empty() {
+                return;
+            }
 
-                    let pipeline = storage.get_mut::<SphereCanvasPipeline>().unwrap();
-
-                    // TODO: Update only the modified area.
-                    if let Some(modified_area) = state.modified_area {
-                        let buffer = state.image.as_ref().unwrap().clone();
-                        queue.write_texture(
-                            wgpu::ImageCopyTexture {
-                                texture: &pipeline.texture,
-                                mip_level: 0,
-                                origin: wgpu::Origin3d::ZERO,
-                                aspect: wgpu::TextureAspect::All,
-                            },
-                            buffer.read().unwrap().as_bytes(),
-                            wgpu::ImageDataLayout {
-                                offset: 0,
-                                bytes_per_row: Some(4 * state.image_width),
-                                rows_per_image: Some(state.image_height),
-                            },
-                            pipeline.texture.size(),
-                        );
-                        state.modified_area = None;
+            // 画像の大きさが変わったらテクスチャを作り直す
+            let needs_pipeline = match storage.get::<SphereCanvasPipeline>() {
+                Some(pipeline) => {
+                    (pipeline.image_width, pipeline.image_height)
+                        != (state.image_width, state.image_height)
+                }
+                None => true,
+            };
+            if needs_pipeline {
+                let images = state
+                    .layers
+                    .layers
+                    .iter()
+                    .map(|layer| layer.image.clone())
+                    .collect();
+                let pipeline = SphereCanvasPipeline::new(
+                    device,
+                    format,
+                    images,
+                    state.image_width,
+                    state.image_height,
+                );
+                // 作り直したテクスチャには全てのレイヤーを転送する
+                let full = Rectangle {
+                    x: 0,
+                    y: 0,
+                    width: pipeline.image_width,
+                    height: pipeline.image_height,
+                };
+                for (slot, image) in pipeline.images.iter().enumerate() {
+                    if let Some(Ok(image)) = image.as_ref().map(|image| image.read()) {
+                        pipeline.write_area(queue, slot, &image, full);
                     }
+                }
+                state.take_modified();
+                storage.store(pipeline);
+            }
 
-                    pipeline.update(queue, &self.uniforms);
+            let pipeline = storage.get_mut::<SphereCanvasPipeline>().unwrap();
+            // レイヤーの追加・削除は、変わったレイヤーだけスロットを割り当て直す
+            pipeline.sync_images(device, queue, &state.layers);
+
+            // 変更されたレイヤーの、変更された範囲だけテクスチャを更新する
+            for (image, areas) in state.take_modified() {
+                let Some(slot) = pipeline.slot_of(&image) else {
+                    continue;
+                };
+                if let Ok(image) = image.read() {
+                    for area in &areas {
+                        pipeline.write_area(queue, slot, &image, *area);
+                    }
                 }
             }
+
+            // 選択範囲が変わったらマスクを転送し直す
+            if pipeline.selection_revision != Some(state.selection.revision()) {
+                pipeline.write_selection(queue, &state.selection);
+            }
+
+            pipeline.update(queue, &self.uniforms, &state.layers);
         }
     }
 
@@ -528,7 +1066,8 @@ impl shader::Primitive for SphereCanvasPrimitive {
         target: &wgpu::TextureView,
         clip_bounds: &Rectangle<u32>,
     ) {
-        let pipeline = storage.get::<SphereCanvasPipeline>().unwrap();
-        pipeline.render(target, encoder, *clip_bounds);
+        if let Some(pipeline) = storage.get::<SphereCanvasPipeline>() {
+            pipeline.render(target, encoder, *clip_bounds);
+        }
     }
 }
@@ -0,0 +1,206 @@
+use std::sync::{Arc, RwLock};
+
+use glam::Vec3;
+use iced::widget::canvas::{self, Frame, Geometry, Path, Stroke};
+use iced::{Color, Point, Rectangle, Renderer, Size, Theme, Vector, mouse};
+
+use crate::math::projection::SphereProjection;
+use crate::widget::sphere_canvas::SphereCanvasState;
+
+/// 折れ線を途切れさせる、隣り合う点のview上の距離 (正距円筒図法の左右端をまたぐ場合など)
+const MAX_SEGMENT_LENGTH: f32 = 0.5;
+/// 対称の基準線の点の間隔 (ラジアン)
+const GUIDE_STEP: f32 = 0.02;
+
+/// viewに重ねて描画する球面上の折れ線 (ツールのプレビューなど)
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct OverlayPath {
+    /// 折れ線が通る球面上の方向
+    pub points: Vec<Vec3>,
+    /// 最後の点と最初の点を繋ぐ
+    pub closed: bool,
+}
+
+pub fn sphere_overlay(state: Arc<RwLock<SphereCanvasState>>) -> SphereOverlay {
+    SphereOverlay { state }
+}
+
+/// SphereCanvasState の overlay を、球面の描画と同じ射影でviewに描画する
+pub struct SphereOverlay {
+    state: Arc<RwLock<SphereCanvasState>>,
+}
+
+impl SphereOverlay {
+    /**
+     * 折れ線をviewのピクセル座標に射影し、viewに写らない点や大きく飛ぶ箇所で分割する
+     */
+    fn project_path(proj: &SphereProjection, path: &OverlayPath, size: Size) -> Vec<Vec<Point>> {
+        let mut points: Vec<Option<Point>> = path
+            .points
+            .iter()
+            .map(|direction| {
+                proj.direction_to_view(*direction)
+                    .map(|view| Point::new(view.x * size.width, (1.0 - view.y) * size.height))
+            })
+            .collect();
+        if path.closed {
+            points.push(points.first().copied().flatten());
+        }
+
+        let max_length = MAX_SEGMENT_LENGTH * size.width.max(size.height);
+        let mut segments: Vec<Vec<Point>> = Vec::new();
+        let mut current: Vec<Point> = Vec::new();
+        for point in points {
+            match point {
+                Some(point)
+                    if current
+                        .last()
+                        .is_none_or(|last| last.distance(point) <= max_length) =>
+                {
+                    current.push(point)
+                }
+                Some(point) => {
+                    segments.push(std::mem::take(&mut current));
+                    current.push(point);
+                }
+                None => segments.push(std::mem::take(&mut current)),
+            }
+        }
+        segments.push(current);
+        segments.retain(|segment| segment.len() >= 2);
+
+        segments
+    }
+
+    /**
+     * どんな色の画像の上でも見えるように、黒い縁取りの上に白い線を引く
+     */
+    fn stroke_outlined(frame: &mut Frame, path: &Path) {
+        frame.stroke(
+            path,
+            Stroke::default()
+                .with_color(Color::from_rgba(0.0, 0.0, 0.0, 0.6))
+                .with_width(3.0),
+        );
+        frame.stroke(path, Stroke::default().with_color(Color::WHITE));
+    }
+
+    /**
+     * ツールのプレビューと区別できるように、対称の基準線は色を付けて細く引く
+     */
+    fn stroke_guide(frame: &mut Frame, path: &Path) {
+        frame.stroke(
+            path,
+            Stroke::default()
+                .with_color(Color::from_rgba(0.0, 0.8, 1.0, 0.7))
+                .with_width(1.0),
+        );
+    }
+
+    /**
+     * 折れ線をviewに射影した各区間を Path にする
+     */
+    fn segment_paths(
+        proj: &SphereProjection,
+        path: &OverlayPath,
+        size: Size,
+    ) -> impl Iterator<Item = Path> {
+        Self::project_path(proj, path, size)
+            .into_iter()
+            .map(|segment| {
+                Path::new(|builder| {
+                    builder.move_to(segment[0]);
+                    for point in &segment[1..] {
+                        builder.line_to(*point);
+                    }
+                })
+            })
+    }
+}
+
+impl<Message> canvas::Program<Message> for SphereOverlay {
+    type State = ();
+
+    fn draw(
+        &self,
+        _state: &Self::State,
+        renderer: &Renderer,
+        _theme: &Theme,
+        bounds: Rectangle,
+        _cursor: mouse::Cursor,
+    ) -> Vec<Geometry> {
+        let Ok(state) = self.state.read() else {
+            return Vec::new();
+        };
+        if state.overlay.is_empty()
+            && state.floating.is_none()
+            && state.text_preview.is_none()
+            && !state.symmetry.is_active()
+        {
+            return Vec::new();
+        }
+
+        let proj = state.sphere_projection();
+        let mut frame = Frame::new(renderer, bounds.size());
+
+        // 対称の基準線は画像の一部のように、ツールのプレビューより下に描く
+        for points in state.symmetry.guides(GUIDE_STEP) {
+            let guide = OverlayPath {
+                points,
+                closed: false,
+            };
+            for path in Self::segment_paths(&proj, &guide, bounds.size()) {
+                Self::stroke_guide(&mut frame, &path);
+            }
+        }
+
+        // 置いた文字は確定するまで、レイヤーに描いた時と同じ位置に重ねる
+        if let Some(preview) = state.text_preview.as_ref() {
+            if let Some((rect, handle)) = preview.view_image(&proj, bounds.size(), &state.selection)
+            {
+                frame.draw_image(rect, canvas::Image::new(handle));
+            }
+        }
+
+        // 貼り付けた画像は確定するまで viewに平らに重ねて、枠を付けて表示する
+        if let Some(floating) = state.floating.as_ref() {
+            let size = floating.size();
+            let top_left = floating.center - size * 0.5;
+            frame.draw_image(
+                Rectangle::new(
+                    Point::new(top_left.x, top_left.y),
+                    Size::new(size.x, size.y),
+                ),
+                canvas::Image::new(floating.handle.clone()).rotation(floating.rotation),
+            );
+
+            let corners = floating.corners();
+            let path = Path::new(|builder| {
+                builder.move_to(Point::new(corners[0].x, corners[0].y));
+                for corner in &corners[1..] {
+                    builder.line_to(Point::new(corner.x, corner.y));
+                }
+                builder.close();
+            });
+            Self::stroke_outlined(&mut frame, &path);
+
+            // 角の拡大縮小の取っ手
+            for corner in corners {
+                let handle = Path::rectangle(
+                    Point::new(corner.x, corner.y) - Vector::new(3.0, 3.0),
+                    Size::new(6.0, 6.0),
+                );
+                frame.fill(&handle, Color::WHITE);
+                frame.stroke(&handle, Stroke::default().with_color(Color::BLACK));
+            }
+        }
+
+        for overlay_path in &state.overlay {
+            for path in Self::segment_paths(&proj, overlay_path, bounds.size()) {
+                Self::stroke_outlined(&mut frame, &path);
+            }
+        }
+
+        vec![frame.into_geometry()]
+    }
+}
@@ -1,16 +1,48 @@
 const PI = 3.1415926;
 const TAU = 1.5707963;
 
+// 射影方式 (ViewProjection::shader_index と一致させること)
+const PROJECTION_RECTILINEAR = 0u;
+const PROJECTION_STEREOGRAPHIC = 1u;
+const PROJECTION_FISHEYE = 2u;
+const PROJECTION_PANNINI = 3u;
+const PROJECTION_EQUIRECTANGULAR = 4u;
+
+// レイヤーの合成方法 (BlendMode::shader_index と一致させること)
+const BLEND_NORMAL = 0u;
+const BLEND_MULTIPLY = 1u;
+const BLEND_SCREEN = 2u;
+const BLEND_OVERLAY = 3u;
+const BLEND_ADD = 4u;
+
+// 重ねられるレイヤーの上限 (layer::MAX_LAYERS と一致させること)
+const MAX_LAYERS = 16;
+
+// 選択範囲の境界線の縞模様の周期 (viewのピクセル数, sphere_canvas::ANTS_PERIOD と一致させること)
+const ANTS_PERIOD = 8.;
+
 struct Uniforms {
     aov: f32, // 視野
     look_at: vec3<f32>, // 視点
     up: vec3<f32>, // 視点上方向(単位ベクトル)
-    right: vec3<f32> // 視点右方向(単位ベクトル)
+    right: vec3<f32>, // 視点右方向(単位ベクトル)
+    image_size: vec2<f32>, // 画像全体のピクセル数
+    tile_size: vec2<f32>, // 1タイルが受け持つピクセル数
+    tile_grid: vec2<f32>, // タイルの列数, 行数
+    layer_size: vec2<f32>, // 隣接ピクセルを含めたレイヤーのピクセル数
+    projection: u32, // 射影方式
+    aspect: f32, // viewの縦横比 (高さ / 幅)
+    layer_count: u32, // 合成するレイヤーの数
+    selection: u32, // 選択範囲があれば 1
+    view_size: vec2<f32>, // viewのピクセル数
+    ants_phase: f32, // 選択範囲の境界線の縞模様をずらす量 (ピクセル)
+    layers: array<vec4<f32>, MAX_LAYERS> // 下から順に (不透明度, 合成方法, スロット番号, 未使用)
 }
 
 @group(0) @binding(0) var<uniform> uniforms: Uniforms;
-@group(0) @binding(1) var texture: texture_2d<f32>;
+@group(0) @binding(1) var texture: texture_2d_array<f32>;
 @group(0) @binding(2) var texture_sampler: sampler;
+@group(0) @binding(3) var selection_texture: texture_2d<f32>;
 
 struct VertexIn {
     @builtin(vertex_index) vertex_index: u32,
@@ -27,19 +59,186 @@ struct VertexOut {
     return VertexOut(position, uv);
 }
 
-@fragment fn fs_main(in: VertexOut) -> @location(0) vec4<f32> {
-    // 視点(look_at)を基準とした場合の描画ピクセルの相対位置
-    let yaw = uniforms.aov * (in.uv.x - 0.5);
-    let pitch = uniforms.aov * (in.uv.y - 0.5);
+// テクスチャ座標(0.0~1.0)の色をタイルに分割されたテクスチャのスロットから取得する
+fn sample_equirect(uv: vec2<f32>, slot: u32) -> vec4<f32> {
+    let pixel = vec2(fract(uv.x), clamp(uv.y, 0., 1.)) * uniforms.image_size;
+    let tile = min(floor(pixel / uniforms.tile_size), uniforms.tile_grid - 1.);
+    let tiles_per_slot = u32(uniforms.tile_grid.x * uniforms.tile_grid.y);
+    let layer = i32(slot * tiles_per_slot + u32(tile.y * uniforms.tile_grid.x + tile.x));
 
-    // ヨー, ピッチから球面座標に変換
-    let sphereCoord = normalize(uniforms.look_at + yaw * uniforms.right + pitch * uniforms.up);
+    // タイルの周囲1ピクセルは隣接ピクセルなので、その分ずらす
+    let local = pixel - tile * uniforms.tile_size + 1.;
+    return textureSampleLevel(texture, texture_sampler, local / uniforms.layer_size, layer, 0.);
+}
+
+// 下の色 backdrop と上の色 source の合成 (BlendMode::blend と同じ計算)
+fn blend(backdrop: vec3<f32>, source: vec3<f32>, mode: u32) -> vec3<f32> {
+    switch mode {
+        case BLEND_MULTIPLY: {
+            return backdrop * source;
+        }
+        case BLEND_SCREEN: {
+            return backdrop + source - backdrop * source;
+        }
+        case BLEND_OVERLAY: {
+            return select(
+                1. - 2. * (1. - backdrop) * (1. - source),
+                2. * backdrop * source,
+                backdrop <= vec3(0.5)
+            );
+        }
+        case BLEND_ADD: {
+            return min(backdrop + source, vec3(1.));
+        }
+        default: {
+            return source;
+        }
+    }
+}
 
-    // 球面座標から平面座標に変換
-    let x = atan2(sphereCoord.z, sphereCoord.x);
-    let y = atan2(sphereCoord.y, sqrt(sphereCoord.x * sphereCoord.x + sphereCoord.z * sphereCoord.z));
+// 下の色に上のレイヤーの色を重ねる (layer::composite と同じ計算)
+fn composite(backdrop: vec4<f32>, source: vec4<f32>, opacity: f32, mode: u32) -> vec4<f32> {
+    let alpha_s = source.a * opacity;
+    if (alpha_s <= 0.) {
+        return backdrop;
+    }
+    let alpha_b = backdrop.a;
+    let alpha_o = alpha_s + alpha_b * (1. - alpha_s);
 
-    // 平面座標からテクスチャの色を取得
-    let color = textureSample(texture, texture_sampler, vec2(x / (2 * PI) + 0.5, 0.5 - y / PI));
+    let mixed = (1. - alpha_b) * source.rgb + alpha_b * blend(backdrop.rgb, source.rgb, mode);
+    let color = (alpha_s * mixed + alpha_b * (1. - alpha_s) * backdrop.rgb) / alpha_o;
+    return vec4(color, alpha_o);
+}
+
+// 全レイヤーを下から順に合成する
+fn sample_layers(uv: vec2<f32>) -> vec4<f32> {
+    var color = vec4(0.);
+    for (var i = 0u; i < uniforms.layer_count; i++) {
+        let params = uniforms.layers[i];
+        if (params.x <= 0.) {
+            continue;
+        }
+        color = composite(color, sample_equirect(uv, u32(params.z)), params.x, u32(params.y));
+    }
     return color;
-}
\ No newline at end of file
+}
+
+// sRGBのガンマ空間の色をリニアに変換する
+fn srgb_to_linear(color: vec3<f32>) -> vec3<f32> {
+    return select(
+        pow((color + 0.055) / 1.055, vec3(2.4)),
+        color / 12.92,
+        color <= vec3(0.04045)
+    );
+}
+
+// 視点座標系 (right, up, look_at) のベクトルをワールド座標に変換する
+fn local_to_world(local: vec3<f32>) -> vec3<f32> {
+    return local.x * uniforms.right + local.y * uniforms.up + local.z * uniforms.look_at;
+}
+
+// 投影面上の向き p と視点からの角度 theta から方向を求める
+fn polar_to_world(p: vec2<f32>, theta: f32) -> vec3<f32> {
+    let rho = length(p);
+    if (rho <= 1e-6) {
+        return uniforms.look_at;
+    }
+    let d = p / rho * sin(theta);
+    return normalize(local_to_world(vec3(d, cos(theta))));
+}
+
+// view座標(0.0~1.0)から球面上の方向を求める (w が 0 なら射影の範囲外)
+fn view_to_sphere(uv: vec2<f32>) -> vec4<f32> {
+    // 画角の端が ±1.0 になる投影面上の座標
+    let plane = vec2((uv.x - 0.5) * 2., (uv.y - 0.5) * 2. * uniforms.aspect);
+    let half_aov = uniforms.aov * 0.5;
+
+    switch uniforms.projection {
+        case PROJECTION_STEREOGRAPHIC: {
+            let p = plane * 2. * tan(half_aov * 0.5);
+            return vec4(polar_to_world(p, 2. * atan(length(p) * 0.5)), 1.);
+        }
+        case PROJECTION_FISHEYE: {
+            let p = plane * 2. * sin(half_aov * 0.5);
+            let rho = length(p);
+            if (rho > 2.) {
+                return vec4(0.);
+            }
+            return vec4(polar_to_world(p, 2. * asin(rho * 0.5)), 1.);
+        }
+        case PROJECTION_PANNINI: {
+            let p = plane * 2. * tan(half_aov * 0.5);
+            let phi = 2. * atan(p.x * 0.5);
+            let s = 2. / (1. + cos(phi));
+            return vec4(normalize(local_to_world(vec3(sin(phi), p.y / s, cos(phi)))), 1.);
+        }
+        case PROJECTION_EQUIRECTANGULAR: {
+            let look_at = uniforms.look_at;
+            let lon = atan2(look_at.z, look_at.x) + plane.x * half_aov;
+            let lat = atan2(look_at.y, sqrt(look_at.x * look_at.x + look_at.z * look_at.z)) + plane.y * half_aov;
+            if (abs(lat) > PI * 0.5) {
+                return vec4(0.);
+            }
+            return vec4(cos(lat) * cos(lon), sin(lat), cos(lat) * sin(lon), 1.);
+        }
+        default: {
+            let p = plane * tan(half_aov);
+            return vec4(normalize(local_to_world(vec3(p, 1.))), 1.);
+        }
+    }
+}
+
+// 球面上の方向から正距円筒図法のテクスチャ座標(0.0~1.0)に変換する
+fn sphere_to_equirect(direction: vec3<f32>) -> vec2<f32> {
+    let x = atan2(direction.z, direction.x);
+    let y = atan2(direction.y, sqrt(direction.x * direction.x + direction.z * direction.z));
+    return vec2(x / (2 * PI) + 0.5, 0.5 - y / PI);
+}
+
+// view座標の位置が選択範囲に含まれるか (射影の範囲外は含まれない)
+fn is_selected(uv: vec2<f32>) -> bool {
+    let direction = view_to_sphere(uv);
+    if (direction.w <= 0.) {
+        return false;
+    }
+    let tex = sphere_to_equirect(direction.xyz);
+    let mask = textureSampleLevel(selection_texture, texture_sampler, vec2(fract(tex.x), tex.y), 0.);
+    return mask.r >= 0.5;
+}
+
+// 選択範囲の境界にあるピクセルなら、縞模様の色 (白か黒) を返す (w が 0 なら境界ではない)
+fn selection_outline(uv: vec2<f32>, position: vec2<f32>) -> vec4<f32> {
+    let pixel = 1. / uniforms.view_size;
+    let selected = is_selected(uv);
+    let edge = selected != is_selected(uv + vec2(pixel.x, 0.))
+        || selected != is_selected(uv - vec2(pixel.x, 0.))
+        || selected != is_selected(uv + vec2(0., pixel.y))
+        || selected != is_selected(uv - vec2(0., pixel.y));
+    if (!edge) {
+        return vec4(0.);
+    }
+
+    let stripe = fract((position.x + position.y + uniforms.ants_phase) / ANTS_PERIOD) < 0.5;
+    return vec4(vec3(select(0., 1., stripe)), 1.);
+}
+
+@fragment fn fs_main(in: VertexOut) -> @location(0) vec4<f32> {
+    // 投影面上の座標から球面座標に変換
+    let sphereCoord = view_to_sphere(in.uv);
+    if (sphereCoord.w <= 0.) {
+        return vec4(0., 0., 0., 1.);
+    }
+
+    // 平面座標からレイヤーを合成した色を取得
+    let color = sample_layers(sphere_to_equirect(sphereCoord.xyz));
+
+    // 選択範囲の境界線 (marching ants) を重ねる
+    if (uniforms.selection != 0u) {
+        let outline = selection_outline(in.uv, in.position.xy);
+        if (outline.w > 0.) {
+            return outline;
+        }
+    }
+
+    return vec4(srgb_to_linear(color.rgb * color.a), 1.);
+}
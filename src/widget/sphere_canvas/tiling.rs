@@ -0,0 +1,172 @@
+use iced::Rectangle;
+
+/// タイルの周囲に確保する隣接ピクセルの幅
+/// (バイリニア補間がタイル境界で隣のタイルを参照できるようにする)
+pub const GUTTER: u32 = 1;
+
+/// GPUのテクスチャサイズ上限に収まるように画像をタイル(テクスチャ配列のレイヤー)へ分割する
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TextureTiling {
+    pub image_width: u32,
+    pub image_height: u32,
+    /// 1タイルが受け持つ画像のピクセル数 (最後の列・行は小さくなる場合がある)
+    pub tile_width: u32,
+    pub tile_height: u32,
+    pub columns: u32,
+    pub rows: u32,
+}
+
+/// タイルへの転送単位
+/// layer の (layer_x, layer_y) から width × height を、
+/// 画像の (source_x, source_y) から読み出して書き込む
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TileCopy {
+    pub layer: u32,
+    pub layer_x: u32,
+    pub layer_y: u32,
+    pub source_x: u32,
+    pub source_y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl TextureTiling {
+    pub fn new(image_width: u32, image_height: u32, max_dimension: u32) -> Self {
+        let max_tile = max_dimension.saturating_sub(2 * GUTTER).max(1);
+        let image_width = image_width.max(1);
+        let image_height = image_height.max(1);
+
+        let columns = image_width.div_ceil(max_tile);
+        let rows = image_height.div_ceil(max_tile);
+
+        Self {
+            image_width,
+            image_height,
+            tile_width: image_width.div_ceil(columns),
+            tile_height: image_height.div_ceil(rows),
+            columns,
+            rows,
+        }
+    }
+
+    pub fn layer_count(&self) -> u32 {
+        self.columns * self.rows
+    }
+
+    /**
+     * 周囲の隣接ピクセルを含めたレイヤーの大きさ
+     */
+    pub fn layer_width(&self) -> u32 {
+        self.tile_width + 2 * GUTTER
+    }
+
+    pub fn layer_height(&self) -> u32 {
+        self.tile_height + 2 * GUTTER
+    }
+
+    /**
+     * 画像の範囲(ピクセル座標)が変更されたときに書き換えるべきタイル上の範囲
+     */
+    pub fn copies(&self, area: Rectangle<u32>) -> Vec<TileCopy> {
+        let mut copies = Vec::new();
+
+        for row in 0..self.rows {
+            let y_spans = Self::spans(
+                row * self.tile_height,
+                self.tile_len(row, self.tile_height, self.image_height),
+                self.image_height,
+                area.y,
+                area.y + area.height,
+                false,
+            );
+            if y_spans.is_empty() {
+                continue;
+            }
+
+            for column in 0..self.columns {
+                let x_spans = Self::spans(
+                    column * self.tile_width,
+                    self.tile_len(column, self.tile_width, self.image_width),
+                    self.image_width,
+                    area.x,
+                    area.x + area.width,
+                    true,
+                );
+
+                for &(layer_y, source_y, height) in &y_spans {
+                    for &(layer_x, source_x, width) in &x_spans {
+                        copies.push(TileCopy {
+                            layer: row * self.columns + column,
+                            layer_x,
+                            layer_y,
+                            source_x,
+                            source_y,
+                            width,
+                            height,
+                        });
+                    }
+                }
+            }
+        }
+
+        copies
+    }
+
+    /**
+     * index番目のタイルが実際に受け持つピクセル数
+     */
+    fn tile_len(&self, index: u32, tile_len: u32, image_len: u32) -> u32 {
+        tile_len.min(image_len - index * tile_len)
+    }
+
+    /**
+     * 1軸について、変更範囲 [start, end) が影響するタイル上の区間を
+     * (レイヤー上の位置, 画像上の位置, 長さ) の組で返す
+     * 隣接ピクセルは、wrap なら反対側の端、そうでなければ端のピクセルを複製する
+     */
+    fn spans(
+        origin: u32,
+        len: u32,
+        image_len: u32,
+        start: u32,
+        end: u32,
+        wrap: bool,
+    ) -> Vec<(u32, u32, u32)> {
+        let mut spans = Vec::new();
+        let contains = |x: u32| start <= x && x < end;
+
+        let before = if origin > 0 {
+            origin - 1
+        } else if wrap {
+            image_len - 1
+        } else {
+            0
+        };
+        if contains(before) {
+            spans.push((0, before, 1));
+        }
+
+        let inner_start = start.max(origin);
+        let inner_end = end.min(origin + len);
+        if inner_start < inner_end {
+            spans.push((
+                inner_start - origin + GUTTER,
+                inner_start,
+                inner_end - inner_start,
+            ));
+        }
+
+        let after = if origin + len < image_len {
+            origin + len
+        } else if wrap {
+            0
+        } else {
+            image_len - 1
+        };
+        if contains(after) {
+            spans.push((len + GUTTER, after, 1));
+        }
+
+        spans
+    }
+}